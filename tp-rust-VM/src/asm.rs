@@ -0,0 +1,536 @@
+use crate::macros;
+use crate::{Comparison, Condition, FloatComparison, FloatOp, Instruction, LineInfo, ObjectFile, MAX_NAME_LEN, Relocation, RelocationKind, Section, SectionKind, Symbol, NREGS};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Error reported by the assembler, with the 1-based line it occurred on.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Immediate operand, either a number or a reference to a label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Value(i64),
+    Label(String),
+}
+
+#[derive(Debug)]
+enum Item {
+    Label(String),
+    Instr(Instruction),
+    /// `loadimm`, encoded on 6 bytes with opcode 9 when `wide` is set
+    LoadImm { dst: u8, value: Operand, wide: bool },
+    /// Relative jump or branch to a label, whose offset is only known once
+    /// the layout is done
+    Branch { instr: Instruction, label: String },
+    Bytes(Vec<u8>),
+    Word(Operand),
+}
+
+impl Item {
+    fn size(&self) -> usize {
+        match self {
+            Item::Label(_) => 0,
+            Item::Instr(instr) | Item::Branch { instr, .. } => instr.size(),
+            Item::LoadImm { wide: true, .. } => 6,
+            Item::LoadImm { .. } | Item::Word(_) => 4,
+            Item::Bytes(bytes) => bytes.len(),
+        }
+    }
+}
+
+const TEXT: usize = 0;
+const DATA: usize = 1;
+
+struct Parsed {
+    line: usize,
+    section: usize,
+    item: Item,
+}
+
+/// Address of every item, the data section being placed right after the
+/// code, and size of every section.
+fn layout(items: &[Parsed]) -> (Vec<usize>, [usize; 2]) {
+    let mut sizes = [0; 2];
+    let mut offsets: Vec<usize> = items
+        .iter()
+        .map(|parsed| {
+            let offset = sizes[parsed.section];
+            sizes[parsed.section] += parsed.item.size();
+            offset
+        })
+        .collect();
+    for (offset, parsed) in offsets.iter_mut().zip(items) {
+        if parsed.section == DATA {
+            *offset += sizes[TEXT];
+        }
+    }
+    (offsets, sizes)
+}
+
+fn fits_i16(value: i64) -> bool {
+    i16::try_from(value).is_ok()
+}
+
+/// Assemble a program written in the syntax of the `.dis` listings into an
+/// object file.
+///
+/// Each line holds a label definition (`name:`), an instruction, or data
+/// written as a bytes literal (`b'Hello\n'`), a list of bytes (`[0, 0]`) or a
+/// `.word` directive. The address column of the listings (`0042` or `????`)
+/// is accepted and ignored, and comments start with `;`. The `.text` and
+/// `.data` directives select the section in which the following lines are
+/// placed, the data section being loaded right after the code one. The
+/// `.entry label` directive sets the entry point, which defaults to 0.
+///
+/// Labels are local to the object file unless exported with `.global label`.
+/// Labels defined in other object files must be declared with `.extern label`
+/// and will be resolved by the linker. Every reference to a label gives a
+/// relocation in the object file.
+///
+/// `loadimm` is encoded with a 16-bit immediate when the value fits, and as
+/// `loadimm32` otherwise. External labels are assumed to fit.
+///
+/// The structured macros `push rX`, `pop rX`, `call label`, `ret`,
+/// `if rX != 0 { ... } else { ... }` and `while rX != 0 { ... }` (also with
+/// `== 0`) are expanded into the original instructions first, using r2 as the
+/// stack pointer and r3 as a scratch register.
+///
+/// Relative jumps and branches (`jmp`, `bz`, `bnz`, `bneg`) take either a
+/// label or a raw offset such as `#-8`, counted from the next instruction.
+pub fn assemble(source: &str) -> Result<ObjectFile, AsmError> {
+    let mut items = vec![];
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut section = TEXT;
+    let mut entry = None;
+    let mut globals = HashMap::new();
+    let mut externs = HashSet::new();
+
+    let mut lines = vec![];
+    for (idx, raw) in source.lines().enumerate() {
+        let mut text = strip_comment(raw).trim();
+
+        // Skip the address column of the listings
+        if let Some((first, rest)) = text.split_once(char::is_whitespace) {
+            if first == "????" || first.chars().all(|c| c.is_ascii_digit()) {
+                text = rest.trim_start();
+            }
+        }
+        if let Some(name) = reserved_label(text) {
+            return Err(AsmError { line: idx + 1, message: format!("label `{name}` is reserved for the macros") });
+        }
+        if !text.is_empty() {
+            lines.push((idx + 1, text.to_string()));
+        }
+    }
+
+    for (line, text) in macros::expand(lines)? {
+        let err = |message: String| AsmError { line, message };
+        let text = text.as_str();
+
+        if let Some(name) = text.strip_suffix(':') {
+            if name.len() > MAX_NAME_LEN {
+                return Err(err(format!("label name `{name}` is longer than {MAX_NAME_LEN} bytes")));
+            }
+            if !is_label(name) {
+                return Err(err(format!("invalid label name `{name}`")));
+            }
+            if labels.insert(name.to_string(), items.len()).is_some() {
+                return Err(err(format!("label `{name}` defined twice")));
+            }
+            items.push(Parsed { line, section, item: Item::Label(name.to_string()) });
+            continue;
+        }
+
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let item = match words[0] {
+            ".text" => {
+                section = TEXT;
+                continue;
+            }
+            ".data" => {
+                section = DATA;
+                continue;
+            }
+            ".entry" | ".global" | ".extern" => {
+                let name = match words[1..] {
+                    [name] if is_identifier(name) => name.to_string(),
+                    _ => return Err(err(format!("expected a label after {}", words[0]))),
+                };
+                match words[0] {
+                    ".entry" => entry = Some((line, name)),
+                    ".global" => {
+                        globals.insert(name, line);
+                    }
+                    _ => {
+                        externs.insert(name);
+                    }
+                }
+                continue;
+            }
+            ".word" => match words[1..] {
+                [value] => Item::Word(parse_operand(value).map_err(err)?),
+                _ => return Err(err("expected a single value after .word".to_string())),
+            },
+            _ if text.starts_with("b'") || text.starts_with("b\"") => {
+                Item::Bytes(parse_bytes_literal(text).map_err(err)?)
+            }
+            _ if text.starts_with('[') => Item::Bytes(parse_byte_list(text).map_err(err)?),
+            _ => parse_instruction(&words).map_err(err)?,
+        };
+        items.push(Parsed { line, section, item });
+    }
+    if let Some((name, &line)) = globals.iter().find(|(name, _)| !labels.contains_key(*name)) {
+        return Err(AsmError { line, message: format!("global label `{name}` is not defined") });
+    }
+
+    let resolve = |offsets: &[usize], line: usize, operand: &Operand| match operand {
+        Operand::Value(v) => Ok(*v),
+        Operand::Label(name) => match labels.get(name) {
+            Some(&idx) => Ok(offsets[idx] as i64),
+            None if externs.contains(name) => Ok(0),
+            None => Err(AsmError { line, message: format!("undefined label `{name}`") }),
+        },
+    };
+
+    // Widen the `loadimm` whose value does not fit in 16 bits until the
+    // layout is stable. As instructions only grow, this terminates.
+    for parsed in &mut items {
+        if let Item::LoadImm { value: Operand::Value(v), wide, .. } = &mut parsed.item {
+            *wide |= !fits_i16(*v);
+        }
+    }
+    let (offsets, sizes) = loop {
+        let (offsets, sizes) = layout(&items);
+        let mut changed = false;
+        for parsed in &mut items {
+            if let Item::LoadImm { value, wide: wide @ false, .. } = &mut parsed.item {
+                if !fits_i16(resolve(&offsets, parsed.line, value)?) {
+                    *wide = true;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break (offsets, sizes);
+        }
+    };
+
+    let mut data = [vec![], vec![]];
+    let mut lines = vec![];
+    let mut relocations = vec![];
+    for (parsed, &addr) in items.iter().zip(&offsets) {
+        let out = &mut data[parsed.section];
+        let offset = out.len();
+        let mut relocate = |operand: &Operand, field: usize, kind: RelocationKind| {
+            if let Operand::Label(symbol) = operand {
+                let offset = (offset + field) as u32;
+                relocations.push(Relocation { section: parsed.section as u16, offset, kind, symbol: symbol.clone() });
+            }
+        };
+        let out_of_range = |value: i64, bits: u32| AsmError {
+            line: parsed.line,
+            message: format!("{value} does not fit in {bits} bits"),
+        };
+        match &parsed.item {
+            Item::Label(_) => continue,
+            Item::Instr(instr) => instr.encode(out),
+            Item::LoadImm { dst, value, wide: false } => {
+                relocate(value, 2, RelocationKind::Imm16);
+                let value = resolve(&offsets, parsed.line, value)?;
+                Instruction::LoadImm { dst: *dst, imm: value as i16 }.encode(out);
+            }
+            Item::LoadImm { dst, value, wide: true } => {
+                relocate(value, 2, RelocationKind::Word32);
+                let value = resolve(&offsets, parsed.line, value)?;
+                if value < i32::MIN as i64 || value > u32::MAX as i64 {
+                    return Err(out_of_range(value, 32));
+                }
+                Instruction::LoadImm32 { dst: *dst, imm: value as u32 }.encode(out);
+            }
+            Item::Branch { instr, label } => {
+                let label = Operand::Label(label.clone());
+                let size = instr.size();
+                relocate(&label, size - 2, RelocationKind::Rel16);
+                let offset = resolve(&offsets, parsed.line, &label)? - (addr + size) as i64;
+                let offset = i16::try_from(offset).map_err(|_| out_of_range(offset, 16))?;
+                with_offset(*instr, offset).encode(out);
+            }
+            Item::Bytes(bytes) => out.extend(bytes),
+            Item::Word(value) => {
+                relocate(value, 0, RelocationKind::Word32);
+                let value = resolve(&offsets, parsed.line, value)?;
+                if value < i32::MIN as i64 || value > u32::MAX as i64 {
+                    return Err(out_of_range(value, 32));
+                }
+                out.extend((value as u32).to_le_bytes());
+            }
+        }
+        lines.push(LineInfo { addr: addr as u32, line: parsed.line as u32 });
+    }
+    lines.sort_by_key(|l| l.addr);
+
+    let entry = match entry {
+        Some((line, name)) => resolve(&offsets, line, &Operand::Label(name))? as u32,
+        None => 0,
+    };
+    let [text, data] = data;
+    let mut sections = vec![Section { name: ".text".to_string(), kind: SectionKind::Code, addr: 0, data: text }];
+    if !data.is_empty() {
+        sections.push(Section { name: ".data".to_string(), kind: SectionKind::Data, addr: sizes[TEXT] as u32, data });
+    }
+    let symbols = items
+        .iter()
+        .zip(&offsets)
+        .filter_map(|(parsed, &addr)| match &parsed.item {
+            Item::Label(name) => Some(Symbol {
+                name: name.clone(),
+                addr: addr as u32,
+                section: parsed.section as u16,
+                global: globals.contains_key(name),
+            }),
+            _ => None,
+        })
+        .collect();
+
+    Ok(ObjectFile { entry, sections, symbols, lines, relocations })
+}
+
+fn strip_comment(line: &str) -> &str {
+    // A `;` inside a bytes literal does not start a comment
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, ';') => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Whether `name` can be a label, which must also fit in an object file.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && name.len() <= MAX_NAME_LEN
+}
+
+/// Whether `name` can be a label, either of the source or generated by the
+/// macros with a leading `.`.
+fn is_label(name: &str) -> bool {
+    is_identifier(name.strip_prefix('.').unwrap_or(name))
+}
+
+/// Label starting with a `.` defined or used by a line of the source, as
+/// only the macros may generate them.
+fn reserved_label(text: &str) -> Option<&str> {
+    if text.starts_with("b'") || text.starts_with("b\"") {
+        return None;
+    }
+    let names: Vec<&str> = match text.strip_suffix(':') {
+        Some(name) => vec![name],
+        None => text.split_whitespace().skip(1).map(|word| word.trim_start_matches('#').trim_end_matches(',')).collect(),
+    };
+    names.into_iter().find(|name| name.starts_with('.') && is_label(name))
+}
+
+fn parse_reg(word: &str) -> Result<u8, String> {
+    word.strip_prefix('r')
+        .and_then(|n| n.parse::<u8>().ok())
+        .filter(|&n| (n as usize) < NREGS)
+        .ok_or_else(|| format!("invalid register `{word}`"))
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, word),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// A float literal, which has a decimal point or an exponent to be told
+/// apart from integers.
+fn parse_float(word: &str) -> Option<f32> {
+    let digits = word.strip_prefix('-').unwrap_or(word);
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) || !digits.contains(['.', 'e', 'E']) {
+        return None;
+    }
+    word.parse().ok()
+}
+
+fn parse_operand(word: &str) -> Result<Operand, String> {
+    if let Some(value) = parse_number(word) {
+        Ok(Operand::Value(value))
+    } else if let Some(value) = parse_float(word) {
+        Ok(Operand::Value(value.to_bits() as i64))
+    } else if is_label(word) {
+        Ok(Operand::Label(word.to_string()))
+    } else {
+        Err(format!("invalid operand `{word}`"))
+    }
+}
+
+fn parse_instruction(words: &[&str]) -> Result<Item, String> {
+    let instr = match *words {
+        ["move", a, "<-", b, "if", c, "!=", "0"] => {
+            Instruction::MoveIf { dst: parse_reg(a)?, src: parse_reg(b)?, cond: parse_reg(c)? }
+        }
+        ["move", a, "<-", b, "if", c, "==", "0"] => {
+            Instruction::MoveIfZero { dst: parse_reg(a)?, src: parse_reg(b)?, cond: parse_reg(c)? }
+        }
+        ["move", a, "<-", b, "if", c, "<", "0"] => {
+            Instruction::MoveIfNeg { dst: parse_reg(a)?, src: parse_reg(b)?, cond: parse_reg(c)? }
+        }
+        [op, a, "<-", b, c] if Comparison::ALL.iter().any(|cmp| cmp.mnemonic() == op) => {
+            let op = *Comparison::ALL.iter().find(|cmp| cmp.mnemonic() == op).unwrap();
+            let lhs = parse_reg(b.strip_suffix(',').ok_or_else(|| format!("expected `,` after `{b}`"))?)?;
+            Instruction::Compare { op, dst: parse_reg(a)?, lhs, rhs: parse_reg(c)? }
+        }
+        [op, a, "<-", b, c] if FloatComparison::ALL.iter().any(|cmp| cmp.mnemonic() == op) => {
+            let op = *FloatComparison::ALL.iter().find(|cmp| cmp.mnemonic() == op).unwrap();
+            let lhs = parse_reg(b.strip_suffix(',').ok_or_else(|| format!("expected `,` after `{b}`"))?)?;
+            Instruction::FloatCompare { op, dst: parse_reg(a)?, lhs, rhs: parse_reg(c)? }
+        }
+        [op, a, "<-", b, symbol, c] if FloatOp::ALL.iter().any(|fop| fop.mnemonic() == op && fop.symbol() == symbol) => {
+            let op = *FloatOp::ALL.iter().find(|fop| fop.mnemonic() == op).unwrap();
+            Instruction::FloatArith { op, dst: parse_reg(a)?, lhs: parse_reg(b)?, rhs: parse_reg(c)? }
+        }
+        ["itof", a, "<-", b] => Instruction::IntToFloat { dst: parse_reg(a)?, src: parse_reg(b)? },
+        ["ftoi", a, "<-", b] => Instruction::FloatToInt { dst: parse_reg(a)?, src: parse_reg(b)? },
+        ["out_float", a] => Instruction::OutFloat { src: parse_reg(a)? },
+        ["store", a, "<-", b] => Instruction::Store { addr: parse_indirect(a)?, src: parse_reg(b)? },
+        ["load", a, "<-", b] => Instruction::Load { dst: parse_reg(a)?, addr: parse_indirect(b)? },
+        [op @ ("load8" | "load8s"), a, "<-", b] => {
+            Instruction::Load8 { dst: parse_reg(a)?, addr: parse_indirect(b)?, signed: op == "load8s" }
+        }
+        [op @ ("load16" | "load16s"), a, "<-", b] => {
+            Instruction::Load16 { dst: parse_reg(a)?, addr: parse_indirect(b)?, signed: op == "load16s" }
+        }
+        ["store8", a, "<-", b] => Instruction::Store8 { addr: parse_indirect(a)?, src: parse_reg(b)? },
+        ["store16", a, "<-", b] => Instruction::Store16 { addr: parse_indirect(a)?, src: parse_reg(b)? },
+        [op @ ("loadimm" | "loadimm32"), a, "<-", imm] => {
+            let value = parse_operand(parse_immediate(imm)?)?;
+            return Ok(Item::LoadImm { dst: parse_reg(a)?, value, wide: op == "loadimm32" });
+        }
+        ["loadhi", a, "<-", imm] => {
+            let value = parse_number(parse_immediate(imm)?).filter(|v| (0..=0xffff).contains(v));
+            let imm = value.ok_or_else(|| format!("invalid 16-bit value `{imm}`"))? as u16;
+            Instruction::LoadHi { dst: parse_reg(a)?, imm }
+        }
+        ["sub", a, "<-", b, "-", c] => Instruction::Sub { dst: parse_reg(a)?, lhs: parse_reg(b)?, rhs: parse_reg(c)? },
+        ["div", a, "<-", b, "/", c] => Instruction::Div { dst: parse_reg(a)?, lhs: parse_reg(b)?, rhs: parse_reg(c)? },
+        ["out", a] => Instruction::Out { src: parse_reg(a)? },
+        ["exit"] => Instruction::Exit,
+        ["rti"] => Instruction::ReturnFromTrap,
+        ["setptb", a] => Instruction::SetPageTable { src: parse_reg(a)? },
+        ["out_number", a] => Instruction::OutNumber { src: parse_reg(a)? },
+        ["in", a] => Instruction::In { dst: parse_reg(a)? },
+        ["jmp", target] => return parse_branch(Instruction::Jump { offset: 0 }, target),
+        [op @ ("bz" | "bnz" | "bneg"), a, target] => {
+            let cond = match op {
+                "bz" => Condition::Zero,
+                "bnz" => Condition::NonZero,
+                _ => Condition::Negative,
+            };
+            let src = parse_reg(a.strip_suffix(',').ok_or_else(|| format!("expected `,` after `{a}`"))?)?;
+            return parse_branch(Instruction::Branch { cond, src, offset: 0 }, target);
+        }
+        _ => return Err(format!("cannot parse `{}`", words.join(" "))),
+    };
+    Ok(Item::Instr(instr))
+}
+
+/// Relative jump or branch to `target`, which is either a label or an
+/// immediate offset.
+fn parse_branch(instr: Instruction, target: &str) -> Result<Item, String> {
+    let offset = match parse_immediate(target) {
+        Ok(offset) => parse_number(offset)
+            .and_then(|v| i16::try_from(v).ok())
+            .ok_or_else(|| format!("invalid 16-bit offset `{target}`"))?,
+        Err(_) if is_label(target) => return Ok(Item::Branch { instr, label: target.to_string() }),
+        Err(_) => return Err(format!("invalid branch target `{target}`")),
+    };
+    Ok(Item::Instr(with_offset(instr, offset)))
+}
+
+fn with_offset(instr: Instruction, offset: i16) -> Instruction {
+    match instr {
+        Instruction::Jump { .. } => Instruction::Jump { offset },
+        Instruction::Branch { cond, src, .. } => Instruction::Branch { cond, src, offset },
+        instr => instr,
+    }
+}
+
+fn parse_immediate(word: &str) -> Result<&str, String> {
+    word.strip_prefix('#').ok_or_else(|| format!("expected `#` before `{word}`"))
+}
+
+fn parse_indirect(word: &str) -> Result<u8, String> {
+    word.strip_prefix('[')
+        .and_then(|w| w.strip_suffix(']'))
+        .ok_or_else(|| format!("expected `[rX]`, found `{word}`"))
+        .and_then(parse_reg)
+}
+
+fn parse_bytes_literal(text: &str) -> Result<Vec<u8>, String> {
+    let quote = text.as_bytes()[1];
+    let body = text[2..]
+        .strip_suffix(quote as char)
+        .ok_or_else(|| format!("unterminated bytes literal `{text}`"))?;
+    let mut bytes = vec![];
+    let mut chars = body.bytes();
+    while let Some(b) = chars.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some(b'n') => b'\n',
+            Some(b't') => b'\t',
+            Some(b'r') => b'\r',
+            Some(b'0') => 0,
+            Some(b'x') => {
+                let hex: Vec<u8> = chars.by_ref().take(2).collect();
+                std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| format!("invalid escape in `{text}`"))?
+            }
+            Some(c @ (b'\\' | b'\'' | b'"')) => c,
+            _ => return Err(format!("invalid escape in `{text}`")),
+        });
+    }
+    Ok(bytes)
+}
+
+fn parse_byte_list(text: &str) -> Result<Vec<u8>, String> {
+    let body = text
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or_else(|| format!("unterminated byte list `{text}`"))?;
+    body.split(',')
+        .map(str::trim)
+        .filter(|b| !b.is_empty())
+        .map(|b| parse_number(b).and_then(|v| u8::try_from(v).ok()).ok_or_else(|| format!("invalid byte `{b}`")))
+        .collect()
+}
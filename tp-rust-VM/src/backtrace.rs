@@ -0,0 +1,97 @@
+use crate::{Instruction, Machine, SymbolTable, MEMORY_SIZE};
+use std::fmt::Write;
+
+const IP: u8 = 0;
+const SP: u8 = 2;
+
+/// One level of the call chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Address of the faulting instruction for the innermost frame, or of
+    /// the jump to the callee for the other ones.
+    pub pc: u32,
+    /// Entry point of the function this frame belongs to, when known.
+    pub function: Option<u32>,
+    /// Stack slot holding the return address into this frame.
+    pub return_slot: Option<u32>,
+}
+
+/// Call chain of a machine, recovered from the return addresses saved on the
+/// r2 stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backtrace {
+    pub frames: Vec<Frame>,
+}
+
+/// Call sequence found right before a return address:
+///
+/// ```text
+///     loadimm rX <- #return_address
+///     store [r2] <- rX
+///     loadimm r0 <- #callee          ; or jmp callee
+/// return_address:
+/// ```
+///
+/// Returns the address of the jump and the callee.
+fn call_before(mem: &[u8], return_address: u32) -> Option<(u32, u32)> {
+    let ret = return_address as usize;
+    let (jump, callee) = match Instruction::decode(mem, ret.checked_sub(4)?) {
+        Ok(Instruction::LoadImm { dst: IP, imm }) => (ret - 4, imm as i32 as u32),
+        _ => {
+            let jump = ret.checked_sub(3)?;
+            match Instruction::decode(mem, jump) {
+                Ok(instr @ Instruction::Jump { .. }) => (jump, instr.relative_target(jump)? as u32),
+                _ => return None,
+            }
+        }
+    };
+    let Ok(Instruction::Store { addr: SP, src }) = Instruction::decode(mem, jump.checked_sub(3)?) else {
+        return None;
+    };
+    match Instruction::decode(mem, jump.checked_sub(7)?) {
+        Ok(Instruction::LoadImm { dst, imm }) if dst == src && imm as i32 == ret as i32 => Some((jump as u32, callee)),
+        _ => None,
+    }
+}
+
+impl Backtrace {
+    /// Walk the stack of `machine` from r2 to the end of memory, keeping the
+    /// words which are return addresses, that is addresses located right
+    /// after a call sequence. The innermost frame is the one of the last
+    /// executed instruction.
+    pub fn capture(machine: &Machine) -> Backtrace {
+        let mem = machine.memory();
+        let mut frames = vec![Frame { pc: machine.last_ip(), function: None, return_slot: None }];
+        let mut slot = machine.regs()[SP as usize] as usize;
+        while slot + 4 <= MEMORY_SIZE {
+            let word = u32::from_le_bytes(mem[slot..slot + 4].try_into().unwrap());
+            if let Some((jump, callee)) = call_before(mem, word) {
+                frames.last_mut().unwrap().function = Some(callee);
+                frames.push(Frame { pc: jump, function: None, return_slot: Some(slot as u32) });
+            }
+            slot += 4;
+        }
+        Backtrace { frames }
+    }
+
+    /// Render the backtrace, one frame per line, innermost first. Addresses
+    /// are symbolized when a symbol table is available.
+    pub fn format(&self, symbols: Option<&SymbolTable>) -> String {
+        let describe = |addr: u32| match symbols {
+            Some(symbols) => symbols.describe(addr),
+            None => format!("{addr:04}"),
+        };
+        let mut out = String::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            write!(out, "#{i:<3}{:04}", frame.pc).unwrap();
+            if let Some(function) = frame.function {
+                write!(out, " in {}", describe(function)).unwrap();
+            }
+            if symbols.is_some() {
+                write!(out, " ({})", describe(frame.pc)).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
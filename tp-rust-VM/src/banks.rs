@@ -0,0 +1,47 @@
+use crate::MEMORY_SIZE;
+
+/// Address of the window onto the selected bank, right above the machine
+/// memory.
+pub const BANK_WINDOW: usize = MEMORY_SIZE;
+/// Address of the 32-bit bank-select port, right above the window. Stores
+/// to it select the bank visible in the window, and loads read it back.
+pub const BANK_SELECT: usize = BANK_WINDOW + MEMORY_SIZE;
+
+/// Banks of memory, one of which at a time is visible in the window.
+#[derive(Default)]
+pub(crate) struct Banks {
+    pub banks: Vec<Box<[u8; MEMORY_SIZE]>>,
+    pub selected: u32,
+}
+
+impl Banks {
+    /// Whether `addr` can be accessed, being in the port or in the window
+    /// while a bank which exists is selected.
+    pub fn contains(&self, addr: usize) -> bool {
+        match addr {
+            _ if self.banks.is_empty() => false,
+            BANK_WINDOW..BANK_SELECT => (self.selected as usize) < self.banks.len(),
+            _ => (BANK_SELECT..BANK_SELECT + 4).contains(&addr),
+        }
+    }
+
+    /// Byte at `addr`, which must be [contained](Banks::contains).
+    pub fn read(&self, addr: usize) -> u8 {
+        match addr {
+            BANK_WINDOW..BANK_SELECT => self.banks[self.selected as usize][addr - BANK_WINDOW],
+            _ => self.selected.to_le_bytes()[addr - BANK_SELECT],
+        }
+    }
+
+    /// Write the byte at `addr`, which must be [contained](Banks::contains).
+    pub fn write(&mut self, addr: usize, byte: u8) {
+        match addr {
+            BANK_WINDOW..BANK_SELECT => self.banks[self.selected as usize][addr - BANK_WINDOW] = byte,
+            _ => {
+                let mut port = self.selected.to_le_bytes();
+                port[addr - BANK_SELECT] = byte;
+                self.selected = u32::from_le_bytes(port);
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{assemble, ObjectFile};
+use std::fmt::{self, Write};
+
+/// Error reported by the Brainfuck translator, with the 1-based line of the
+/// offending bracket.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BfError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for BfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Translate a Brainfuck program into an object file.
+///
+/// The tape starts right after the program and extends to the end of the
+/// memory, one byte per cell, with r10 pointing to the current cell. Runs of
+/// `+`, `-`, `<` and `>` are folded into a single `sub`. `.` prints the cell
+/// with `out`, and `,` reads it with `in`, storing 0 at the end of the input.
+/// Loops are compiled into `move_if` branches, as in the listings. Every
+/// other character is a comment.
+pub fn bf2vm(source: &str) -> Result<ObjectFile, BfError> {
+    let mut asm = String::from("    loadimm r10 <- #tape\n    loadimm r13 <- #0\n");
+    // Number and line of the open loops
+    let mut loops: Vec<(usize, usize)> = vec![];
+    let mut count = 0;
+
+    let mut ops = source
+        .lines()
+        .enumerate()
+        .flat_map(|(idx, text)| text.bytes().map(move |op| (idx + 1, op)))
+        .filter(|(_, op)| b"+-<>.,[]".contains(op))
+        .peekable();
+    while let Some((line, op)) = ops.next() {
+        let mut run = 1;
+        if b"+-<>".contains(&op) {
+            while ops.next_if(|&(_, next)| next == op).is_some() {
+                run += 1;
+            }
+        }
+        match op {
+            b'+' | b'-' => {
+                let delta = if op == b'+' { -run } else { run };
+                writeln!(asm, "    load8 r11 <- [r10]\n    loadimm r12 <- #{delta}").unwrap();
+                asm.push_str("    sub r11 <- r11 - r12\n    store8 [r10] <- r11\n");
+            }
+            b'>' | b'<' => {
+                let delta = if op == b'>' { -run } else { run };
+                writeln!(asm, "    loadimm r12 <- #{delta}\n    sub r10 <- r10 - r12").unwrap();
+            }
+            b'.' => asm.push_str("    load8 r11 <- [r10]\n    out r11\n"),
+            b',' => asm.push_str("    in r11\n    move r11 <- r13 if r11 < 0\n    store8 [r10] <- r11\n"),
+            b'[' => {
+                count += 1;
+                loops.push((count, line));
+                writeln!(asm, "    load8 r11 <- [r10]\n    loadimm r12 <- #bf_body_{count}").unwrap();
+                writeln!(asm, "    move r0 <- r12 if r11 != 0\n    loadimm r0 <- #bf_end_{count}\nbf_body_{count}:").unwrap();
+            }
+            _ => {
+                let (n, _) = loops.pop().ok_or_else(|| BfError { line, message: "unmatched `]`".to_string() })?;
+                writeln!(asm, "    load8 r11 <- [r10]\n    loadimm r12 <- #bf_body_{n}").unwrap();
+                writeln!(asm, "    move r0 <- r12 if r11 != 0\nbf_end_{n}:").unwrap();
+            }
+        }
+    }
+    if let Some(&(_, line)) = loops.last() {
+        return Err(BfError { line, message: "unmatched `[`".to_string() });
+    }
+    asm.push_str("    exit\ntape:\n");
+
+    Ok(assemble(&asm).expect("the translation is valid assembly"))
+}
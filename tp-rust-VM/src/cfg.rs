@@ -0,0 +1,248 @@
+use crate::Instruction;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+const IP: u8 = 0;
+const SP: u8 = 2;
+
+/// How the control leaves a basic block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    /// The block runs into the next one, which starts at the given address.
+    Fallthrough(usize),
+    /// `loadimm r0 <- #target` or `jmp target`
+    Jump(usize),
+    /// A conditional move to r0 such as `move r0 <- rX if rY != 0` where `rX`
+    /// was loaded with a known address, or a relative conditional branch.
+    Branch { taken: usize, fallthrough: usize },
+    /// A return address pushed on the r2 stack followed by a jump.
+    Call { target: usize, return_to: usize },
+    /// `load r0 <- [rX]`, that is a return through the r2 stack.
+    Return,
+    /// A write to r0 whose value is not statically known. If the write is
+    /// conditional, execution may also continue at `fallthrough`.
+    Indirect { fallthrough: Option<usize> },
+    /// `exit`
+    Exit,
+    /// The next instruction cannot be decoded.
+    Invalid,
+}
+
+/// Kind of an edge between two basic blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    Taken,
+    NotTaken,
+    Call,
+    CallReturn,
+}
+
+/// A straight-line sequence of instructions with a single entry point.
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+    pub terminator: Terminator,
+}
+
+impl BasicBlock {
+    /// Address just after the last instruction of the block.
+    pub fn end(&self) -> usize {
+        match self.instructions.last() {
+            Some((addr, instr)) => addr + instr.size(),
+            None => self.start,
+        }
+    }
+
+    /// Addresses of the blocks which may be executed after this one.
+    pub fn successors(&self) -> Vec<(usize, EdgeKind)> {
+        match self.terminator {
+            Terminator::Fallthrough(next) => vec![(next, EdgeKind::Fallthrough)],
+            Terminator::Jump(target) => vec![(target, EdgeKind::Jump)],
+            Terminator::Branch { taken, fallthrough } => {
+                vec![(taken, EdgeKind::Taken), (fallthrough, EdgeKind::NotTaken)]
+            }
+            Terminator::Call { target, return_to } => {
+                vec![(target, EdgeKind::Call), (return_to, EdgeKind::CallReturn)]
+            }
+            Terminator::Indirect { fallthrough: Some(next) } => vec![(next, EdgeKind::NotTaken)],
+            _ => vec![],
+        }
+    }
+}
+
+/// Control-flow graph of a program, recovered by following the branch idioms
+/// of the listings from the entry point.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    entry: usize,
+    blocks: BTreeMap<usize, BasicBlock>,
+}
+
+impl Cfg {
+    /// Build the control-flow graph of the program stored in `code`, starting
+    /// the exploration at `entry`. Bytes which are never reached (such as
+    /// strings) are not part of any block.
+    pub fn build(code: &[u8], entry: usize) -> Cfg {
+        let mut instrs: BTreeMap<usize, Option<Instruction>> = BTreeMap::new();
+        let mut terminators: BTreeMap<usize, Terminator> = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut work = vec![entry];
+
+        while let Some(start) = work.pop() {
+            leaders.insert(start);
+            let mut addr = start;
+            // Constants loaded in registers along this trace, and the value
+            // pushed on the stack by the previous instruction if any.
+            let mut consts: [Option<usize>; 16] = [None; 16];
+            let mut pushed: Option<usize> = None;
+
+            while !instrs.contains_key(&addr) {
+                let instr = match Instruction::decode(code, addr) {
+                    Ok(instr) => instr,
+                    Err(_) => {
+                        instrs.insert(addr, None);
+                        terminators.insert(addr, Terminator::Invalid);
+                        break;
+                    }
+                };
+                instrs.insert(addr, Some(instr));
+                let next = addr + instr.size();
+                let konst = |reg: u8| consts.get(reg as usize).copied().flatten();
+
+                let terminator = match (instr, instr.constant_load()) {
+                    (_, Some((IP, target))) => {
+                        let target = target as usize;
+                        Some(match pushed {
+                            Some(return_to) => Terminator::Call { target, return_to },
+                            None => Terminator::Jump(target),
+                        })
+                    }
+                    (Instruction::MoveIf { dst: IP, src, cond }, _) => Some(match (konst(src), cond) {
+                        // r0 is never 0 once incremented, the move always happens
+                        (Some(target), IP) => Terminator::Jump(target),
+                        (None, IP) => Terminator::Indirect { fallthrough: None },
+                        (Some(taken), _) => Terminator::Branch { taken, fallthrough: next },
+                        (None, _) => Terminator::Indirect { fallthrough: Some(next) },
+                    }),
+                    (Instruction::MoveIfZero { dst: IP, src, .. } | Instruction::MoveIfNeg { dst: IP, src, .. }, _) => {
+                        Some(match konst(src) {
+                            Some(taken) => Terminator::Branch { taken, fallthrough: next },
+                            None => Terminator::Indirect { fallthrough: Some(next) },
+                        })
+                    }
+                    (Instruction::Jump { .. }, _) => instr.relative_target(addr).map(|target| match pushed {
+                        Some(return_to) => Terminator::Call { target, return_to },
+                        None => Terminator::Jump(target),
+                    }),
+                    (Instruction::Branch { .. }, _) => {
+                        instr.relative_target(addr).map(|taken| Terminator::Branch { taken, fallthrough: next })
+                    }
+                    (Instruction::Load { dst: IP, .. }, _) => Some(Terminator::Return),
+                    (Instruction::Exit, _) => Some(Terminator::Exit),
+                    _ if instr.written_reg() == Some(IP) => Some(Terminator::Indirect { fallthrough: None }),
+                    _ => None,
+                };
+
+                pushed = match instr {
+                    Instruction::Store { addr: SP, src } => konst(src),
+                    _ => None,
+                };
+                if let Some(dst) = instr.written_reg() {
+                    if let Some(slot) = consts.get_mut(dst as usize) {
+                        *slot = instr.constant_load().map(|(_, value)| value as usize);
+                    }
+                }
+
+                if let Some(terminator) = terminator {
+                    terminators.insert(addr, terminator);
+                    let block = BasicBlock { start: addr, instructions: vec![], terminator };
+                    for (succ, _) in block.successors() {
+                        leaders.insert(succ);
+                        work.push(succ);
+                    }
+                    break;
+                }
+                addr = next;
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut current: Option<BasicBlock> = None;
+        for (&addr, instr) in &instrs {
+            if let Some(mut block) = current.take() {
+                if leaders.contains(&addr) || block.end() != addr {
+                    block.terminator = Terminator::Fallthrough(block.end());
+                    blocks.insert(block.start, block);
+                } else {
+                    current = Some(block);
+                }
+            }
+            let block = current.get_or_insert_with(|| BasicBlock {
+                start: addr,
+                instructions: vec![],
+                terminator: Terminator::Invalid,
+            });
+            if let Some(instr) = instr {
+                block.instructions.push((addr, *instr));
+            }
+            if let Some(&terminator) = terminators.get(&addr) {
+                block.terminator = terminator;
+                blocks.insert(block.start, current.take().unwrap());
+            }
+        }
+        if let Some(mut block) = current {
+            block.terminator = Terminator::Fallthrough(block.end());
+            blocks.insert(block.start, block);
+        }
+
+        Cfg { entry, blocks }
+    }
+
+    /// Address of the entry block.
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    /// Basic blocks, sorted by address.
+    pub fn blocks(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks.values()
+    }
+
+    /// Basic block starting at `addr`, if any.
+    pub fn block(&self, addr: usize) -> Option<&BasicBlock> {
+        self.blocks.get(&addr)
+    }
+
+    /// Export the graph in the Graphviz DOT format. Each block is labeled
+    /// with its disassembly.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks() {
+            let mut label = String::new();
+            for (addr, instr) in &block.instructions {
+                write!(label, "{addr:04}   {instr}\\l").unwrap();
+            }
+            if block.terminator == Terminator::Invalid {
+                write!(label, "{:04}   ???\\l", block.end()).unwrap();
+            }
+            writeln!(dot, "    b{:04} [label=\"{}\"];", block.start, label.replace('"', "\\\"")).unwrap();
+        }
+        for block in self.blocks() {
+            for (succ, kind) in block.successors() {
+                let attrs = match kind {
+                    EdgeKind::Fallthrough | EdgeKind::Jump => "",
+                    EdgeKind::Taken => " [label=\"taken\"]",
+                    EdgeKind::NotTaken => " [label=\"not taken\"]",
+                    EdgeKind::Call => " [label=\"call\"]",
+                    EdgeKind::CallReturn => " [style=dashed]",
+                };
+                writeln!(dot, "    b{:04} -> b{succ:04}{attrs};", block.start).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
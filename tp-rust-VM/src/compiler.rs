@@ -0,0 +1,765 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+/// Error reported by the compiler, with the 1-based line it occurred on.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Instructions the compiler may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isa {
+    /// The eight opcodes of the original machine only.
+    #[default]
+    Base,
+    /// The extended opcodes as well, which allow ordered comparisons.
+    Extended,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Punct(&'static str),
+}
+
+const PUNCTS: [&str; 17] = ["==", "!=", "<=", ">=", "<", ">", "=", "+", "-", "*", "(", ")", "{", "}", ",", ";", "!"];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, CompileError> {
+    let mut tokens = vec![];
+    for (idx, text) in source.lines().enumerate() {
+        let line = idx + 1;
+        let err = |message: String| CompileError { line, message };
+        let bytes = text.as_bytes();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let c = bytes[pos];
+            if c.is_ascii_whitespace() {
+                pos += 1;
+            } else if text[pos..].starts_with("//") {
+                // Strings are read at once, so this comment is outside of them
+                break;
+            } else if c.is_ascii_alphabetic() || c == b'_' {
+                let len = bytes[pos..].iter().take_while(|b| b.is_ascii_alphanumeric() || **b == b'_').count();
+                tokens.push((line, Token::Ident(text[pos..pos + len].to_string())));
+                pos += len;
+            } else if c.is_ascii_digit() {
+                let len = bytes[pos..].iter().take_while(|b| b.is_ascii_digit()).count();
+                let value = text[pos..pos + len]
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|&v| v <= u32::MAX as i64)
+                    .ok_or_else(|| err(format!("number `{}` does not fit in 32 bits", &text[pos..pos + len])))?;
+                tokens.push((line, Token::Number(value)));
+                pos += len;
+            } else if c == b'"' {
+                let mut string = vec![];
+                pos += 1;
+                loop {
+                    match bytes.get(pos) {
+                        None => return Err(err("unterminated string".to_string())),
+                        Some(b'"') => break,
+                        Some(b'\\') => {
+                            string.push(match bytes.get(pos + 1) {
+                                Some(b'n') => b'\n',
+                                Some(b't') => b'\t',
+                                Some(b'0') => 0,
+                                Some(&c @ (b'\\' | b'"')) => c,
+                                _ => return Err(err("invalid escape in string".to_string())),
+                            });
+                            pos += 2;
+                        }
+                        Some(&c) => {
+                            string.push(c);
+                            pos += 1;
+                        }
+                    }
+                }
+                tokens.push((line, Token::Str(string)));
+                pos += 1;
+            } else {
+                let punct = PUNCTS
+                    .iter()
+                    .find(|p| text[pos..].starts_with(**p))
+                    .ok_or_else(|| err(format!("unexpected character `{}`", &text[pos..].chars().next().unwrap())))?;
+                tokens.push((line, Token::Punct(punct)));
+                pos += punct.len();
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Var(String),
+    Call(String, Vec<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+enum PrintArg {
+    Expr(Expr),
+    Str(Vec<u8>),
+}
+
+#[derive(Debug)]
+enum StmtKind {
+    Var(String, Expr),
+    Assign(String, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Print(Vec<PrintArg>),
+    Expr(Expr),
+}
+
+#[derive(Debug)]
+struct Stmt {
+    line: usize,
+    kind: StmtKind,
+}
+
+#[derive(Debug)]
+struct Function {
+    line: usize,
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        match self.tokens.get(self.pos).or(self.tokens.last()) {
+            Some((line, _)) => *line,
+            None => 1,
+        }
+    }
+
+    fn err<T>(&self, message: String) -> Result<T, CompileError> {
+        Err(CompileError { line: self.line(), message })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), CompileError> {
+        if !self.eat(punct) {
+            return self.err(format!("expected `{punct}`"));
+        }
+        Ok(())
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(name)) if name == keyword)
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        match self.next() {
+            Some(Token::Ident(name)) if !KEYWORDS.contains(&name.as_str()) => Ok(name),
+            _ => {
+                self.pos -= 1;
+                self.err("expected an identifier".to_string())
+            }
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, CompileError> {
+        let line = self.line();
+        if !self.is_keyword("fn") {
+            return self.err("expected `fn`".to_string());
+        }
+        self.pos += 1;
+        let name = self.ident()?;
+        self.expect("(")?;
+        let mut params = vec![];
+        while !self.eat(")") {
+            if !params.is_empty() {
+                self.expect(",")?;
+            }
+            params.push(self.ident()?);
+        }
+        let body = self.block()?;
+        Ok(Function { line, name, params, body })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut stmts = vec![];
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return self.err("expected `}`".to_string());
+            }
+            stmts.push(self.statement()?);
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        let keyword = match self.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            _ => String::new(),
+        };
+        let kind = match keyword.as_str() {
+            "var" => {
+                self.pos += 1;
+                let name = self.ident()?;
+                self.expect("=")?;
+                let value = self.expr()?;
+                self.expect(";")?;
+                StmtKind::Var(name, value)
+            }
+            "if" => {
+                self.pos += 1;
+                let cond = self.condition()?;
+                let then = self.block()?;
+                let otherwise = if self.is_keyword("else") {
+                    self.pos += 1;
+                    if self.is_keyword("if") {
+                        vec![self.statement()?]
+                    } else {
+                        self.block()?
+                    }
+                } else {
+                    vec![]
+                };
+                StmtKind::If(cond, then, otherwise)
+            }
+            "while" => {
+                self.pos += 1;
+                let cond = self.condition()?;
+                StmtKind::While(cond, self.block()?)
+            }
+            "return" => {
+                self.pos += 1;
+                let value = if self.is_punct(";") { None } else { Some(self.expr()?) };
+                self.expect(";")?;
+                StmtKind::Return(value)
+            }
+            "print" => {
+                self.pos += 1;
+                self.expect("(")?;
+                let mut args = vec![];
+                while !self.eat(")") {
+                    if !args.is_empty() {
+                        self.expect(",")?;
+                    }
+                    args.push(match self.peek() {
+                        Some(Token::Str(string)) => {
+                            let string = string.clone();
+                            self.pos += 1;
+                            PrintArg::Str(string)
+                        }
+                        _ => PrintArg::Expr(self.expr()?),
+                    });
+                }
+                self.expect(";")?;
+                StmtKind::Print(args)
+            }
+            _ => {
+                let kind = match self.tokens.get(self.pos + 1) {
+                    Some((_, Token::Punct("="))) => {
+                        let name = self.ident()?;
+                        self.pos += 1;
+                        StmtKind::Assign(name, self.expr()?)
+                    }
+                    _ => StmtKind::Expr(self.expr()?),
+                };
+                self.expect(";")?;
+                kind
+            }
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    fn condition(&mut self) -> Result<Expr, CompileError> {
+        self.expect("(")?;
+        let cond = self.expr()?;
+        self.expect(")")?;
+        Ok(cond)
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        let lhs = self.additive()?;
+        let op = match self.peek() {
+            Some(Token::Punct("==")) => BinOp::Eq,
+            Some(Token::Punct("!=")) => BinOp::Ne,
+            Some(Token::Punct("<")) => BinOp::Lt,
+            Some(Token::Punct("<=")) => BinOp::Le,
+            Some(Token::Punct(">")) => BinOp::Gt,
+            Some(Token::Punct(">=")) => BinOp::Ge,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        Ok(Expr::Binary(op, Box::new(lhs), Box::new(self.additive()?)))
+    }
+
+    fn additive(&mut self) -> Result<Expr, CompileError> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct("+")) => BinOp::Add,
+                Some(Token::Punct("-")) => BinOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, CompileError> {
+        let mut lhs = self.unary()?;
+        while self.eat("*") {
+            lhs = Expr::Binary(BinOp::Mul, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Punct("(")) => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(_)) => {
+                self.pos -= 1;
+                let name = self.ident()?;
+                if !self.eat("(") {
+                    return Ok(Expr::Var(name));
+                }
+                let mut args = vec![];
+                while !self.eat(")") {
+                    if !args.is_empty() {
+                        self.expect(",")?;
+                    }
+                    args.push(self.expr()?);
+                }
+                Ok(Expr::Call(name, args))
+            }
+            _ => {
+                self.pos -= 1;
+                self.err("expected an expression".to_string())
+            }
+        }
+    }
+}
+
+const KEYWORDS: [&str; 7] = ["fn", "var", "if", "else", "while", "return", "print"];
+
+/// Code generation for one function. Expressions are evaluated in r1, with
+/// their operands in r4 and r5, and the intermediate results pushed on the r2
+/// stack. r6 to r8 are used by the runtime routines and to compute addresses,
+/// and r12 is the frame pointer.
+struct Codegen<'a> {
+    isa: Isa,
+    functions: &'a HashMap<String, usize>,
+    function: &'a Function,
+    /// Variables in scope, innermost scope last, with their frame offset.
+    scopes: Vec<HashMap<String, i32>>,
+    locals: i32,
+    loops: usize,
+    line: usize,
+    out: String,
+    strings: &'a mut Vec<Vec<u8>>,
+    uses_mul: &'a mut bool,
+    uses_print: &'a mut bool,
+}
+
+impl Codegen<'_> {
+    fn err<T>(&self, message: String) -> Result<T, CompileError> {
+        Err(CompileError { line: self.line, message })
+    }
+
+    fn emit(&mut self, text: &str) {
+        writeln!(self.out, "    {text}").unwrap();
+    }
+
+    fn label(&mut self, name: &str) {
+        writeln!(self.out, "{name}:").unwrap();
+    }
+
+    /// Frame offset of a variable: parameters are above the return address
+    /// and the saved frame pointer, locals below.
+    fn offset(&self, name: &str) -> Result<i32, CompileError> {
+        if let Some(&offset) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return Ok(offset);
+        }
+        match self.function.params.iter().position(|p| p == name) {
+            Some(i) => Ok(8 + 4 * (self.function.params.len() - 1 - i) as i32),
+            None => self.err(format!("unknown variable `{name}`")),
+        }
+    }
+
+    /// Compute the address of a variable in r6.
+    fn address(&mut self, name: &str) -> Result<(), CompileError> {
+        let offset = self.offset(name)?;
+        self.emit(&format!("loadimm r6 <- #{}", -offset));
+        self.emit("sub r6 <- r12 - r6");
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), CompileError> {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        self.line = stmt.line;
+        match &stmt.kind {
+            StmtKind::Var(name, value) => {
+                self.expr(value)?;
+                self.locals += 1;
+                let offset = -4 * self.locals;
+                self.scopes.last_mut().unwrap().insert(name.clone(), offset);
+                self.address(name)?;
+                self.emit("store [r6] <- r1");
+            }
+            StmtKind::Assign(name, value) => {
+                self.expr(value)?;
+                self.address(name)?;
+                self.emit("store [r6] <- r1");
+            }
+            StmtKind::If(cond, then, otherwise) => {
+                self.expr(cond)?;
+                self.emit("if r1 != 0 {");
+                self.block(then)?;
+                if !otherwise.is_empty() {
+                    self.emit("} else {");
+                    self.block(otherwise)?;
+                }
+                self.emit("}");
+            }
+            StmtKind::While(cond, body) => {
+                self.loops += 1;
+                let label = format!("{}.loop_{}", self.function.name, self.loops);
+                self.label(&label);
+                self.expr(cond)?;
+                self.emit("if r1 != 0 {");
+                self.block(body)?;
+                self.emit(&format!("loadimm r0 <- #{label}"));
+                self.emit("}");
+            }
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value)?,
+                    None => self.emit("loadimm r1 <- #0"),
+                }
+                self.emit(&format!("loadimm r0 <- #{}.end", self.function.name));
+            }
+            StmtKind::Print(args) => {
+                for arg in args {
+                    match arg {
+                        PrintArg::Expr(expr) => {
+                            self.expr(expr)?;
+                            self.emit("out_number r1");
+                        }
+                        PrintArg::Str(string) => {
+                            self.strings.push(string.clone());
+                            self.emit(&format!("loadimm r4 <- #str.{}", self.strings.len()));
+                            self.emit(&format!("loadimm r5 <- #{}", string.len()));
+                            self.emit("call rt.print");
+                            *self.uses_print = true;
+                        }
+                    }
+                }
+            }
+            StmtKind::Expr(expr) => self.expr(expr)?,
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        match expr {
+            Expr::Number(value) => self.number(*value),
+            Expr::Var(name) => {
+                self.address(name)?;
+                self.emit("load r1 <- [r6]");
+            }
+            Expr::Call(name, args) => {
+                match self.functions.get(name) {
+                    Some(&arity) if arity == args.len() => {}
+                    Some(&arity) => return self.err(format!("`{name}` expects {arity} arguments")),
+                    None => return self.err(format!("unknown function `{name}`")),
+                }
+                for arg in args {
+                    self.expr(arg)?;
+                    self.emit("push r1");
+                }
+                self.emit(&format!("call {name}"));
+                if !args.is_empty() {
+                    self.emit(&format!("loadimm r6 <- #{}", -4 * args.len() as i32));
+                    self.emit("sub r2 <- r2 - r6");
+                }
+            }
+            Expr::Neg(expr) => {
+                self.expr(expr)?;
+                self.emit("loadimm r4 <- #0");
+                self.emit("sub r1 <- r4 - r1");
+            }
+            Expr::Not(expr) => {
+                self.expr(expr)?;
+                self.emit("move r4 <- r1 if r0 != 0");
+                self.emit("loadimm r1 <- #1");
+                self.emit("loadimm r5 <- #0");
+                self.emit("move r1 <- r5 if r4 != 0");
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                self.expr(lhs)?;
+                self.emit("push r1");
+                self.expr(rhs)?;
+                self.emit("move r5 <- r1 if r0 != 0");
+                self.emit("pop r4");
+                self.binary(*op)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load `value` in r1. With [Isa::Base], values which do not fit in the
+    /// 16 bits of `loadimm` are built from their upper half, doubled 16 times,
+    /// and their lower half, instead of using `loadimm32`.
+    fn number(&mut self, value: i64) {
+        let value = value as u32 as i32;
+        if self.isa == Isa::Extended || i16::try_from(value).is_ok() {
+            self.emit(&format!("loadimm r1 <- #{value}"));
+            return;
+        }
+        let low = value as i16 as i32;
+        let high = value.wrapping_sub(low) >> 16;
+        self.emit(&format!("loadimm r1 <- #{high}"));
+        self.emit("loadimm r4 <- #0");
+        self.emit("loadimm r6 <- #16");
+        self.emit("loadimm r7 <- #1");
+        self.emit("while r6 != 0 {");
+        self.emit("    sub r5 <- r4 - r1");
+        self.emit("    sub r1 <- r1 - r5");
+        self.emit("    sub r6 <- r6 - r7");
+        self.emit("}");
+        self.emit(&format!("loadimm r5 <- #{low}"));
+        self.emit("sub r5 <- r4 - r5");
+        self.emit("sub r1 <- r1 - r5");
+    }
+
+    /// Compute `r4 op r5` in r1.
+    fn binary(&mut self, op: BinOp) -> Result<(), CompileError> {
+        match (op, self.isa) {
+            (BinOp::Sub, _) => self.emit("sub r1 <- r4 - r5"),
+            (BinOp::Add, _) => {
+                self.emit("loadimm r6 <- #0");
+                self.emit("sub r5 <- r6 - r5");
+                self.emit("sub r1 <- r4 - r5");
+            }
+            (BinOp::Mul, _) => {
+                self.emit("call rt.mul");
+                *self.uses_mul = true;
+            }
+            (BinOp::Eq | BinOp::Ne, Isa::Base) => {
+                let (equal, different) = if op == BinOp::Eq { (1, 0) } else { (0, 1) };
+                self.emit("sub r4 <- r4 - r5");
+                self.emit(&format!("loadimm r1 <- #{equal}"));
+                self.emit(&format!("loadimm r5 <- #{different}"));
+                self.emit("move r1 <- r5 if r4 != 0");
+            }
+            (BinOp::Eq, Isa::Extended) => self.emit("eq r1 <- r4, r5"),
+            (BinOp::Ne, Isa::Extended) => {
+                self.emit("eq r1 <- r4, r5");
+                self.emit("loadimm r5 <- #1");
+                self.emit("sub r1 <- r5 - r1");
+            }
+            (_, Isa::Base) => return self.err("ordered comparisons need the extended instruction set".to_string()),
+            (BinOp::Lt, Isa::Extended) => self.emit("slt r1 <- r4, r5"),
+            (BinOp::Le, Isa::Extended) => self.emit("sle r1 <- r4, r5"),
+            (BinOp::Gt, Isa::Extended) => self.emit("slt r1 <- r5, r4"),
+            (BinOp::Ge, Isa::Extended) => self.emit("sle r1 <- r5, r4"),
+        }
+        Ok(())
+    }
+}
+
+/// Compile a program into assembly source for [assemble](crate::assemble).
+///
+/// A program is a list of functions, `main` being called first:
+///
+/// ```text
+/// fn fact(n) {
+///     if (n == 0) {
+///         return 1;
+///     }
+///     return n * fact(n - 1);
+/// }
+///
+/// fn main() {
+///     var i = 1;
+///     while (i != 8) {
+///         print("fact(", i, ") = ", fact(i), "\n");
+///         i = i + 1;
+///     }
+/// }
+/// ```
+///
+/// Values are 32-bit integers, and expressions use `+`, `-`, `*`, unary `-`
+/// and `!`, and the comparisons `==` and `!=`, which give 0 or 1. The
+/// ordered comparisons `<`, `<=`, `>` and `>=` are only available with
+/// [Isa::Extended]. `print` writes numbers in decimal and string literals as
+/// they are.
+///
+/// Arguments and local variables live on the r2 stack. Multiplication adds
+/// the right operand as many times as the absolute value of the left one
+/// says, as in the listings.
+pub fn compile(source: &str, isa: Isa) -> Result<String, CompileError> {
+    let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+    let mut functions = vec![];
+    while parser.peek().is_some() {
+        functions.push(parser.function()?);
+    }
+
+    let mut arities = HashMap::new();
+    for function in &functions {
+        if arities.insert(function.name.clone(), function.params.len()).is_some() {
+            let message = format!("function `{}` defined twice", function.name);
+            return Err(CompileError { line: function.line, message });
+        }
+    }
+    if arities.get("main") != Some(&0) {
+        return Err(CompileError { line: parser.line(), message: "expected a `main` function without parameters".to_string() });
+    }
+
+    let mut out = String::from("    loadimm r2 <- #4096\n    call main\n    exit\n");
+    let mut strings = vec![];
+    let (mut uses_mul, mut uses_print) = (false, false);
+    for function in &functions {
+        let mut codegen = Codegen {
+            isa,
+            functions: &arities,
+            function,
+            scopes: vec![],
+            locals: 0,
+            loops: 0,
+            line: function.line,
+            out: String::new(),
+            strings: &mut strings,
+            uses_mul: &mut uses_mul,
+            uses_print: &mut uses_print,
+        };
+        codegen.block(&function.body)?;
+        let Codegen { locals, out: body, .. } = codegen;
+
+        writeln!(out, "{}:", function.name).unwrap();
+        out.push_str("    push r12\n    move r12 <- r2 if r0 != 0\n");
+        if locals > 0 {
+            writeln!(out, "    loadimm r6 <- #{}\n    sub r2 <- r2 - r6", 4 * locals).unwrap();
+        }
+        out.push_str(&body);
+        writeln!(out, "{}.end:", function.name).unwrap();
+        out.push_str("    move r2 <- r12 if r0 != 0\n    pop r12\n    ret\n");
+    }
+
+    if uses_mul {
+        // r1 <- r4 * r5, by adding r5 to r1 r4 times, both operands being
+        // negated when r4 is negative
+        out.push_str("rt.mul:\n    loadimm r1 <- #0\n    loadimm r6 <- #0\n    sub r7 <- r6 - r5\n    sub r8 <- r6 - r4\n");
+        if isa == Isa::Extended {
+            out.push_str("    move r7 <- r5 if r4 < 0\n    move r4 <- r8 if r4 < 0\n");
+        }
+        out.push_str("    loadimm r6 <- #1\n    while r4 != 0 {\n        sub r1 <- r1 - r7\n        sub r4 <- r4 - r6\n");
+        if isa == Isa::Base {
+            // Without ordered comparisons, r4 is negative when -r4 reaches 0
+            // first, after adding r5 -r4 times, so the sum is negated
+            out.push_str("        sub r8 <- r8 - r6\n        if r8 == 0 {\n            loadimm r4 <- #0\n");
+            out.push_str("            sub r1 <- r4 - r1\n        }\n");
+        }
+        out.push_str("    }\n    ret\n");
+    }
+    if uses_print {
+        // Print r5 bytes starting at r4
+        let load = if isa == Isa::Extended { "load8" } else { "load" };
+        out.push_str("rt.print:\n    loadimm r6 <- #1\n    loadimm r8 <- #-1\n    while r5 != 0 {\n");
+        writeln!(out, "        {load} r7 <- [r4]").unwrap();
+        out.push_str("        out r7\n        sub r4 <- r4 - r8\n        sub r5 <- r5 - r6\n    }\n    ret\n");
+    }
+
+    if !strings.is_empty() {
+        out.push_str(".data\n");
+    }
+    for (i, string) in strings.iter().enumerate() {
+        writeln!(out, "str.{}:", i + 1).unwrap();
+        let mut literal = String::new();
+        for &b in string {
+            match b {
+                b'"' | b'\\' => write!(literal, "\\x{b:02x}").unwrap(),
+                b' '..=b'~' => literal.push(b as char),
+                _ => write!(literal, "\\x{b:02x}").unwrap(),
+            }
+        }
+        if !literal.is_empty() {
+            writeln!(out, "    b\"{literal}\"").unwrap();
+        }
+    }
+    if isa == Isa::Base && !strings.is_empty() {
+        // `load` reads a whole word for the last characters
+        out.push_str("    [0, 0, 0]\n");
+    }
+    Ok(out)
+}
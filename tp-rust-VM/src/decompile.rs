@@ -0,0 +1,749 @@
+use crate::{BasicBlock, Cfg, Comparison, FloatComparison, Instruction, SymbolTable, Terminator, MEMORY_SIZE};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+const IP: u8 = 0;
+const SP: u8 = 2;
+const SCRATCH: u8 = 3;
+/// Virtual node following every exit of a function.
+const EXIT: usize = usize::MAX;
+
+/// Test on a register deciding a branch.
+#[derive(Debug, Clone)]
+struct Cond {
+    lhs: String,
+    op: &'static str,
+}
+
+impl Cond {
+    fn negate(self) -> Cond {
+        let op = match self.op {
+            "!=" => "==",
+            "==" => "!=",
+            "<" => ">=",
+            _ => "<",
+        };
+        Cond { lhs: self.lhs, op }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Assign(u8, String),
+    Line(String),
+    Push(u8),
+    Pop(u8),
+    Call(usize),
+    TailCall(usize),
+    Return,
+    If(Cond, Vec<Stmt>, Vec<Stmt>),
+    Loop(Vec<Stmt>),
+    While(Cond, Vec<Stmt>),
+    DoWhile(Vec<Stmt>, Cond),
+    Save(Vec<u8>, Vec<Stmt>),
+    Break,
+    Continue,
+    Goto(usize),
+    Label(usize),
+}
+
+impl Stmt {
+    /// Whether the control never goes past this statement.
+    fn is_jump(&self) -> bool {
+        matches!(self, Stmt::Break | Stmt::Continue | Stmt::Goto(_) | Stmt::Return | Stmt::TailCall(_))
+    }
+}
+
+/// Decompile a memory image into C-like pseudo-code.
+///
+/// Functions are recovered from the call idiom of the listings, which
+/// pushes the return address on the r2 stack, and from jumps to a lower
+/// address, which are tail calls. Returns popping the IP end them. Within a
+/// function, loops are recovered from the back edges of the control-flow
+/// graph, and `if`/`else` from the `move_if` on r0 and the relative
+/// branches. A push and a pop of the same register around a sequence of
+/// statements is shown as a `save` block.
+///
+/// Functions are named after `symbols` when given, the entry point being
+/// `main` otherwise, or `_start` when another function is named `main`.
+///
+/// Code which cannot be structured is shown with `goto`.
+///
+/// # Panics
+/// This function panics when `image` is larger than the machine memory.
+pub fn decompile(image: &[u8], entry: u32, symbols: Option<&SymbolTable>) -> String {
+    assert!(image.len() <= MEMORY_SIZE, "The memory is larger than the machine memory");
+    let mut mem = image.to_vec();
+    mem.resize(MEMORY_SIZE, 0);
+    let cfg = Cfg::build(&mem, entry as usize);
+
+    // Find the functions, until the tail calls do not reveal new ones
+    let mut entries = BTreeSet::from([entry as usize]);
+    let functions = loop {
+        let functions: Vec<Function> = entries.iter().map(|&start| Function::new(&cfg, start, &entries)).collect();
+        let found: BTreeSet<usize> = functions.iter().flat_map(|f| f.callees.iter().copied()).collect();
+        if found.is_subset(&entries) {
+            break functions;
+        }
+        entries.extend(found);
+    };
+
+    let symbol = |addr: usize| match symbols.and_then(|symbols| symbols.lookup(addr as u32)) {
+        Some((name, 0)) => Some(name.to_string()),
+        _ => None,
+    };
+    // The entry point is `main`, unless a function already has this name
+    let has_main = functions.iter().any(|f| symbol(f.start).is_some_and(|name| name == "main"));
+    let names = Names {
+        function: &|addr| match symbol(addr) {
+            Some(name) => name,
+            None if addr == entry as usize => if has_main { "_start" } else { "main" }.to_string(),
+            None => format!("f_{addr:04}"),
+        },
+        label: &|addr| symbol(addr).unwrap_or_else(|| format!("l{addr:04}")),
+    };
+    let mut out = String::new();
+    for function in &functions {
+        let mut structurer = Structurer::new(&cfg, function);
+        let mut body = vec![];
+        structurer.seq(function.start, None, &mut body);
+        let body = save_registers(simplify(remove_labels(body, &structurer.gotos)));
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        writeln!(out, "fn {}() {{", (names.function)(function.start)).unwrap();
+        render(&body, 1, &names, &mut out);
+        out.push_str("}\n");
+    }
+    out
+}
+
+/// Blocks of a function, reached from its start without following calls.
+struct Function {
+    start: usize,
+    blocks: BTreeSet<usize>,
+    /// Entries of the functions this one calls or jumps to.
+    callees: BTreeSet<usize>,
+    succs: BTreeMap<usize, Vec<usize>>,
+}
+
+impl Function {
+    fn new(cfg: &Cfg, start: usize, entries: &BTreeSet<usize>) -> Function {
+        let mut function = Function { start, blocks: BTreeSet::new(), callees: BTreeSet::new(), succs: BTreeMap::new() };
+        let mut work = vec![start];
+        while let Some(addr) = work.pop() {
+            let Some(block) = cfg.block(addr).filter(|_| function.blocks.insert(addr)) else {
+                continue;
+            };
+            let mut succs = vec![];
+            for next in edges(block) {
+                if function.is_tail_call(next, entries) {
+                    function.callees.insert(next);
+                } else {
+                    succs.push(next);
+                    work.push(next);
+                }
+            }
+            if let Terminator::Call { target, .. } = block.terminator {
+                function.callees.insert(target);
+            }
+            function.succs.insert(addr, succs);
+        }
+        function
+    }
+
+    /// Whether going to `addr` leaves the function: the entries of the other
+    /// functions, and the code located before this one.
+    fn is_tail_call(&self, addr: usize, entries: &BTreeSet<usize>) -> bool {
+        addr != self.start && (addr < self.start || entries.contains(&addr))
+    }
+
+    /// Successors within the function, or the virtual exit.
+    fn succs(&self, addr: usize) -> Vec<usize> {
+        match self.succs.get(&addr) {
+            Some(succs) if !succs.is_empty() => succs.clone(),
+            _ => vec![EXIT],
+        }
+    }
+}
+
+/// Blocks which may follow `block` in the same function.
+fn edges(block: &BasicBlock) -> Vec<usize> {
+    match block.terminator {
+        Terminator::Call { return_to, .. } => vec![return_to],
+        _ => block.successors().into_iter().map(|(addr, _)| addr).collect(),
+    }
+}
+
+/// Solve the dominator sets of the nodes of `order`, given the predecessors
+/// of every node. The first node is the root.
+fn dominators(order: &[usize], preds: &BTreeMap<usize, Vec<usize>>) -> BTreeMap<usize, BTreeSet<usize>> {
+    let all: BTreeSet<usize> = order.iter().copied().collect();
+    let mut doms: BTreeMap<usize, BTreeSet<usize>> = order.iter().map(|&n| (n, all.clone())).collect();
+    doms.insert(order[0], BTreeSet::from([order[0]]));
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &n in &order[1..] {
+            let mut set: Option<BTreeSet<usize>> = None;
+            for p in preds.get(&n).into_iter().flatten() {
+                let d = &doms[p];
+                set = Some(match set {
+                    None => d.clone(),
+                    Some(s) => s.intersection(d).copied().collect(),
+                });
+            }
+            let mut set = set.unwrap_or_default();
+            set.insert(n);
+            if set != doms[&n] {
+                doms.insert(n, set);
+                changed = true;
+            }
+        }
+    }
+    doms
+}
+
+struct LoopCtx {
+    header: usize,
+    body: BTreeSet<usize>,
+    follow: Option<usize>,
+}
+
+/// Turns the blocks of a function into structured statements.
+struct Structurer<'a> {
+    cfg: &'a Cfg,
+    function: &'a Function,
+    /// Loop headers, with the sources of their back edges.
+    headers: BTreeMap<usize, Vec<usize>>,
+    preds: BTreeMap<usize, Vec<usize>>,
+    postdoms: BTreeMap<usize, BTreeSet<usize>>,
+    loops: Vec<LoopCtx>,
+    emitted: BTreeSet<usize>,
+    gotos: BTreeSet<usize>,
+}
+
+impl<'a> Structurer<'a> {
+    fn new(cfg: &'a Cfg, function: &'a Function) -> Self {
+        let mut preds: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        let mut rpreds: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for &n in &function.blocks {
+            for s in function.succs(n) {
+                preds.entry(s).or_default().push(n);
+                rpreds.entry(n).or_default().push(s);
+            }
+        }
+        let mut order = vec![function.start];
+        order.extend(function.blocks.iter().filter(|&&n| n != function.start));
+        let doms = dominators(&order, &preds);
+        let mut rorder = vec![EXIT];
+        rorder.extend(function.blocks.iter().rev());
+        let postdoms = dominators(&rorder, &rpreds);
+
+        let mut headers: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for &n in &function.blocks {
+            for s in function.succs(n) {
+                if doms[&n].contains(&s) {
+                    headers.entry(s).or_default().push(n);
+                }
+            }
+        }
+        Structurer {
+            cfg,
+            function,
+            headers,
+            preds,
+            postdoms,
+            loops: vec![],
+            emitted: BTreeSet::new(),
+            gotos: BTreeSet::new(),
+        }
+    }
+
+    /// Emit the statements from `start` until reaching `stop`, or a jump out
+    /// of the current construct.
+    fn seq(&mut self, start: usize, stop: Option<usize>, out: &mut Vec<Stmt>) {
+        let mut next = Some(start);
+        while let Some(addr) = next {
+            if Some(addr) == stop {
+                return;
+            }
+            if let Some(jump) = self.jump(addr) {
+                out.push(jump);
+                return;
+            }
+            if self.emitted.contains(&addr) {
+                self.gotos.insert(addr);
+                out.push(Stmt::Goto(addr));
+                return;
+            }
+            next = if self.headers.contains_key(&addr) { self.emit_loop(addr, out) } else { self.emit_block(addr, out) };
+        }
+    }
+
+    /// Statement going to `addr` when it leaves the current construct.
+    fn jump(&mut self, addr: usize) -> Option<Stmt> {
+        if addr == EXIT {
+            return Some(Stmt::Return);
+        }
+        if !self.function.blocks.contains(&addr) {
+            return Some(Stmt::TailCall(addr));
+        }
+        for (depth, ctx) in self.loops.iter().rev().enumerate() {
+            if addr == ctx.header || Some(addr) == ctx.follow {
+                if depth > 0 {
+                    self.gotos.insert(addr);
+                    return Some(Stmt::Goto(addr));
+                }
+                return Some(if addr == ctx.header { Stmt::Continue } else { Stmt::Break });
+            }
+        }
+        None
+    }
+
+    fn emit_loop(&mut self, header: usize, out: &mut Vec<Stmt>) -> Option<usize> {
+        // Natural loop of the back edges
+        let mut body = BTreeSet::from([header]);
+        let mut work = self.headers[&header].clone();
+        while let Some(n) = work.pop() {
+            if body.insert(n) {
+                work.extend(self.preds.get(&n).into_iter().flatten().copied());
+            }
+        }
+        let exits: BTreeSet<usize> =
+            body.iter().flat_map(|&n| self.function.succs(n)).filter(|s| !body.contains(s) && *s != EXIT).collect();
+        let follow = self.ipostdom(header).filter(|d| exits.contains(d)).or_else(|| exits.first().copied());
+
+        self.loops.push(LoopCtx { header, body, follow });
+        let mut stmts = vec![];
+        if let Some(next) = self.emit_block(header, &mut stmts) {
+            self.seq(next, None, &mut stmts);
+        }
+        self.loops.pop();
+        out.push(Stmt::Loop(stmts));
+        follow
+    }
+
+    /// Immediate post-dominator of a block, if any within the function.
+    fn ipostdom(&self, addr: usize) -> Option<usize> {
+        let strict: Vec<usize> = self.postdoms[&addr].iter().copied().filter(|&d| d != addr).collect();
+        let idom = strict.iter().copied().find(|d| strict.iter().all(|e| self.postdoms[d].contains(e)))?;
+        (idom != EXIT).then_some(idom)
+    }
+
+    /// Emit a block, returning where the sequence goes on.
+    fn emit_block(&mut self, addr: usize, out: &mut Vec<Stmt>) -> Option<usize> {
+        self.emitted.insert(addr);
+        out.push(Stmt::Label(addr));
+        let block = self.cfg.block(addr).unwrap();
+        let instrs: Vec<Instruction> = block.instructions.iter().map(|&(_, instr)| instr).collect();
+        match block.terminator {
+            Terminator::Fallthrough(next) => {
+                lift(&instrs, out);
+                Some(next)
+            }
+            Terminator::Jump(target) => {
+                lift(without_target(&instrs), out);
+                Some(target)
+            }
+            Terminator::Call { target, return_to } => {
+                let instrs = without_target(&instrs);
+                match instrs {
+                    [rest @ .., Instruction::LoadImm { dst: SCRATCH, imm: 4 }, Instruction::Sub { dst: SP, lhs: SP, rhs: SCRATCH }, Instruction::LoadImm { dst: SCRATCH, .. } | Instruction::LoadImm32 { dst: SCRATCH, .. }, Instruction::Store { addr: SP, src: SCRATCH }] => {
+                        lift(rest, out)
+                    }
+                    _ => lift(instrs, out),
+                }
+                out.push(Stmt::Call(target));
+                Some(return_to)
+            }
+            Terminator::Branch { taken, fallthrough } => {
+                let (branch, rest) = instrs.split_last().unwrap();
+                let cond = match *branch {
+                    Instruction::MoveIf { cond, .. } => Cond { lhs: reg(cond), op: "!=" },
+                    Instruction::MoveIfZero { cond, .. } => Cond { lhs: reg(cond), op: "==" },
+                    Instruction::MoveIfNeg { cond, .. } => Cond { lhs: reg(cond), op: "<" },
+                    Instruction::Branch { cond, src, .. } => {
+                        Cond { lhs: reg(src), op: ["==", "!=", "<"][cond as usize] }
+                    }
+                    _ => unreachable!("not a branch"),
+                };
+                let rest = match *branch {
+                    Instruction::MoveIf { src, .. } | Instruction::MoveIfZero { src, .. } | Instruction::MoveIfNeg { src, .. } => {
+                        without_load(rest, src)
+                    }
+                    _ => rest.to_vec(),
+                };
+                lift(&rest, out);
+
+                let join = self.ipostdom(addr).filter(|j| match self.loops.last() {
+                    Some(ctx) => ctx.body.contains(j),
+                    None => true,
+                });
+                let mut then = vec![];
+                self.seq(taken, join, &mut then);
+                let mut otherwise = vec![];
+                self.seq(fallthrough, join, &mut otherwise);
+                out.push(Stmt::If(cond, then, otherwise));
+                join
+            }
+            Terminator::Return => {
+                match instrs[..] {
+                    [ref rest @ .., Instruction::LoadImm { dst: SCRATCH, imm: -4 }, Instruction::Sub { dst: SP, lhs: SP, rhs: SCRATCH }, Instruction::LoadImm { dst: SCRATCH, imm: 4 }, Instruction::Sub { dst: SCRATCH, lhs: SP, rhs: SCRATCH }, Instruction::Load { dst: IP, addr: SCRATCH }] => {
+                        lift(rest, out);
+                        out.push(Stmt::Return);
+                    }
+                    _ => lift(&instrs, out),
+                }
+                None
+            }
+            Terminator::Indirect { fallthrough } => {
+                lift(&instrs, out);
+                fallthrough
+            }
+            Terminator::Exit | Terminator::Invalid => {
+                lift(&instrs, out);
+                if block.terminator == Terminator::Invalid {
+                    out.push(Stmt::Line(format!("invalid({:04})", block.end())));
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Instructions of a block ending with a jump to a known address, without
+/// the jump and the load of its target.
+fn without_target(instrs: &[Instruction]) -> &[Instruction] {
+    let (jump, rest) = instrs.split_last().unwrap();
+    match *jump {
+        // `move r0 <- rX if r0 != 0` along with the load of `rX`
+        Instruction::MoveIf { src, .. } if src != IP => match rest.split_last() {
+            Some((load, before)) if load.constant_load().is_some_and(|(dst, _)| dst == src) => before,
+            _ => rest,
+        },
+        _ => rest,
+    }
+}
+
+/// `instrs` without the last write to `reg`, when it loads a constant.
+fn without_load(instrs: &[Instruction], reg: u8) -> Vec<Instruction> {
+    let mut instrs = instrs.to_vec();
+    if let Some(idx) = instrs.iter().rposition(|instr| instr.written_reg() == Some(reg)) {
+        if instrs[idx].constant_load().is_some() {
+            instrs.remove(idx);
+        }
+    }
+    instrs
+}
+
+fn reg(reg: u8) -> String {
+    format!("r{reg}")
+}
+
+/// Turn straight-line instructions into statements, recognizing the stack
+/// idioms and the additions of constants.
+fn lift(instrs: &[Instruction], out: &mut Vec<Stmt>) {
+    let mut rest = instrs;
+    while !rest.is_empty() {
+        let (stmt, used) = match *rest {
+            [Instruction::LoadImm { dst: SCRATCH, imm: 4 }, Instruction::Sub { dst: SP, lhs: SP, rhs: SCRATCH }, Instruction::Store { addr: SP, src }, ..]
+                if src != SCRATCH =>
+            {
+                (Some(Stmt::Push(src)), 3)
+            }
+            [Instruction::LoadImm { dst: SCRATCH, imm: -4 }, Instruction::Sub { dst: SP, lhs: SP, rhs: SCRATCH }, Instruction::LoadImm { dst: SCRATCH, imm: 4 }, Instruction::Sub { dst: SCRATCH, lhs: SP, rhs: SCRATCH }, Instruction::Load { dst, addr: SCRATCH }, ..] => {
+                (Some(Stmt::Pop(dst)), 5)
+            }
+            [Instruction::LoadImm { dst: tmp, imm }, Instruction::Sub { dst, lhs, rhs }, ..]
+                if rhs == tmp && lhs != tmp && (tmp == dst || tmp == SCRATCH) =>
+            {
+                let imm = imm as i32;
+                let stmt = match (dst == lhs, imm) {
+                    (true, 0) => None,
+                    (true, imm) if imm < 0 => Some(Stmt::Line(format!("r{dst} += {}", -imm))),
+                    (true, imm) => Some(Stmt::Line(format!("r{dst} -= {imm}"))),
+                    (false, 0) => Some(Stmt::Assign(dst, reg(lhs))),
+                    (false, imm) if imm < 0 => Some(Stmt::Assign(dst, format!("r{lhs} + {}", -imm))),
+                    (false, imm) => Some(Stmt::Assign(dst, format!("r{lhs} - {imm}"))),
+                };
+                (stmt, 2)
+            }
+            [instr, ..] => (statement(instr), 1),
+            [] => unreachable!(),
+        };
+        out.extend(stmt);
+        rest = &rest[used..];
+    }
+}
+
+/// Statement for a single instruction.
+fn statement(instr: Instruction) -> Option<Stmt> {
+    let moved = |dst: u8, src: u8, cond: u8, op: &str| {
+        if dst == src {
+            return None;
+        }
+        let assign = if dst == IP { format!("goto *r{src}") } else { format!("r{dst} = r{src}") };
+        Some(Stmt::Line(format!("if (r{cond} {op} 0) {assign}")))
+    };
+    let stmt = match instr {
+        Instruction::MoveIf { dst, src, cond: IP } if dst != src => Stmt::Assign(dst, reg(src)),
+        Instruction::MoveIf { dst, src, cond } => return moved(dst, src, cond, "!="),
+        Instruction::MoveIfZero { dst, src, cond } => return moved(dst, src, cond, "=="),
+        Instruction::MoveIfNeg { dst, src, cond } => return moved(dst, src, cond, "<"),
+        Instruction::Store { addr, src } => Stmt::Line(format!("mem32[r{addr}] = r{src}")),
+        Instruction::Store8 { addr, src } => Stmt::Line(format!("mem8[r{addr}] = r{src}")),
+        Instruction::Store16 { addr, src } => Stmt::Line(format!("mem16[r{addr}] = r{src}")),
+        Instruction::Load { dst, addr } => Stmt::Assign(dst, format!("mem32[r{addr}]")),
+        Instruction::Load8 { dst, addr, signed } => {
+            Stmt::Assign(dst, format!("{}mem8[r{addr}]", if signed { "(i8)" } else { "" }))
+        }
+        Instruction::Load16 { dst, addr, signed } => {
+            Stmt::Assign(dst, format!("{}mem16[r{addr}]", if signed { "(i16)" } else { "" }))
+        }
+        Instruction::LoadImm { .. } | Instruction::LoadImm32 { .. } => {
+            let (dst, value) = instr.constant_load().unwrap();
+            Stmt::Assign(dst, (value as i32).to_string())
+        }
+        Instruction::LoadHi { dst, imm } => Stmt::Assign(dst, format!("{:#x} | (r{dst} & 0xffff)", (imm as u32) << 16)),
+        Instruction::Sub { dst, lhs, rhs } if lhs == rhs => Stmt::Assign(dst, "0".to_string()),
+        Instruction::Sub { dst, lhs, rhs } if dst == lhs && dst != IP => Stmt::Line(format!("r{dst} -= r{rhs}")),
+        Instruction::Sub { dst, lhs, rhs } => Stmt::Assign(dst, format!("r{lhs} - r{rhs}")),
+        Instruction::Compare { op, dst, lhs, rhs } => {
+            let expr = match op {
+                Comparison::Slt => format!("r{lhs} < r{rhs}"),
+                Comparison::Sle => format!("r{lhs} <= r{rhs}"),
+                Comparison::Ult => format!("(unsigned)r{lhs} < (unsigned)r{rhs}"),
+                Comparison::Ule => format!("(unsigned)r{lhs} <= (unsigned)r{rhs}"),
+                Comparison::Eq => format!("r{lhs} == r{rhs}"),
+            };
+            Stmt::Assign(dst, expr)
+        }
+        Instruction::Out { src } => Stmt::Line(format!("out(r{src})")),
+        Instruction::OutNumber { src } => Stmt::Line(format!("out_number(r{src})")),
+        Instruction::In { dst } => Stmt::Assign(dst, "in()".to_string()),
+        // float() reads the bits of a register as a float, bits() does the
+        // opposite
+        Instruction::FloatArith { op, dst, lhs, rhs } => {
+            Stmt::Assign(dst, format!("bits(float(r{lhs}) {} float(r{rhs}))", op.symbol()))
+        }
+        Instruction::IntToFloat { dst, src } => Stmt::Assign(dst, format!("bits((float)r{src})")),
+        Instruction::FloatToInt { dst, src } => Stmt::Assign(dst, format!("(int)float(r{src})")),
+        Instruction::FloatCompare { op, dst, lhs, rhs } => {
+            let symbol = match op {
+                FloatComparison::Lt => "<",
+                FloatComparison::Le => "<=",
+                FloatComparison::Eq => "==",
+            };
+            Stmt::Assign(dst, format!("float(r{lhs}) {symbol} float(r{rhs})"))
+        }
+        Instruction::OutFloat { src } => Stmt::Line(format!("out_float(r{src})")),
+        Instruction::Div { dst, lhs, rhs } if dst == lhs && dst != IP => Stmt::Line(format!("r{dst} /= r{rhs}")),
+        Instruction::Div { dst, lhs, rhs } => Stmt::Assign(dst, format!("r{lhs} / r{rhs}")),
+        Instruction::ReturnFromTrap => Stmt::Line("return_from_trap()".to_string()),
+        Instruction::SetPageTable { src } => Stmt::Line(format!("set_page_table(r{src})")),
+        Instruction::Exit => Stmt::Line("exit()".to_string()),
+        Instruction::Jump { offset } => Stmt::Line(format!("goto *(r0 + {offset})")),
+        Instruction::Branch { .. } => Stmt::Line(format!("{instr}")),
+    };
+    Some(stmt)
+}
+
+/// Remove the labels which are not the target of a `goto`.
+fn remove_labels(stmts: Vec<Stmt>, gotos: &BTreeSet<usize>) -> Vec<Stmt> {
+    map_bodies(stmts, &|body| remove_labels(body, gotos))
+        .into_iter()
+        .filter(|stmt| !matches!(stmt, Stmt::Label(addr) if !gotos.contains(addr)))
+        .collect()
+}
+
+/// Apply `f` to the nested statement lists.
+fn map_bodies(stmts: Vec<Stmt>, f: &dyn Fn(Vec<Stmt>) -> Vec<Stmt>) -> Vec<Stmt> {
+    stmts
+        .into_iter()
+        .map(|stmt| match stmt {
+            Stmt::If(cond, then, otherwise) => Stmt::If(cond, f(then), f(otherwise)),
+            Stmt::Loop(body) => Stmt::Loop(f(body)),
+            Stmt::While(cond, body) => Stmt::While(cond, f(body)),
+            Stmt::DoWhile(body, cond) => Stmt::DoWhile(f(body), cond),
+            Stmt::Save(regs, body) => Stmt::Save(regs, f(body)),
+            stmt => stmt,
+        })
+        .collect()
+}
+
+/// Whether a loop body continues the loop itself, outside of nested loops.
+fn continues(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Continue => true,
+        Stmt::If(_, then, otherwise) => continues(then) || continues(otherwise),
+        Stmt::Save(_, body) => continues(body),
+        _ => false,
+    })
+}
+
+/// Rewrite the loops and conditionals into their most readable form.
+fn simplify(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    let mut out = vec![];
+    for stmt in map_bodies(stmts, &simplify) {
+        match stmt {
+            // if (c) {} else { E }  =>  if (!c) { E }
+            Stmt::If(cond, then, otherwise) if then.is_empty() => {
+                if !otherwise.is_empty() {
+                    out.push(Stmt::If(cond.negate(), otherwise, vec![]));
+                }
+            }
+            // if (c) { ...; jump } else { E }  =>  if (c) { ...; jump } E
+            Stmt::If(cond, then, otherwise) if then.last().is_some_and(Stmt::is_jump) => {
+                out.push(Stmt::If(cond, then, vec![]));
+                out.extend(otherwise);
+            }
+            Stmt::Loop(mut body) => {
+                if matches!(body.last(), Some(Stmt::Continue)) {
+                    body.pop();
+                }
+                out.push(structure_loop(body));
+            }
+            stmt => out.push(stmt),
+        }
+    }
+    out
+}
+
+/// Turn an infinite loop into a `while` or a `do`/`while` when it starts or
+/// ends with a conditional `break`.
+fn structure_loop(mut body: Vec<Stmt>) -> Stmt {
+    let exit_test = |stmt: &Stmt| match stmt {
+        Stmt::If(cond, then, otherwise) if matches!(then[..], [Stmt::Break]) && otherwise.is_empty() => Some(cond.clone()),
+        _ => None,
+    };
+    // if (c) { T; continue; } break;  =>  if (!c) break; T
+    if let [.., Stmt::If(_, then, otherwise), Stmt::Break] = &body[..] {
+        if otherwise.is_empty() && matches!(then.last(), Some(Stmt::Continue)) {
+            body.pop();
+            let Some(Stmt::If(cond, mut then, _)) = body.pop() else { unreachable!() };
+            then.pop();
+            body.push(Stmt::If(cond.negate(), vec![Stmt::Break], vec![]));
+            body.extend(then);
+        }
+    }
+    if let Some(cond) = body.first().and_then(exit_test) {
+        body.remove(0);
+        return Stmt::While(cond.negate(), body);
+    }
+    // Fold the computation of the tested register into the condition
+    if let [Stmt::Assign(dst, expr), test, ..] = &body[..] {
+        if let Some(cond) = exit_test(test).filter(|cond| cond.lhs == reg(*dst)) {
+            let cond = Cond { lhs: format!("(r{dst} = {expr})"), op: cond.op };
+            body.drain(..2);
+            return Stmt::While(cond.negate(), body);
+        }
+    }
+    if let Some(cond) = body.last().and_then(exit_test) {
+        if !continues(&body) {
+            body.pop();
+            return Stmt::DoWhile(body, cond.negate());
+        }
+    }
+    Stmt::Loop(body)
+}
+
+/// Turn the pushes and pops of the same register around a sequence of
+/// statements into `save` blocks.
+fn save_registers(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    let stmts = map_bodies(stmts, &save_registers);
+    let mut out = vec![];
+    let mut i = 0;
+    while i < stmts.len() {
+        let Stmt::Push(reg) = stmts[i] else {
+            out.push(stmts[i].clone());
+            i += 1;
+            continue;
+        };
+        // Find the matching pop, the pushes and pops in between being balanced
+        let mut pushed = vec![];
+        let mut end = None;
+        for (j, stmt) in stmts.iter().enumerate().skip(i + 1) {
+            match *stmt {
+                Stmt::Push(other) => pushed.push(other),
+                Stmt::Pop(other) if pushed.pop().is_none() => {
+                    end = (other == reg).then_some(j);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        match end {
+            Some(end) => {
+                let body = save_registers(stmts[i + 1..end].to_vec());
+                out.push(match &body[..] {
+                    [Stmt::Save(regs, inner)] => Stmt::Save([vec![reg], regs.clone()].concat(), inner.clone()),
+                    _ => Stmt::Save(vec![reg], body),
+                });
+                i = end + 1;
+            }
+            None => {
+                out.push(Stmt::Push(reg));
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Names of the functions and of the targets of the `goto`.
+struct Names<'a> {
+    function: &'a dyn Fn(usize) -> String,
+    label: &'a dyn Fn(usize) -> String,
+}
+
+fn render(stmts: &[Stmt], depth: usize, names: &Names, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    let block = |header: String, body: &[Stmt], out: &mut String| {
+        writeln!(out, "{indent}{header} {{").unwrap();
+        render(body, depth + 1, names, out);
+    };
+    for stmt in stmts {
+        match stmt {
+            Stmt::Assign(IP, expr) => writeln!(out, "{indent}goto *{expr};").unwrap(),
+            Stmt::Assign(dst, expr) => writeln!(out, "{indent}r{dst} = {expr};").unwrap(),
+            Stmt::Line(line) => writeln!(out, "{indent}{line};").unwrap(),
+            Stmt::Push(reg) => writeln!(out, "{indent}push(r{reg});").unwrap(),
+            Stmt::Pop(IP) => writeln!(out, "{indent}goto *pop();").unwrap(),
+            Stmt::Pop(reg) => writeln!(out, "{indent}r{reg} = pop();").unwrap(),
+            Stmt::Call(target) => writeln!(out, "{indent}{}();", (names.function)(*target)).unwrap(),
+            Stmt::TailCall(target) => writeln!(out, "{indent}return {}();", (names.function)(*target)).unwrap(),
+            Stmt::Return => writeln!(out, "{indent}return;").unwrap(),
+            Stmt::Break => writeln!(out, "{indent}break;").unwrap(),
+            Stmt::Continue => writeln!(out, "{indent}continue;").unwrap(),
+            Stmt::Goto(addr) => writeln!(out, "{indent}goto {};", (names.label)(*addr)).unwrap(),
+            Stmt::Label(addr) => writeln!(out, "{}{}:", "    ".repeat(depth - 1), (names.label)(*addr)).unwrap(),
+            Stmt::If(cond, then, otherwise) => {
+                block(format!("if ({} {} 0)", cond.lhs, cond.op), then, out);
+                if otherwise.is_empty() {
+                    writeln!(out, "{indent}}}").unwrap();
+                } else {
+                    block("} else".to_string(), otherwise, out);
+                    writeln!(out, "{indent}}}").unwrap();
+                }
+            }
+            Stmt::Loop(body) => {
+                block("while (true)".to_string(), body, out);
+                writeln!(out, "{indent}}}").unwrap();
+            }
+            Stmt::While(cond, body) => {
+                block(format!("while ({} {} 0)", cond.lhs, cond.op), body, out);
+                writeln!(out, "{indent}}}").unwrap();
+            }
+            Stmt::DoWhile(body, cond) => {
+                block("do".to_string(), body, out);
+                writeln!(out, "{indent}}} while ({} {} 0);", cond.lhs, cond.op).unwrap();
+            }
+            Stmt::Save(regs, body) => {
+                let regs: Vec<String> = regs.iter().map(|&r| reg(r)).collect();
+                block(format!("save ({})", regs.join(", ")), body, out);
+                writeln!(out, "{indent}}}").unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{Machine, MachineError, SliceOutcome, MEMORY_SIZE, NREGS};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+/// Number of instructions run between two checks for commands.
+const SLICE: usize = 1024;
+
+/// State of a machine run by a [MachineHandle].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    Paused,
+    /// The program ran an `exit` instruction.
+    Exited,
+    /// The program stopped on an error.
+    Faulted(MachineError),
+}
+
+/// Error returned by the requests of a [MachineHandle].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    /// The request needs the machine to be paused, exited or faulted.
+    NotPaused,
+    /// The thread running the machine panicked.
+    Disconnected,
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleError::NotPaused => write!(f, "the machine is running"),
+            HandleError::Disconnected => write!(f, "the machine thread panicked"),
+        }
+    }
+}
+
+/// State of the thread running the machine.
+struct Worker {
+    machine: Machine,
+    status: Status,
+    output: ChannelWriter,
+    /// Requests waiting for the machine to stop running.
+    waiters: Vec<Sender<Status>>,
+    stopped: bool,
+}
+
+type Command = Box<dyn FnOnce(&mut Worker) + Send>;
+
+/// Output sent over a channel, one message per write.
+struct ChannelWriter(Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The output is discarded once the handle is dropped
+        let _ = self.0.send(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Worker {
+    fn set_status(&mut self, status: Status) {
+        self.status = status;
+        if status != Status::Running {
+            for waiter in self.waiters.drain(..) {
+                let _ = waiter.send(status);
+            }
+        }
+    }
+
+    /// Run at most `steps` instructions, reading the end of the input, and
+    /// update the status.
+    fn run_slice(&mut self, steps: usize) -> Status {
+        match self.machine.run_slice(steps, &mut io::empty(), &mut self.output) {
+            Ok(SliceOutcome::Yielded) => {}
+            Ok(SliceOutcome::Exited) => self.set_status(Status::Exited),
+            Err(e) => self.set_status(Status::Faulted(e)),
+        }
+        self.status
+    }
+
+    fn run(mut self, commands: Receiver<Command>) -> Machine {
+        while !self.stopped {
+            let command = if self.status == Status::Running {
+                match commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => break,
+                }
+            };
+            match command {
+                Some(command) => command(&mut self),
+                None => {
+                    self.run_slice(SLICE);
+                }
+            }
+        }
+        self.machine
+    }
+}
+
+/// A machine running on its own thread, controlled without blocking the
+/// caller for longer than a slice of instructions.
+///
+/// Requests are served between two instructions. Registers and memory can
+/// be read whenever the machine is not running, that is once paused or
+/// after the program ended. Output instructions send what they print over
+/// the [output](MachineHandle::output) channel, and input instructions read
+/// the end of the input. Dropping the handle stops the thread.
+pub struct MachineHandle {
+    commands: Sender<Command>,
+    output: Receiver<Vec<u8>>,
+    thread: JoinHandle<Machine>,
+}
+
+impl MachineHandle {
+    /// Start running `machine` on a new thread.
+    pub fn spawn(machine: Machine) -> MachineHandle {
+        let (commands, receiver) = mpsc::channel::<Command>();
+        let (sender, output) = mpsc::channel();
+        let worker = Worker {
+            machine,
+            status: Status::Running,
+            output: ChannelWriter(sender),
+            waiters: vec![],
+            stopped: false,
+        };
+        let thread = thread::spawn(move || worker.run(receiver));
+        MachineHandle { commands, output, thread }
+    }
+
+    /// Run `request` on the machine thread and wait for its result.
+    fn request<T: Send + 'static>(&self, request: impl FnOnce(&mut Worker) -> T + Send + 'static) -> Result<T, HandleError> {
+        let (sender, receiver) = mpsc::channel();
+        let command: Command = Box::new(move |worker| {
+            let _ = sender.send(request(worker));
+        });
+        self.commands.send(command).map_err(|_| HandleError::Disconnected)?;
+        receiver.recv().map_err(|_| HandleError::Disconnected)
+    }
+
+    /// Current status of the machine.
+    pub fn status(&self) -> Result<Status, HandleError> {
+        self.request(|worker| worker.status)
+    }
+
+    /// Pause a running machine, returning its new status.
+    pub fn pause(&self) -> Result<Status, HandleError> {
+        self.request(|worker| {
+            if worker.status == Status::Running {
+                worker.set_status(Status::Paused);
+            }
+            worker.status
+        })
+    }
+
+    /// Resume a paused machine, returning its new status.
+    pub fn resume(&self) -> Result<Status, HandleError> {
+        self.request(|worker| {
+            if worker.status == Status::Paused {
+                worker.set_status(Status::Running);
+            }
+            worker.status
+        })
+    }
+
+    /// Run a single instruction of a paused machine, returning its new
+    /// status. This does nothing once the program ended.
+    pub fn step(&self) -> Result<Status, HandleError> {
+        self.request(|worker| match worker.status {
+            Status::Running => Err(HandleError::NotPaused),
+            Status::Paused => Ok(worker.run_slice(1)),
+            status => Ok(status),
+        })?
+    }
+
+    /// Wait until the machine stops running, because it is paused or the
+    /// program ended, and return its status.
+    pub fn wait(&self) -> Result<Status, HandleError> {
+        let (sender, receiver) = mpsc::channel();
+        self.request(move |worker| match worker.status {
+            Status::Running => worker.waiters.push(sender),
+            status => {
+                let _ = sender.send(status);
+            }
+        })?;
+        receiver.recv().map_err(|_| HandleError::Disconnected)
+    }
+
+    /// Registers of a machine which is not running.
+    pub fn regs(&self) -> Result<[u32; NREGS], HandleError> {
+        self.inspect(|machine| machine.regs().try_into().unwrap())
+    }
+
+    /// Memory of a machine which is not running.
+    pub fn memory(&self) -> Result<Box<[u8; MEMORY_SIZE]>, HandleError> {
+        self.inspect(|machine| Box::new(machine.memory().try_into().unwrap()))
+    }
+
+    fn inspect<T: Send + 'static>(&self, read: impl FnOnce(&Machine) -> T + Send + 'static) -> Result<T, HandleError> {
+        self.request(|worker| match worker.status {
+            Status::Running => Err(HandleError::NotPaused),
+            _ => Ok(read(&worker.machine)),
+        })?
+    }
+
+    /// Channel receiving what the program prints, in order.
+    pub fn output(&self) -> &Receiver<Vec<u8>> {
+        &self.output
+    }
+
+    /// Stop the thread and return the machine in its current state. Output
+    /// not yet received is lost.
+    pub fn stop(self) -> Result<Machine, HandleError> {
+        let _ = self.request(|worker| worker.stopped = true);
+        self.thread.join().map_err(|_| HandleError::Disconnected)
+    }
+}
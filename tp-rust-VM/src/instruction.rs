@@ -0,0 +1,499 @@
+use crate::MachineError;
+use core::fmt;
+
+/// A decoded machine instruction. Register numbers are kept as they appear
+/// in the encoding and are only checked by the machine when executing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `move rA <- rB if rC != 0`
+    MoveIf { dst: u8, src: u8, cond: u8 },
+    /// `store [rA] <- rB`
+    Store { addr: u8, src: u8 },
+    /// `load rA <- [rB]`
+    Load { dst: u8, addr: u8 },
+    /// `loadimm rA <- #imm`
+    LoadImm { dst: u8, imm: i16 },
+    /// `sub rA <- rB - rC`
+    Sub { dst: u8, lhs: u8, rhs: u8 },
+    /// `out rA`
+    Out { src: u8 },
+    /// `exit`
+    Exit,
+    /// `out_number rA`
+    OutNumber { src: u8 },
+    /// `loadimm32 rA <- #imm`
+    LoadImm32 { dst: u8, imm: u32 },
+    /// `loadhi rA <- #imm`, which replaces the upper half of `rA`
+    LoadHi { dst: u8, imm: u16 },
+    /// `load8 rA <- [rB]`, or `load8s` when sign-extending
+    Load8 { dst: u8, addr: u8, signed: bool },
+    /// `load16 rA <- [rB]`, or `load16s` when sign-extending
+    Load16 { dst: u8, addr: u8, signed: bool },
+    /// `store8 [rA] <- rB`
+    Store8 { addr: u8, src: u8 },
+    /// `store16 [rA] <- rB`
+    Store16 { addr: u8, src: u8 },
+    /// `jmp #offset`, the offset being relative to the next instruction
+    Jump { offset: i16 },
+    /// `bz rA, #offset`, `bnz rA, #offset` or `bneg rA, #offset`
+    Branch { cond: Condition, src: u8, offset: i16 },
+    /// `slt rA <- rB, rC` and the other comparisons, setting `rA` to 1 when
+    /// the comparison holds and to 0 otherwise
+    Compare { op: Comparison, dst: u8, lhs: u8, rhs: u8 },
+    /// `move rA <- rB if rC == 0`
+    MoveIfZero { dst: u8, src: u8, cond: u8 },
+    /// `move rA <- rB if rC < 0`
+    MoveIfNeg { dst: u8, src: u8, cond: u8 },
+    /// `in rA`, which reads a byte or -1 at the end of the input
+    In { dst: u8 },
+    /// `fadd rA <- rB + rC` and the other arithmetic operations on the
+    /// registers read as IEEE-754 single-precision floats
+    FloatArith { op: FloatOp, dst: u8, lhs: u8, rhs: u8 },
+    /// `itof rA <- rB`, converting a signed integer to the nearest float
+    IntToFloat { dst: u8, src: u8 },
+    /// `ftoi rA <- rB`, truncating a float toward zero to a signed integer.
+    /// Out of range values saturate and NaN becomes 0.
+    FloatToInt { dst: u8, src: u8 },
+    /// `flt rA <- rB, rC` and the other float comparisons, which do not hold
+    /// when an operand is NaN
+    FloatCompare { op: FloatComparison, dst: u8, lhs: u8, rhs: u8 },
+    /// `out_float rA`
+    OutFloat { src: u8 },
+    /// `div rA <- rB / rC`, a signed division truncating toward zero
+    Div { dst: u8, lhs: u8, rhs: u8 },
+    /// `rti`, which pops the cause and the IP pushed by a trap and resumes
+    /// at that IP
+    ReturnFromTrap,
+    /// `setptb rA`, setting the physical address of the page table, 0
+    /// disabling the translation
+    SetPageTable { src: u8 },
+}
+
+/// Arithmetic operation computed by a float instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl FloatOp {
+    pub const ALL: [FloatOp; 4] = [FloatOp::Add, FloatOp::Sub, FloatOp::Mul, FloatOp::Div];
+
+    /// Result of the operation on the bits of two floats, as the bits of a
+    /// float.
+    pub fn apply(self, lhs: u32, rhs: u32) -> u32 {
+        let (lhs, rhs) = (f32::from_bits(lhs), f32::from_bits(rhs));
+        let result = match self {
+            FloatOp::Add => lhs + rhs,
+            FloatOp::Sub => lhs - rhs,
+            FloatOp::Mul => lhs * rhs,
+            FloatOp::Div => lhs / rhs,
+        };
+        result.to_bits()
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            FloatOp::Add => "fadd",
+            FloatOp::Sub => "fsub",
+            FloatOp::Mul => "fmul",
+            FloatOp::Div => "fdiv",
+        }
+    }
+
+    /// Operator written between the operands.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            FloatOp::Add => "+",
+            FloatOp::Sub => "-",
+            FloatOp::Mul => "*",
+            FloatOp::Div => "/",
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn opcode(self) -> u8 {
+        29 + FloatOp::ALL.iter().position(|&op| op == self).unwrap() as u8
+    }
+}
+
+/// Comparison computed by a float compare instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatComparison {
+    Lt,
+    Le,
+    Eq,
+}
+
+impl FloatComparison {
+    pub const ALL: [FloatComparison; 3] = [FloatComparison::Lt, FloatComparison::Le, FloatComparison::Eq];
+
+    /// Whether the comparison holds between the floats whose bits are `lhs`
+    /// and `rhs`.
+    pub fn holds(self, lhs: u32, rhs: u32) -> bool {
+        let (lhs, rhs) = (f32::from_bits(lhs), f32::from_bits(rhs));
+        match self {
+            FloatComparison::Lt => lhs < rhs,
+            FloatComparison::Le => lhs <= rhs,
+            FloatComparison::Eq => lhs == rhs,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            FloatComparison::Lt => "flt",
+            FloatComparison::Le => "fle",
+            FloatComparison::Eq => "feq",
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn opcode(self) -> u8 {
+        35 + FloatComparison::ALL.iter().position(|&op| op == self).unwrap() as u8
+    }
+}
+
+/// Comparison computed by a compare instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// Signed `<`
+    Slt,
+    /// Signed `<=`
+    Sle,
+    /// Unsigned `<`
+    Ult,
+    /// Unsigned `<=`
+    Ule,
+    Eq,
+}
+
+impl Comparison {
+    pub const ALL: [Comparison; 5] = [Comparison::Slt, Comparison::Sle, Comparison::Ult, Comparison::Ule, Comparison::Eq];
+
+    /// Whether the comparison holds between `lhs` and `rhs`.
+    pub fn holds(self, lhs: u32, rhs: u32) -> bool {
+        match self {
+            Comparison::Slt => (lhs as i32) < (rhs as i32),
+            Comparison::Sle => (lhs as i32) <= (rhs as i32),
+            Comparison::Ult => lhs < rhs,
+            Comparison::Ule => lhs <= rhs,
+            Comparison::Eq => lhs == rhs,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Comparison::Slt => "slt",
+            Comparison::Sle => "sle",
+            Comparison::Ult => "ult",
+            Comparison::Ule => "ule",
+            Comparison::Eq => "eq",
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn opcode(self) -> u8 {
+        21 + Comparison::ALL.iter().position(|&op| op == self).unwrap() as u8
+    }
+}
+
+/// Condition on a register tested by a relative branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Zero,
+    NonZero,
+    /// The register is negative when read as a signed integer.
+    Negative,
+}
+
+impl Condition {
+    /// Whether the branch is taken when the tested register holds `value`.
+    pub fn holds(self, value: u32) -> bool {
+        match self {
+            Condition::Zero => value == 0,
+            Condition::NonZero => value != 0,
+            Condition::Negative => (value as i32) < 0,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn opcode(self) -> u8 {
+        match self {
+            Condition::Zero => 18,
+            Condition::NonZero => 19,
+            Condition::Negative => 20,
+        }
+    }
+
+    fn mnemonic(self) -> &'static str {
+        match self {
+            Condition::Zero => "bz",
+            Condition::NonZero => "bnz",
+            Condition::Negative => "bneg",
+        }
+    }
+}
+
+impl Instruction {
+    /// Decode the instruction located at `addr` in `mem`.
+    pub fn decode(mem: &[u8], addr: usize) -> Result<Instruction, MachineError> {
+        let byte = |offset: usize| mem.get(addr + offset).copied().ok_or(MachineError::InvalidMemAddr);
+
+        let instr = match byte(0)? {
+            1 => Instruction::MoveIf { dst: byte(1)?, src: byte(2)?, cond: byte(3)? },
+            2 => Instruction::Store { addr: byte(1)?, src: byte(2)? },
+            3 => Instruction::Load { dst: byte(1)?, addr: byte(2)? },
+            4 => Instruction::LoadImm { dst: byte(1)?, imm: i16::from_le_bytes([byte(2)?, byte(3)?]) },
+            5 => Instruction::Sub { dst: byte(1)?, lhs: byte(2)?, rhs: byte(3)? },
+            6 => Instruction::Out { src: byte(1)? },
+            7 => Instruction::Exit,
+            8 => Instruction::OutNumber { src: byte(1)? },
+            9 => Instruction::LoadImm32 { dst: byte(1)?, imm: u32::from_le_bytes([byte(2)?, byte(3)?, byte(4)?, byte(5)?]) },
+            10 => Instruction::LoadHi { dst: byte(1)?, imm: u16::from_le_bytes([byte(2)?, byte(3)?]) },
+            11 | 12 => Instruction::Load8 { dst: byte(1)?, addr: byte(2)?, signed: byte(0)? == 12 },
+            13 | 14 => Instruction::Load16 { dst: byte(1)?, addr: byte(2)?, signed: byte(0)? == 14 },
+            15 => Instruction::Store8 { addr: byte(1)?, src: byte(2)? },
+            16 => Instruction::Store16 { addr: byte(1)?, src: byte(2)? },
+            17 => Instruction::Jump { offset: i16::from_le_bytes([byte(1)?, byte(2)?]) },
+            opcode @ 18..=20 => {
+                let cond = [Condition::Zero, Condition::NonZero, Condition::Negative][opcode as usize - 18];
+                Instruction::Branch { cond, src: byte(1)?, offset: i16::from_le_bytes([byte(2)?, byte(3)?]) }
+            }
+            opcode @ 21..=25 => Instruction::Compare {
+                op: Comparison::ALL[opcode as usize - 21],
+                dst: byte(1)?,
+                lhs: byte(2)?,
+                rhs: byte(3)?,
+            },
+            26 => Instruction::MoveIfZero { dst: byte(1)?, src: byte(2)?, cond: byte(3)? },
+            27 => Instruction::MoveIfNeg { dst: byte(1)?, src: byte(2)?, cond: byte(3)? },
+            28 => Instruction::In { dst: byte(1)? },
+            opcode @ 29..=32 => Instruction::FloatArith {
+                op: FloatOp::ALL[opcode as usize - 29],
+                dst: byte(1)?,
+                lhs: byte(2)?,
+                rhs: byte(3)?,
+            },
+            33 => Instruction::IntToFloat { dst: byte(1)?, src: byte(2)? },
+            34 => Instruction::FloatToInt { dst: byte(1)?, src: byte(2)? },
+            opcode @ 35..=37 => Instruction::FloatCompare {
+                op: FloatComparison::ALL[opcode as usize - 35],
+                dst: byte(1)?,
+                lhs: byte(2)?,
+                rhs: byte(3)?,
+            },
+            38 => Instruction::OutFloat { src: byte(1)? },
+            39 => Instruction::Div { dst: byte(1)?, lhs: byte(2)?, rhs: byte(3)? },
+            40 => Instruction::ReturnFromTrap,
+            41 => Instruction::SetPageTable { src: byte(1)? },
+            _ => return Err(MachineError::InvalidOpcode),
+        };
+        Ok(instr)
+    }
+
+    /// Size of the encoded instruction in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LoadImm32 { .. } => 6,
+            Instruction::MoveIf { .. }
+            | Instruction::LoadImm { .. }
+            | Instruction::Sub { .. }
+            | Instruction::LoadHi { .. }
+            | Instruction::Branch { .. }
+            | Instruction::Compare { .. }
+            | Instruction::FloatArith { .. }
+            | Instruction::FloatCompare { .. }
+            | Instruction::Div { .. }
+            | Instruction::MoveIfZero { .. }
+            | Instruction::MoveIfNeg { .. } => 4,
+            Instruction::Store { .. }
+            | Instruction::Load { .. }
+            | Instruction::Load8 { .. }
+            | Instruction::Load16 { .. }
+            | Instruction::Store8 { .. }
+            | Instruction::Store16 { .. }
+            | Instruction::Jump { .. }
+            | Instruction::IntToFloat { .. }
+            | Instruction::FloatToInt { .. } => 3,
+            Instruction::Out { .. }
+            | Instruction::OutNumber { .. }
+            | Instruction::In { .. }
+            | Instruction::OutFloat { .. }
+            | Instruction::SetPageTable { .. } => 2,
+            Instruction::Exit | Instruction::ReturnFromTrap => 1,
+        }
+    }
+
+    /// Append the encoding of the instruction to `out`.
+    #[cfg(feature = "std")]
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Instruction::MoveIf { dst, src, cond } => out.extend([1, dst, src, cond]),
+            Instruction::Store { addr, src } => out.extend([2, addr, src]),
+            Instruction::Load { dst, addr } => out.extend([3, dst, addr]),
+            Instruction::LoadImm { dst, imm } => {
+                let [l, h] = imm.to_le_bytes();
+                out.extend([4, dst, l, h])
+            }
+            Instruction::Sub { dst, lhs, rhs } => out.extend([5, dst, lhs, rhs]),
+            Instruction::Out { src } => out.extend([6, src]),
+            Instruction::Exit => out.push(7),
+            Instruction::OutNumber { src } => out.extend([8, src]),
+            Instruction::LoadImm32 { dst, imm } => {
+                out.extend([9, dst]);
+                out.extend(imm.to_le_bytes())
+            }
+            Instruction::LoadHi { dst, imm } => {
+                let [l, h] = imm.to_le_bytes();
+                out.extend([10, dst, l, h])
+            }
+            Instruction::Load8 { dst, addr, signed } => out.extend([11 + signed as u8, dst, addr]),
+            Instruction::Load16 { dst, addr, signed } => out.extend([13 + signed as u8, dst, addr]),
+            Instruction::Store8 { addr, src } => out.extend([15, addr, src]),
+            Instruction::Store16 { addr, src } => out.extend([16, addr, src]),
+            Instruction::Jump { offset } => {
+                let [l, h] = offset.to_le_bytes();
+                out.extend([17, l, h])
+            }
+            Instruction::Branch { cond, src, offset } => {
+                let [l, h] = offset.to_le_bytes();
+                out.extend([cond.opcode(), src, l, h])
+            }
+            Instruction::Compare { op, dst, lhs, rhs } => out.extend([op.opcode(), dst, lhs, rhs]),
+            Instruction::MoveIfZero { dst, src, cond } => out.extend([26, dst, src, cond]),
+            Instruction::MoveIfNeg { dst, src, cond } => out.extend([27, dst, src, cond]),
+            Instruction::In { dst } => out.extend([28, dst]),
+            Instruction::FloatArith { op, dst, lhs, rhs } => out.extend([op.opcode(), dst, lhs, rhs]),
+            Instruction::IntToFloat { dst, src } => out.extend([33, dst, src]),
+            Instruction::FloatToInt { dst, src } => out.extend([34, dst, src]),
+            Instruction::FloatCompare { op, dst, lhs, rhs } => out.extend([op.opcode(), dst, lhs, rhs]),
+            Instruction::OutFloat { src } => out.extend([38, src]),
+            Instruction::Div { dst, lhs, rhs } => out.extend([39, dst, lhs, rhs]),
+            Instruction::ReturnFromTrap => out.push(40),
+            Instruction::SetPageTable { src } => out.extend([41, src]),
+        }
+    }
+
+    /// Register written by this instruction, if any. `rti` also pops the r2
+    /// stack, besides writing the IP.
+    pub fn written_reg(&self) -> Option<u8> {
+        match *self {
+            Instruction::MoveIf { dst, .. }
+            | Instruction::Load { dst, .. }
+            | Instruction::LoadImm { dst, .. }
+            | Instruction::Sub { dst, .. }
+            | Instruction::LoadImm32 { dst, .. }
+            | Instruction::LoadHi { dst, .. }
+            | Instruction::Load8 { dst, .. }
+            | Instruction::Load16 { dst, .. }
+            | Instruction::Compare { dst, .. }
+            | Instruction::MoveIfZero { dst, .. }
+            | Instruction::MoveIfNeg { dst, .. }
+            | Instruction::In { dst }
+            | Instruction::FloatArith { dst, .. }
+            | Instruction::IntToFloat { dst, .. }
+            | Instruction::FloatToInt { dst, .. }
+            | Instruction::FloatCompare { dst, .. }
+            | Instruction::Div { dst, .. } => Some(dst),
+            Instruction::ReturnFromTrap => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Registers read by this instruction, besides the IP. `loadhi` reads its
+    /// destination, whose lower half is kept.
+    #[cfg(feature = "std")]
+    pub fn read_regs(&self) -> Vec<u8> {
+        match *self {
+            Instruction::MoveIf { src, cond, .. }
+            | Instruction::MoveIfZero { src, cond, .. }
+            | Instruction::MoveIfNeg { src, cond, .. } => vec![src, cond],
+            Instruction::Store { addr, src } | Instruction::Store8 { addr, src } | Instruction::Store16 { addr, src } => {
+                vec![addr, src]
+            }
+            Instruction::Load { addr, .. } | Instruction::Load8 { addr, .. } | Instruction::Load16 { addr, .. } => vec![addr],
+            Instruction::Sub { lhs, rhs, .. }
+            | Instruction::Compare { lhs, rhs, .. }
+            | Instruction::FloatArith { lhs, rhs, .. }
+            | Instruction::FloatCompare { lhs, rhs, .. }
+            | Instruction::Div { lhs, rhs, .. } => vec![lhs, rhs],
+            Instruction::ReturnFromTrap => vec![2],
+            Instruction::Out { src }
+            | Instruction::OutNumber { src }
+            | Instruction::Branch { src, .. }
+            | Instruction::IntToFloat { src, .. }
+            | Instruction::FloatToInt { src, .. }
+            | Instruction::OutFloat { src }
+            | Instruction::SetPageTable { src } => vec![src],
+            Instruction::LoadHi { dst, .. } => vec![dst],
+            Instruction::LoadImm { .. }
+            | Instruction::LoadImm32 { .. }
+            | Instruction::Exit
+            | Instruction::Jump { .. }
+            | Instruction::In { .. } => vec![],
+        }
+    }
+
+    /// Register and value for instructions loading a constant in a register.
+    pub fn constant_load(&self) -> Option<(u8, u32)> {
+        match *self {
+            Instruction::LoadImm { dst, imm } => Some((dst, imm as i32 as u32)),
+            Instruction::LoadImm32 { dst, imm } => Some((dst, imm)),
+            _ => None,
+        }
+    }
+
+    /// Destination of a relative jump or branch located at `addr`.
+    pub fn relative_target(&self, addr: usize) -> Option<usize> {
+        match *self {
+            Instruction::Jump { offset } | Instruction::Branch { offset, .. } => {
+                (addr + self.size()).checked_add_signed(offset as isize)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Format the instruction the same way as the `.dis` listings.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::MoveIf { dst, src, cond } => write!(f, "move r{dst} <- r{src} if r{cond} != 0"),
+            Instruction::Store { addr, src } => write!(f, "store [r{addr}] <- r{src}"),
+            Instruction::Load { dst, addr } => write!(f, "load r{dst} <- [r{addr}]"),
+            Instruction::LoadImm { dst, imm } => write!(f, "loadimm r{dst} <- #{imm}"),
+            Instruction::Sub { dst, lhs, rhs } => write!(f, "sub r{dst} <- r{lhs} - r{rhs}"),
+            Instruction::Out { src } => write!(f, "out r{src}"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::OutNumber { src } => write!(f, "out_number r{src}"),
+            Instruction::LoadImm32 { dst, imm } => write!(f, "loadimm32 r{dst} <- #{}", imm as i32),
+            Instruction::LoadHi { dst, imm } => write!(f, "loadhi r{dst} <- #{imm}"),
+            Instruction::Load8 { dst, addr, signed } => {
+                write!(f, "load8{} r{dst} <- [r{addr}]", if signed { "s" } else { "" })
+            }
+            Instruction::Load16 { dst, addr, signed } => {
+                write!(f, "load16{} r{dst} <- [r{addr}]", if signed { "s" } else { "" })
+            }
+            Instruction::Store8 { addr, src } => write!(f, "store8 [r{addr}] <- r{src}"),
+            Instruction::Store16 { addr, src } => write!(f, "store16 [r{addr}] <- r{src}"),
+            Instruction::Jump { offset } => write!(f, "jmp #{offset}"),
+            Instruction::Branch { cond, src, offset } => write!(f, "{} r{src}, #{offset}", cond.mnemonic()),
+            Instruction::Compare { op, dst, lhs, rhs } => write!(f, "{} r{dst} <- r{lhs}, r{rhs}", op.mnemonic()),
+            Instruction::MoveIfZero { dst, src, cond } => write!(f, "move r{dst} <- r{src} if r{cond} == 0"),
+            Instruction::MoveIfNeg { dst, src, cond } => write!(f, "move r{dst} <- r{src} if r{cond} < 0"),
+            Instruction::In { dst } => write!(f, "in r{dst}"),
+            Instruction::FloatArith { op, dst, lhs, rhs } => {
+                write!(f, "{} r{dst} <- r{lhs} {} r{rhs}", op.mnemonic(), op.symbol())
+            }
+            Instruction::IntToFloat { dst, src } => write!(f, "itof r{dst} <- r{src}"),
+            Instruction::FloatToInt { dst, src } => write!(f, "ftoi r{dst} <- r{src}"),
+            Instruction::FloatCompare { op, dst, lhs, rhs } => write!(f, "{} r{dst} <- r{lhs}, r{rhs}", op.mnemonic()),
+            Instruction::OutFloat { src } => write!(f, "out_float r{src}"),
+            Instruction::Div { dst, lhs, rhs } => write!(f, "div r{dst} <- r{lhs} / r{rhs}"),
+            Instruction::ReturnFromTrap => write!(f, "rti"),
+            Instruction::SetPageTable { src } => write!(f, "setptb r{src}"),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use core::fmt;
+
+/// Failure of an [Input] or an [Output], reported by the machine as a
+/// [ReadError](crate::MachineError::ReadError) or a
+/// [WriteError](crate::MachineError::WriteError).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoError;
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "input/output error")
+    }
+}
+
+/// Destination of the output instructions. With the `std` feature, every
+/// [std::io::Write] is an output.
+pub trait Output {
+    /// Write all of `bytes`.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError>;
+}
+
+/// Source of the input instructions. With the `std` feature, every
+/// [std::io::Read] is an input.
+pub trait Input {
+    /// Read the next byte, or `None` at the end of the input.
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError>;
+}
+
+/// An input which is always at its end.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoInput;
+
+impl Input for NoInput {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Output for W {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        self.write_all(bytes).map_err(|_| IoError)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> Input for R {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        let mut byte = [0u8];
+        match self.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(_) => Err(IoError),
+        }
+    }
+}
@@ -1,3 +1,75 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+mod asm;
+#[cfg(feature = "std")]
+mod backtrace;
+#[cfg(feature = "std")]
+mod banks;
+#[cfg(feature = "std")]
+mod bf;
+#[cfg(feature = "std")]
+mod cfg;
+#[cfg(feature = "std")]
+mod compiler;
+#[cfg(feature = "std")]
+mod decompile;
+#[cfg(feature = "std")]
+mod handle;
+mod instruction;
+mod io;
+#[cfg(feature = "std")]
+mod link;
 mod machine;
+#[cfg(feature = "std")]
+mod macros;
+#[cfg(feature = "std")]
+mod object;
+mod observer;
+mod paging;
+#[cfg(feature = "std")]
+mod optimize;
+#[cfg(feature = "std")]
+mod ranges;
+#[cfg(feature = "std")]
+mod sanitizer;
+#[cfg(feature = "std")]
+mod stack;
+#[cfg(feature = "std")]
+mod vm2rust;
 
+#[cfg(feature = "std")]
+pub use asm::*;
+#[cfg(feature = "std")]
+pub use backtrace::*;
+#[cfg(feature = "std")]
+pub use banks::{BANK_SELECT, BANK_WINDOW};
+#[cfg(feature = "std")]
+pub use bf::*;
+#[cfg(feature = "std")]
+pub use cfg::*;
+#[cfg(feature = "std")]
+pub use compiler::*;
+#[cfg(feature = "std")]
+pub use decompile::*;
+#[cfg(feature = "std")]
+pub use handle::*;
+pub use instruction::*;
+pub use io::*;
+#[cfg(feature = "std")]
+pub use link::*;
 pub use machine::*;
+#[cfg(feature = "std")]
+pub use object::*;
+pub use observer::*;
+pub use paging::{Access, PAGE_COUNT, PAGE_EXEC, PAGE_READ, PAGE_SIZE, PAGE_USER, PAGE_VALID, PAGE_WRITE};
+#[cfg(feature = "std")]
+pub use optimize::*;
+#[cfg(feature = "std")]
+pub use ranges::*;
+#[cfg(feature = "std")]
+pub use sanitizer::UninitializedRead;
+#[cfg(feature = "std")]
+pub use stack::*;
+#[cfg(feature = "std")]
+pub use vm2rust::*;
@@ -0,0 +1,137 @@
+use crate::{LineInfo, ObjectFile, RelocationKind, Section, SectionKind, Symbol, MEMORY_SIZE};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkError {
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    OutOfMemory { size: usize },
+    Overflow(String),
+    InvalidEntry(u32),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::UndefinedSymbol(name) => write!(f, "undefined symbol {name}"),
+            LinkError::DuplicateSymbol(name) => write!(f, "symbol {name} is defined more than once"),
+            LinkError::OutOfMemory { size } => write!(f, "program size {size} exceeds the memory size {MEMORY_SIZE}"),
+            LinkError::Overflow(name) => write!(f, "address of {name} does not fit in its field"),
+            LinkError::InvalidEntry(addr) => write!(f, "entry point {addr} is outside of the sections"),
+        }
+    }
+}
+
+/// Link several object files into a single executable object file.
+///
+/// The code sections of all object files are laid out first, in order,
+/// starting at address 0, followed by their data sections. References to
+/// labels are patched using the definitions from the same object file first,
+/// then the global symbols of all object files. Relative branches are patched
+/// with the distance from the end of the branch to the label. The entry point
+/// is the one of the first object file, and must be in one of its sections.
+///
+/// The result holds one code and one data section, every symbol and line
+/// entry at its final address, and no relocation left. Line numbers still
+/// refer to the source of the object file they come from. Object files are
+/// expected to be well formed, as checked by [ObjectFile::from_bytes].
+pub fn link(objects: &[ObjectFile]) -> Result<ObjectFile, LinkError> {
+    // Layout: address of every section of every object file
+    let mut placement: Vec<Vec<usize>> = objects.iter().map(|o| vec![0; o.sections.len()]).collect();
+    let mut bases = [0usize; 2];
+    let mut cursor = 0;
+    for (k, kind) in [SectionKind::Code, SectionKind::Data].into_iter().enumerate() {
+        bases[k] = cursor;
+        for (i, object) in objects.iter().enumerate() {
+            for (j, section) in object.sections.iter().enumerate() {
+                if section.kind == kind {
+                    placement[i][j] = cursor;
+                    cursor += section.data.len();
+                }
+            }
+        }
+    }
+    if cursor > MEMORY_SIZE {
+        return Err(LinkError::OutOfMemory { size: cursor });
+    }
+
+    let relocated = |i: usize, section: usize, addr: u32| {
+        placement[i][section] + (addr - objects[i].sections[section].addr) as usize
+    };
+    // Final address of `addr` in the object file `i`, if it is in a section
+    let relocated_addr = |i: usize, addr: u32| {
+        objects[i]
+            .sections
+            .iter()
+            .position(|s| s.addr <= addr && (addr as usize) < s.addr as usize + s.data.len())
+            .map(|j| relocated(i, j, addr) as u32)
+    };
+
+    let mut symbols = vec![];
+    let mut globals = HashMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        for symbol in &object.symbols {
+            let addr = relocated(i, symbol.section as usize, symbol.addr) as u32;
+            if symbol.global && globals.insert(symbol.name.clone(), addr).is_some() {
+                return Err(LinkError::DuplicateSymbol(symbol.name.clone()));
+            }
+            let kind = object.sections[symbol.section as usize].kind;
+            symbols.push(Symbol { addr, section: (kind == SectionKind::Data) as u16, ..symbol.clone() });
+        }
+    }
+
+    let mut data = [vec![0; bases[1]], vec![0; cursor - bases[1]]];
+    for (i, object) in objects.iter().enumerate() {
+        for (j, section) in object.sections.iter().enumerate() {
+            let k = (section.kind == SectionKind::Data) as usize;
+            let start = placement[i][j] - bases[k];
+            data[k][start..start + section.data.len()].copy_from_slice(&section.data);
+        }
+
+        for reloc in &object.relocations {
+            let local = object.symbols.iter().find(|s| s.name == reloc.symbol);
+            let value = match local {
+                Some(s) => relocated(i, s.section as usize, s.addr) as u32,
+                None => *globals.get(&reloc.symbol).ok_or_else(|| LinkError::UndefinedSymbol(reloc.symbol.clone()))?,
+            };
+            let section = reloc.section as usize;
+            let k = (object.sections[section].kind == SectionKind::Data) as usize;
+            let start = placement[i][section] - bases[k] + reloc.offset as usize;
+            let bytes = match reloc.kind {
+                RelocationKind::Imm16 => i16::try_from(value)
+                    .map_err(|_| LinkError::Overflow(reloc.symbol.clone()))?
+                    .to_le_bytes()
+                    .to_vec(),
+                RelocationKind::Word32 => value.to_le_bytes().to_vec(),
+                RelocationKind::Rel16 => {
+                    let next = (bases[k] + start + 2) as i64;
+                    i16::try_from(value as i64 - next)
+                        .map_err(|_| LinkError::Overflow(reloc.symbol.clone()))?
+                        .to_le_bytes()
+                        .to_vec()
+                }
+            };
+            data[k][start..start + bytes.len()].copy_from_slice(&bytes);
+        }
+    }
+
+    let entry = match objects.first() {
+        Some(object) => relocated_addr(0, object.entry).ok_or(LinkError::InvalidEntry(object.entry))?,
+        None => 0,
+    };
+
+    let mut lines: Vec<LineInfo> = (0..objects.len())
+        .flat_map(|i| {
+            objects[i].lines.iter().filter_map(move |line| Some(LineInfo { addr: relocated_addr(i, line.addr)?, ..*line }))
+        })
+        .collect();
+    lines.sort_by_key(|line| line.addr);
+
+    let [text, data] = data;
+    let mut sections = vec![Section { name: ".text".to_string(), kind: SectionKind::Code, addr: 0, data: text }];
+    if !data.is_empty() {
+        sections.push(Section { name: ".data".to_string(), kind: SectionKind::Data, addr: bases[1] as u32, data });
+    }
+    Ok(ObjectFile { entry, sections, symbols, lines, ..Default::default() })
+}
@@ -1,21 +1,81 @@
-use std::io::{self, Write};
-
-const MEMORY_SIZE: usize = 4096;
-const NREGS: usize = 16;
+use crate::observer::Observers;
+use crate::paging::Paging;
+use crate::{Access, Comparison, Condition, FloatComparison, FloatOp, Input, Instruction, NoInput, Output};
+use core::fmt::{self, Write};
+#[cfg(feature = "std")]
+use crate::banks::Banks;
+#[cfg(feature = "std")]
+use crate::{sanitizer::Sanitizer, MachineObserver, ObjectError, ObjectFile, UninitializedRead};
+
+/// Size of the machine memory in bytes.
+pub const MEMORY_SIZE: usize = 4096;
+/// Number of registers, r0 being the IP.
+pub const NREGS: usize = 16;
 
 const IP: usize = 0;
+const SP: usize = 2;
+/// Bit of the cause word pushed by a trap from user mode.
+const FROM_USER: u32 = 1 << 8;
 
 pub struct Machine {
     reg: [u32; NREGS],
-    mem: [u8; MEMORY_SIZE]
+    mem: [u8; MEMORY_SIZE],
+    last_ip: u32,
+    /// Whether the floating-point instructions are enabled.
+    float: bool,
+    /// Address of the fault-vector table, when traps are enabled.
+    traps: Option<u32>,
+    /// Privilege and translation state, when paging is enabled.
+    paging: Option<Paging>,
+    /// Banks visible in the window above the memory, none by default.
+    #[cfg(feature = "std")]
+    banks: Banks,
+    /// Size of the loaded image, initialized for the sanitizer.
+    #[cfg(feature = "std")]
+    image_len: usize,
+    observers: Observers,
+}
+
+/// How a call to [run_slice](Machine::run_slice) ended without error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceOutcome {
+    /// The slice ran all its instructions and the program can continue.
+    Yielded,
+    /// The program ran an `exit` instruction.
+    Exited,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MachineError {
     InvalidOpcode,
     InvalidRegisterNumb,
     InvalidMemAddr,
-    WriteError
+    WriteError,
+    ReadError,
+    /// A `div` instruction divided by zero.
+    DivisionByZero,
+    /// The page table does not allow this access to the virtual address
+    /// `addr`.
+    PageFault { addr: u32 },
+    /// An instruction reserved to the supervisor was run in user mode.
+    PrivilegedInstruction,
+}
+
+impl MachineError {
+    /// Cause code pushed by a trap for this error, which is also the index of
+    /// its handler in the fault-vector table. Input and output errors come
+    /// from the host and cannot be trapped.
+    pub fn trap_cause(self) -> Option<u32> {
+        match self {
+            MachineError::InvalidMemAddr => Some(0),
+            MachineError::InvalidRegisterNumb => Some(1),
+            MachineError::InvalidOpcode => Some(2),
+            MachineError::DivisionByZero => Some(3),
+            MachineError::PageFault { .. } => Some(4),
+            MachineError::PrivilegedInstruction => Some(5),
+            MachineError::WriteError | MachineError::ReadError => None,
+        }
+    }
 }
 
 impl Machine {
@@ -31,21 +91,193 @@ impl Machine {
         let mut initial_mem: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
 
         initial_mem[0..memory.len()].copy_from_slice(memory);
-        Machine {mem: initial_mem, reg: [0; NREGS]}
+        Machine {
+            mem: initial_mem,
+            reg: [0; NREGS],
+            last_ip: 0,
+            float: false,
+            traps: None,
+            paging: None,
+            #[cfg(feature = "std")]
+            banks: Banks::default(),
+            #[cfg(feature = "std")]
+            image_len: memory.len(),
+            observers: Observers::default(),
+        }
+    }
+
+    /// Create a new machine from a serialized object file. Every section is
+    /// copied at its load address and the IP is set to the entry point.
+    #[cfg(feature = "std")]
+    pub fn load_object(bytes: &[u8]) -> Result<Self, ObjectError> {
+        let object = ObjectFile::from_bytes(bytes)?;
+        let mut machine = Machine::new(&object.image()?);
+        machine.reg[IP] = object.entry;
+        Ok(machine)
+    }
+
+    /// Enable the uninitialized-memory read detector. From now on, every
+    /// `load` reading a byte which has been written neither by the loaded
+    /// image nor by a `store` is reported in
+    /// [sanitizer_reports](Machine::sanitizer_reports).
+    ///
+    /// This should be called before running the program, as earlier stores
+    /// are not known to the detector. Enabling it again does nothing.
+    #[cfg(feature = "std")]
+    pub fn enable_sanitizer(&mut self) {
+        if self.observer::<Sanitizer>().is_some() {
+            return;
+        }
+        self.attach(Sanitizer::new(self.image_len));
+    }
+
+    /// Uninitialized reads detected since the sanitizer has been enabled.
+    #[cfg(feature = "std")]
+    pub fn sanitizer_reports(&self) -> &[UninitializedRead] {
+        match self.observer::<Sanitizer>() {
+            Some(sanitizer) => sanitizer.reports(),
+            None => &[],
+        }
+    }
+
+    /// Enable the floating-point instructions, opcodes 29 to 38, which read
+    /// registers as IEEE-754 single-precision floats. They are invalid
+    /// opcodes otherwise.
+    pub fn enable_float(&mut self) {
+        self.float = true;
+    }
+
+    /// Enable the traps, with the fault-vector table located at `table`. The
+    /// table holds the address of a handler for every
+    /// [cause](MachineError::trap_cause), as a 32-bit word at `table + 4 *
+    /// cause`, or 0 when the error is not handled.
+    ///
+    /// On a handled error, the address of the faulting instruction and then
+    /// the cause are pushed on the r2 stack, and execution continues at the
+    /// handler, which can resume the faulting instruction with `rti`. The
+    /// error is returned as usual when it is not handled, or when the stack
+    /// has no room left.
+    pub fn enable_traps(&mut self, table: u32) {
+        self.traps = Some(table);
+    }
+
+    /// Enable the supervisor and user modes, with paged virtual memory. The
+    /// machine starts in supervisor mode with the translation disabled, until
+    /// `setptb` (opcode 41) sets the physical address of a page table. The
+    /// table holds a 32-bit entry for each of the [PAGE_COUNT](crate::PAGE_COUNT)
+    /// pages, made of the address of a physical frame and of the `PAGE_*`
+    /// bits allowing the accesses to the page.
+    ///
+    /// Every fetch, load and store is then translated, and faults with
+    /// [PageFault](MachineError::PageFault) when not allowed. `exit`, `rti`
+    /// and `setptb` fault with
+    /// [PrivilegedInstruction](MachineError::PrivilegedInstruction) in user
+    /// mode. A trap from user mode switches to supervisor mode and to the
+    /// supervisor stack, pushing the user r2 below the usual frame, and
+    /// `rti` goes back to user mode when the saved cause says so.
+    pub fn enable_paging(&mut self) {
+        self.paging = Some(Paging::default());
+    }
+
+    /// Add `count` banks of [MEMORY_SIZE] zeroed bytes, bank 0 being
+    /// selected. The selected bank is visible in the window at
+    /// [BANK_WINDOW](crate::BANK_WINDOW), right above the memory, and
+    /// storing its index to the port at [BANK_SELECT](crate::BANK_SELECT)
+    /// switches to another bank. Accessing the window while a bank which
+    /// does not exist is selected is an invalid memory address.
+    ///
+    /// The window and the port are not translated by the page table, and
+    /// cannot be accessed in user mode.
+    #[cfg(feature = "std")]
+    pub fn enable_banks(&mut self, count: usize) {
+        self.banks.banks.resize_with(count, || Box::new([0; MEMORY_SIZE]));
+    }
+
+    /// Content of the bank `index`, if it exists.
+    #[cfg(feature = "std")]
+    pub fn bank(&self, index: usize) -> Option<&[u8]> {
+        self.banks.banks.get(index).map(|bank| &bank[..])
+    }
+
+    /// Content of the bank `index`, to load data into it.
+    #[cfg(feature = "std")]
+    pub fn bank_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        self.banks.banks.get_mut(index).map(|bank| &mut bank[..])
+    }
+
+    /// Index of the bank visible in the window, as last stored to the port.
+    #[cfg(feature = "std")]
+    pub fn selected_bank(&self) -> u32 {
+        self.banks.selected
+    }
+
+    /// Whether the machine runs in user mode, which only happens with
+    /// paging.
+    pub fn user_mode(&self) -> bool {
+        self.paging.is_some_and(|paging| paging.user)
+    }
+
+    /// Physical address of the virtual address `addr`, when the current mode
+    /// and page table allow `access`.
+    pub fn translate(&self, addr: u32, access: Access) -> Result<u32, MachineError> {
+        self.physical(addr as usize, access).map(|addr| addr as u32)
+    }
+
+    /// Attach an observer, notified of every following step.
+    #[cfg(feature = "std")]
+    pub fn attach<O: MachineObserver>(&mut self, observer: O) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// First attached observer of type `O`.
+    #[cfg(feature = "std")]
+    pub fn observer<O: MachineObserver>(&self) -> Option<&O> {
+        self.observers.find()
+    }
+
+    /// First attached observer of type `O`, to update it between steps.
+    #[cfg(feature = "std")]
+    pub fn observer_mut<O: MachineObserver>(&mut self) -> Option<&mut O> {
+        self.observers.find_mut()
     }
-    
 
     /// Run until the program terminates or until an error happens.
     /// If output instructions are run, they print on `fd`.
-    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<(), MachineError> {
+    pub fn run_on<T: Output>(&mut self, fd: &mut T) -> Result<(), MachineError> {
         while !self.step_on(fd)? {}
         Ok(())
     }
 
     /// Run until the program terminates or until an error happens.
     /// If output instructions are run, they print on standard output.
+    #[cfg(feature = "std")]
     pub fn run(&mut self) -> Result<(), MachineError> {
-        self.run_on(&mut io::stdout().lock())
+        self.run_on(&mut std::io::stdout().lock())
+    }
+
+    /// Run until the program terminates or until an error happens.
+    /// Input instructions read from `input`, and output instructions print
+    /// on `output`.
+    pub fn run_with<R: Input, W: Output>(&mut self, input: &mut R, output: &mut W) -> Result<(), MachineError> {
+        while !self.step_with(input, output)? {}
+        Ok(())
+    }
+
+    /// Run at most `steps` instructions, so that a scheduler can interleave
+    /// the machine with other work. Input instructions read from `input`,
+    /// and output instructions print on `output`.
+    pub fn run_slice<R: Input, W: Output>(
+        &mut self,
+        steps: usize,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<SliceOutcome, MachineError> {
+        for _ in 0..steps {
+            if self.step_with(input, output)? {
+                return Ok(SliceOutcome::Exited);
+            }
+        }
+        Ok(SliceOutcome::Yielded)
     }
 
     /// Execute the next instruction by doing the following steps:
@@ -60,11 +292,36 @@ impl Machine {
     /// In case of success, `true` is returned if the program is
     /// terminated (upon encountering an exit instruction), or
     /// `false` if the execution must continue.
-    pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+    pub fn step_on<T: Output>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+        self.step_with(&mut NoInput, fd)
+    }
 
-        let inst_addr = self.reg[IP] as usize;
+    /// Similar to [step_on](Machine::step_on), input instructions reading
+    /// from `input`.
+    pub fn step_with<R: Input, W: Output>(&mut self, input: &mut R, fd: &mut W) -> Result<bool, MachineError> {
+        match self.execute(input, fd) {
+            Err(error) if self.traps.is_some() => self.trap(error),
+            result => result,
+        }
+    }
 
-        let opcode = self.read_mem(inst_addr)?; 
+    /// Decode and execute the next instruction, without trapping.
+    fn execute<R: Input, W: Output>(&mut self, input: &mut R, fd: &mut W) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+        self.last_ip = self.reg[IP];
+
+        let opcode = self.fetch(inst_addr)?; 
+        if !self.observers.is_empty() {
+            let mut bytes = [0u8; 6];
+            let fetched = (0..bytes.len()).take_while(|&i| self.fetch(inst_addr + i).map(|byte| bytes[i] = byte).is_ok()).count();
+            if let Ok(instr) = Instruction::decode(&bytes[..fetched], 0) {
+                self.observers.notify(|observer| observer.on_fetch(inst_addr as u32, &instr));
+            }
+        }
+        if self.user_mode() && matches!(opcode, 7 | 40 | 41) {
+            return Err(MachineError::PrivilegedInstruction);
+        }
 
         match opcode {
             1 => return self.move_if(),
@@ -75,6 +332,41 @@ impl Machine {
             6 => return self.out(fd),
             7 => return self.exit(),
             8 => return self.out_number(fd),
+            9 => return self.loadimm32(),
+            10 => return self.loadhi(),
+            11 => return self.load8(),
+            12 => return self.load8s(),
+            13 => return self.load16(),
+            14 => return self.load16s(),
+            15 => return self.store8(),
+            16 => return self.store16(),
+            17 => return self.jmp(),
+            18 => return self.bz(),
+            19 => return self.bnz(),
+            20 => return self.bneg(),
+            21 => return self.slt(),
+            22 => return self.sle(),
+            23 => return self.ult(),
+            24 => return self.ule(),
+            25 => return self.eq(),
+            26 => return self.move_if_zero(),
+            27 => return self.move_if_neg(),
+            28 => return self.input(input),
+            29..=38 if !self.float => return Err(MachineError::InvalidOpcode),
+            29 => return self.fadd(),
+            30 => return self.fsub(),
+            31 => return self.fmul(),
+            32 => return self.fdiv(),
+            33 => return self.itof(),
+            34 => return self.ftoi(),
+            35 => return self.flt(),
+            36 => return self.fle(),
+            37 => return self.feq(),
+            38 => return self.out_float(fd),
+            39 => return self.div(),
+            40 => return self.rti(),
+            41 if self.paging.is_none() => return Err(MachineError::InvalidOpcode),
+            41 => return self.setptb(),
             _ => return Err(MachineError::InvalidOpcode)
         }
     }
@@ -87,16 +379,16 @@ impl Machine {
         self.reg[IP] += 4u32;
 
         // Decode
-        let reg_a = self.read_mem(inst_addr + 1)? as usize;
-        let reg_b = self.read_mem(inst_addr + 2)? as usize;
-        let reg_c = self.read_mem(inst_addr + 3)? as usize;
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
+        let reg_c = self.fetch(inst_addr + 3)? as usize;
 
         let reg_b_cont: u32 = self.read_reg(reg_b)?;
         let reg_c_cont: u32 = self.read_reg(reg_c)?;
 
         // Execute
         if reg_c_cont != 0 {
-            self.set_reg(reg_a, reg_b_cont)?;
+            self.write_reg(reg_a, reg_b_cont)?;
         }
 
         Ok(false)
@@ -110,23 +402,15 @@ impl Machine {
         self.reg[IP] += 3u32;
 
         // Decode
-        let reg_a = self.read_mem(inst_addr + 1)? as usize;
-        let reg_b = self.read_mem(inst_addr + 2)? as usize;
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
 
         // Execute
         let reg_a_cont = self.read_reg(reg_a)? as usize;
         let reg_b_cont = self.read_reg(reg_b)?;
 
         let data: [u8; 4] = reg_b_cont.to_le_bytes();
-        if reg_a_cont + 3 < MEMORY_SIZE {
-            self.mem[reg_a_cont] = data[0];
-            self.mem[reg_a_cont + 1] = data[1];
-            self.mem[reg_a_cont + 2] = data[2];
-            self.mem[reg_a_cont + 3] = data[3];
-        }
-        else {
-            return Err(MachineError::InvalidMemAddr);
-        }
+        self.write_data(reg_a_cont, &data)?;
         Ok(false)
         
     }
@@ -139,22 +423,15 @@ impl Machine {
         self.reg[IP] += 3u32;
 
         // Decode
-        let reg_a = self.read_mem(instr_addr + 1)? as usize;
-        let reg_b = self.read_mem(instr_addr + 2)? as usize;
+        let reg_a = self.fetch(instr_addr + 1)? as usize;
+        let reg_b = self.fetch(instr_addr + 2)? as usize;
 
         // Execute
         let addr = self.read_reg(reg_b)? as usize;
-        if addr + 3 < MEMORY_SIZE {
-            let value: [u8; 4] = [self.read_mem(addr)?,
-                                  self.read_mem(addr + 1)?,
-                                  self.read_mem(addr + 2)?,
-                                  self.read_mem(addr + 3)?
-                                 ];
-            self.set_reg(reg_a, u32::from_le_bytes(value))?;
-        }
-        else {
-            return Err(MachineError::InvalidMemAddr);
-        }
+        let mut value = [0u8; 4];
+        let phys = self.read_data(addr, &mut value)?;
+        self.write_reg(reg_a, u32::from_le_bytes(value))?;
+        self.notify_access(&phys[..4], &value, false);
         
         Ok(false)
 
@@ -168,15 +445,15 @@ impl Machine {
         self.reg[IP] += 4u32;
 
         // Decode
-        let reg_a = self.read_mem(inst_addr + 1)? as usize;
-        let lh: [u8; 2] = [self.read_mem(inst_addr + 2)?,
-                           self.read_mem(inst_addr + 3)?
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let lh: [u8; 2] = [self.fetch(inst_addr + 2)?,
+                           self.fetch(inst_addr + 3)?
                           ];
         
         // Execute
         let word = i16::from_le_bytes(lh) as i32;
 
-        self.set_reg(reg_a, word as u32)?;
+        self.write_reg(reg_a, word as u32)?;
 
         Ok(false)
 
@@ -190,9 +467,9 @@ impl Machine {
         self.reg[IP] += 4u32;
 
         // Decode
-        let reg_a = self.read_mem(inst_addr + 1)? as usize;
-        let reg_b = self.read_mem(inst_addr + 2)? as usize;
-        let reg_c = self.read_mem(inst_addr + 3)? as usize;
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
+        let reg_c = self.fetch(inst_addr + 3)? as usize;
 
         // Execute
         let reg_b_cont = self.read_reg(reg_b)? as i32;
@@ -201,13 +478,13 @@ impl Machine {
         //let result = i32::wrapping_sub(reg_b_cont, reg_c_cont);
         let result = reg_b_cont.wrapping_sub(reg_c_cont);
 
-        self.set_reg(reg_a, result as u32)?;
+        self.write_reg(reg_a, result as u32)?;
 
         Ok(false)
 
     }
 
-    pub fn out<T: Write>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+    pub fn out<T: Output>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
 
         let inst_addr = self.reg[IP] as usize;
 
@@ -215,15 +492,12 @@ impl Machine {
         self.reg[IP] += 2u32;
 
         // Decode
-        let reg_a = self.read_mem(inst_addr + 1)? as usize;
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
 
         // Execute
         let my_char = (self.read_reg(reg_a)? as u8) as char;
 
-        match fd.write_all(my_char.to_string().as_bytes()) {
-            Ok(_) => Ok(false),
-            Err(_)=> Err(MachineError::WriteError)
-        }
+        self.write_out(fd, my_char.encode_utf8(&mut [0; 4]).as_bytes())
 
     }
 
@@ -235,7 +509,7 @@ impl Machine {
         Ok(true)
     }
 
-    pub fn out_number<T: Write>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+    pub fn out_number<T: Output>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
 
         let inst_addr = self.reg[IP] as usize;
 
@@ -243,22 +517,550 @@ impl Machine {
         self.reg[IP] += 2u32;
 
         // Decode
-        let reg_a = self.read_mem(inst_addr + 1)? as usize;
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
         
         // Execute
         let number = self.read_reg(reg_a)? as i32;
-        let _dec = i32::from_str_radix(&number.to_string(), 10).unwrap();
 
-        match fd.write_all(_dec.to_string().as_bytes()) {
-            Ok(_) => Ok(false),
-            Err(_)=> Err(MachineError::WriteError)
+        self.write_out(fd, format_decimal(number, &mut [0; 11]))
+    }
+
+    pub fn loadimm32(&mut self) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 6u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let word: [u8; 4] = [self.fetch(inst_addr + 2)?,
+                             self.fetch(inst_addr + 3)?,
+                             self.fetch(inst_addr + 4)?,
+                             self.fetch(inst_addr + 5)?
+                            ];
+
+        // Execute
+        self.write_reg(reg_a, u32::from_le_bytes(word))?;
+
+        Ok(false)
+
+    }
+
+    pub fn loadhi(&mut self) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 4u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let lh: [u8; 2] = [self.fetch(inst_addr + 2)?,
+                           self.fetch(inst_addr + 3)?
+                          ];
+
+        // Execute: replace the upper half, keep the lower one
+        let low = self.read_reg(reg_a)? & 0xffff;
+        let high = u16::from_le_bytes(lh) as u32;
+
+        self.write_reg(reg_a, (high << 16) | low)?;
+
+        Ok(false)
+
+    }
+
+    pub fn load8(&mut self) -> Result<bool, MachineError> {
+        self.load_narrow(1, false)
+    }
+
+    pub fn load8s(&mut self) -> Result<bool, MachineError> {
+        self.load_narrow(1, true)
+    }
+
+    pub fn load16(&mut self) -> Result<bool, MachineError> {
+        self.load_narrow(2, false)
+    }
+
+    pub fn load16s(&mut self) -> Result<bool, MachineError> {
+        self.load_narrow(2, true)
+    }
+
+    pub fn store8(&mut self) -> Result<bool, MachineError> {
+        self.store_narrow(1)
+    }
+
+    pub fn store16(&mut self) -> Result<bool, MachineError> {
+        self.store_narrow(2)
+    }
+
+    /// Load `width` bytes (1 or 2) in little-endian order, and zero- or
+    /// sign-extend them to 32 bits.
+    fn load_narrow(&mut self, width: usize, signed: bool) -> Result<bool, MachineError> {
+
+        let instr_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 3u32;
+
+        // Decode
+        let reg_a = self.fetch(instr_addr + 1)? as usize;
+        let reg_b = self.fetch(instr_addr + 2)? as usize;
+
+        // Execute
+        let addr = self.read_reg(reg_b)? as usize;
+        let mut bytes = [0u8; 4];
+        let phys = self.read_data(addr, &mut bytes[..width])?;
+        let mut value = u32::from_le_bytes(bytes);
+        if signed {
+            let shift = 32 - 8 * width as u32;
+            value = (((value << shift) as i32) >> shift) as u32;
+        }
+        self.write_reg(reg_a, value)?;
+        self.notify_access(&phys[..width], &bytes[..width], false);
+
+        Ok(false)
+
+    }
+
+    /// Store the `width` (1 or 2) least significant bytes of a register in
+    /// little-endian order.
+    fn store_narrow(&mut self, width: usize) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 3u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
+
+        // Execute
+        let addr = self.read_reg(reg_a)? as usize;
+        let data: [u8; 4] = self.read_reg(reg_b)?.to_le_bytes();
+        self.write_data(addr, &data[..width])?;
+
+        Ok(false)
+
+    }
+
+    pub fn jmp(&mut self) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 3u32;
+
+        // Decode
+        let lh: [u8; 2] = [self.fetch(inst_addr + 1)?,
+                           self.fetch(inst_addr + 2)?
+                          ];
+
+        // Execute: the offset is relative to the next instruction
+        let offset = i16::from_le_bytes(lh) as i32;
+        self.write_reg(IP, self.reg[IP].wrapping_add(offset as u32))?;
+
+        Ok(false)
+
+    }
+
+    pub fn bz(&mut self) -> Result<bool, MachineError> {
+        self.branch(Condition::Zero)
+    }
+
+    pub fn bnz(&mut self) -> Result<bool, MachineError> {
+        self.branch(Condition::NonZero)
+    }
+
+    pub fn bneg(&mut self) -> Result<bool, MachineError> {
+        self.branch(Condition::Negative)
+    }
+
+    /// Add the offset to the IP when the tested register satisfies `cond`.
+    fn branch(&mut self, cond: Condition) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 4u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let lh: [u8; 2] = [self.fetch(inst_addr + 2)?,
+                           self.fetch(inst_addr + 3)?
+                          ];
+
+        // Execute
+        if cond.holds(self.read_reg(reg_a)?) {
+            let offset = i16::from_le_bytes(lh) as i32;
+            self.write_reg(IP, self.reg[IP].wrapping_add(offset as u32))?;
+        }
+
+        Ok(false)
+
+    }
+
+    pub fn slt(&mut self) -> Result<bool, MachineError> {
+        self.compare(Comparison::Slt)
+    }
+
+    pub fn sle(&mut self) -> Result<bool, MachineError> {
+        self.compare(Comparison::Sle)
+    }
+
+    pub fn ult(&mut self) -> Result<bool, MachineError> {
+        self.compare(Comparison::Ult)
+    }
+
+    pub fn ule(&mut self) -> Result<bool, MachineError> {
+        self.compare(Comparison::Ule)
+    }
+
+    pub fn eq(&mut self) -> Result<bool, MachineError> {
+        self.compare(Comparison::Eq)
+    }
+
+    /// Set a register to 1 if the comparison holds between two other
+    /// registers, and to 0 otherwise.
+    fn compare(&mut self, op: Comparison) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 4u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
+        let reg_c = self.fetch(inst_addr + 3)? as usize;
+
+        // Execute
+        let reg_b_cont = self.read_reg(reg_b)?;
+        let reg_c_cont = self.read_reg(reg_c)?;
+
+        self.write_reg(reg_a, op.holds(reg_b_cont, reg_c_cont) as u32)?;
+
+        Ok(false)
+
+    }
+
+    pub fn move_if_zero(&mut self) -> Result<bool, MachineError> {
+        self.move_when(Condition::Zero)
+    }
+
+    pub fn move_if_neg(&mut self) -> Result<bool, MachineError> {
+        self.move_when(Condition::Negative)
+    }
+
+    /// Same as [move_if](Machine::move_if) with another condition on `rC`.
+    fn move_when(&mut self, cond: Condition) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 4u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
+        let reg_c = self.fetch(inst_addr + 3)? as usize;
+
+        let reg_b_cont: u32 = self.read_reg(reg_b)?;
+        let reg_c_cont: u32 = self.read_reg(reg_c)?;
+
+        // Execute
+        if cond.holds(reg_c_cont) {
+            self.write_reg(reg_a, reg_b_cont)?;
+        }
+
+        Ok(false)
+
+    }
+
+    /// Read a byte from `input`, or -1 at the end of the input.
+    pub fn input<T: Input>(&mut self, input: &mut T) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 2u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+
+        // Execute
+        let value = match input.read_byte() {
+            Ok(None) => u32::MAX,
+            Ok(Some(byte)) => byte as u32,
+            Err(_) => return Err(MachineError::ReadError),
+        };
+        self.write_reg(reg_a, value)?;
+
+        Ok(false)
+
+    }
+
+    pub fn fadd(&mut self) -> Result<bool, MachineError> {
+        self.float_arith(FloatOp::Add)
+    }
+
+    pub fn fsub(&mut self) -> Result<bool, MachineError> {
+        self.float_arith(FloatOp::Sub)
+    }
+
+    pub fn fmul(&mut self) -> Result<bool, MachineError> {
+        self.float_arith(FloatOp::Mul)
+    }
+
+    pub fn fdiv(&mut self) -> Result<bool, MachineError> {
+        self.float_arith(FloatOp::Div)
+    }
+
+    /// Compute `op` on two registers read as floats.
+    fn float_arith(&mut self, op: FloatOp) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 4u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
+        let reg_c = self.fetch(inst_addr + 3)? as usize;
+
+        // Execute
+        let reg_b_cont = self.read_reg(reg_b)?;
+        let reg_c_cont = self.read_reg(reg_c)?;
+
+        self.write_reg(reg_a, op.apply(reg_b_cont, reg_c_cont))?;
+
+        Ok(false)
+
+    }
+
+    /// Convert a signed integer to the nearest float.
+    pub fn itof(&mut self) -> Result<bool, MachineError> {
+        self.convert(|value| (value as i32 as f32).to_bits())
+    }
+
+    /// Convert a float to a signed integer, truncating toward zero. Out of
+    /// range values saturate and NaN becomes 0.
+    pub fn ftoi(&mut self) -> Result<bool, MachineError> {
+        self.convert(|value| f32::from_bits(value) as i32 as u32)
+    }
+
+    fn convert(&mut self, conversion: fn(u32) -> u32) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 3u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
+
+        // Execute
+        let reg_b_cont = self.read_reg(reg_b)?;
+
+        self.write_reg(reg_a, conversion(reg_b_cont))?;
+
+        Ok(false)
+
+    }
+
+    pub fn flt(&mut self) -> Result<bool, MachineError> {
+        self.float_compare(FloatComparison::Lt)
+    }
+
+    pub fn fle(&mut self) -> Result<bool, MachineError> {
+        self.float_compare(FloatComparison::Le)
+    }
+
+    pub fn feq(&mut self) -> Result<bool, MachineError> {
+        self.float_compare(FloatComparison::Eq)
+    }
+
+    /// Same as [compare](Machine::compare) with registers read as floats.
+    fn float_compare(&mut self, op: FloatComparison) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 4u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
+        let reg_c = self.fetch(inst_addr + 3)? as usize;
+
+        // Execute
+        let reg_b_cont = self.read_reg(reg_b)?;
+        let reg_c_cont = self.read_reg(reg_c)?;
+
+        self.write_reg(reg_a, op.holds(reg_b_cont, reg_c_cont) as u32)?;
+
+        Ok(false)
+
+    }
+
+    /// Print a register read as a float, in the shortest decimal form
+    /// which reads back as the same float.
+    pub fn out_float<T: Output>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 2u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+
+        // Execute
+        let value = f32::from_bits(self.read_reg(reg_a)?);
+        let mut text = TextBuffer { bytes: [0; 64], len: 0 };
+        write!(text, "{value}").map_err(|_| MachineError::WriteError)?;
+
+        self.write_out(fd, &text.bytes[..text.len])
+
+    }
+
+    pub fn div(&mut self) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 4u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+        let reg_b = self.fetch(inst_addr + 2)? as usize;
+        let reg_c = self.fetch(inst_addr + 3)? as usize;
+
+        // Execute: i32::MIN / -1 wraps around
+        let reg_b_cont = self.read_reg(reg_b)? as i32;
+        let reg_c_cont = self.read_reg(reg_c)? as i32;
+        if reg_c_cont == 0 {
+            return Err(MachineError::DivisionByZero);
         }
+
+        self.write_reg(reg_a, reg_b_cont.wrapping_div(reg_c_cont) as u32)?;
+
+        Ok(false)
+
+    }
+
+    /// Pop the cause and the IP pushed by a trap, and resume at that IP.
+    /// With paging, the user r2 is also popped when the trap came from user
+    /// mode, going back to it.
+    pub fn rti(&mut self) -> Result<bool, MachineError> {
+
+        // Increment the IP
+        self.reg[IP] += 1u32;
+
+        // Execute
+        let sp = self.reg[SP] as usize;
+        let mut frame = [0u8; 8];
+        let phys = self.read_data(sp, &mut frame)?;
+        self.notify_access(&phys[..8], &frame, false);
+        let [cause, ip] = [0, 4].map(|i| u32::from_le_bytes(frame[i..i + 4].try_into().unwrap()));
+        match self.paging {
+            Some(paging) if cause & FROM_USER != 0 => {
+                let mut user_sp = [0u8; 4];
+                let phys = self.read_data(sp + 8, &mut user_sp)?;
+                self.notify_access(&phys[..4], &user_sp, false);
+                self.paging = Some(Paging { user: true, kernel_sp: (sp + 12) as u32, ..paging });
+                self.write_reg(SP, u32::from_le_bytes(user_sp))?;
+            }
+            _ => self.write_reg(SP, (sp + 8) as u32)?,
+        }
+        self.write_reg(IP, ip)?;
+
+        Ok(false)
+
+    }
+
+    /// Set the physical address of the page table, 0 disabling the
+    /// translation.
+    pub fn setptb(&mut self) -> Result<bool, MachineError> {
+
+        let inst_addr = self.reg[IP] as usize;
+
+        // Increment the IP
+        self.reg[IP] += 2u32;
+
+        // Decode
+        let reg_a = self.fetch(inst_addr + 1)? as usize;
+
+        // Execute
+        let table = self.read_reg(reg_a)?;
+        let paging = self.paging.as_mut().ok_or(MachineError::InvalidOpcode)?;
+        paging.table = table;
+
+        Ok(false)
+
+    }
+
+    /// Push the faulting IP and the cause of `error` on the stack and jump
+    /// to its handler, or return `error` when it cannot be handled. A trap
+    /// from user mode switches to the supervisor stack, and pushes the user
+    /// r2 first.
+    fn trap(&mut self, error: MachineError) -> Result<bool, MachineError> {
+        let (Some(table), Some(cause)) = (self.traps, error.trap_cause()) else {
+            return Err(error);
+        };
+        let entry = table as usize + 4 * cause as usize;
+        let handler = match self.mem.get(entry..entry + 4) {
+            Some(&[a, b, c, d]) => u32::from_le_bytes([a, b, c, d]),
+            _ => return Err(error),
+        };
+        if handler == 0 {
+            return Err(error);
+        }
+
+        let mut word = cause;
+        if let MachineError::PageFault { addr } = error {
+            word |= addr << 16;
+        }
+        let (sp, size) = match self.paging {
+            Some(paging) if paging.user => (paging.kernel_sp as usize, 12),
+            _ => (self.reg[SP] as usize, 8),
+        };
+        if size == 12 {
+            word |= FROM_USER;
+        }
+        let mut frame = [0u8; 12];
+        frame[..4].copy_from_slice(&word.to_le_bytes());
+        frame[4..8].copy_from_slice(&self.last_ip.to_le_bytes());
+        frame[8..].copy_from_slice(&self.reg[SP].to_le_bytes());
+
+        // The frame is written with the privileges of the supervisor
+        let saved = self.paging;
+        if let Some(paging) = &mut self.paging {
+            paging.user = false;
+        }
+        let Some(top) = sp.checked_sub(size).filter(|&top| self.write_data(top, &frame[..size]).is_ok()) else {
+            self.paging = saved;
+            return Err(error);
+        };
+        self.write_reg(SP, top as u32)?;
+        self.write_reg(IP, handler)?;
+        Ok(false)
     }
 
     /// Similar to [step_on](Machine::step_on).
     /// If output instructions are run, they print on standard output.
+    #[cfg(feature = "std")]
     pub fn step(&mut self) -> Result<bool, MachineError> {
-        self.step_on(&mut io::stdout().lock())
+        self.step_on(&mut std::io::stdout().lock())
+    }
+
+    /// Address of the last instruction the machine started to execute. After
+    /// an error, this is the address of the faulting instruction.
+    pub fn last_ip(&self) -> u32 {
+        self.last_ip
     }
 
     /// Reference onto the machine current set of registers.
@@ -275,6 +1077,22 @@ impl Machine {
         Ok(())
     }
 
+    /// Write a register on behalf of an instruction, notifying the observers.
+    fn write_reg(&mut self, reg: usize, value: u32) -> Result<(), MachineError> {
+        let old = self.read_reg(reg)?;
+        self.reg[reg] = value;
+        self.observers.notify(|observer| observer.on_reg_write(reg, old, value));
+        Ok(())
+    }
+
+    /// Print `bytes` on behalf of an output instruction, notifying the
+    /// observers.
+    fn write_out<T: Output>(&mut self, fd: &mut T, bytes: &[u8]) -> Result<bool, MachineError> {
+        fd.write_bytes(bytes).map_err(|_| MachineError::WriteError)?;
+        self.observers.notify(|observer| observer.on_output(bytes));
+        Ok(false)
+    }
+
     /// Reference onto the machine current memory.
     pub fn memory(&self) -> &[u8] {
         return &self.mem[..];
@@ -282,11 +1100,92 @@ impl Machine {
 
 
     /// Check if machine memory adress is located in the right memory space
-    /// (from 0 to MEMORY_SIZE - 1) 
+    /// (from 0 to MEMORY_SIZE - 1), translating it with paging
     pub fn read_mem(&self, addr: usize) -> Result<u8, MachineError> {
-        match addr {
-            n if n < MEMORY_SIZE => Ok(self.mem[addr]),
-            _                           => Err(MachineError::InvalidMemAddr),
+        self.physical(addr, Access::Read).map(|addr| self.byte(addr))
+    }
+
+    /// Read an instruction byte, which must be executable with paging.
+    fn fetch(&self, addr: usize) -> Result<u8, MachineError> {
+        self.physical(addr, Access::Execute).map(|addr| self.byte(addr))
+    }
+
+    /// Physical address of `addr` for `access` in the current mode.
+    fn physical(&self, addr: usize, access: Access) -> Result<usize, MachineError> {
+        #[cfg(feature = "std")]
+        if addr >= MEMORY_SIZE && !self.user_mode() && self.banks.contains(addr) {
+            return Ok(addr);
+        }
+        match self.paging {
+            Some(paging) => paging.translate(&self.mem, addr, access, paging.user),
+            None if addr < MEMORY_SIZE => Ok(addr),
+            None => Err(MachineError::InvalidMemAddr),
+        }
+    }
+
+    /// Physical addresses of the `len` bytes at `addr`, checked for
+    /// `access` before any of them is accessed.
+    fn physical_range(&self, addr: usize, len: usize, access: Access) -> Result<[usize; 12], MachineError> {
+        let mut phys = [0; 12];
+        for (i, slot) in phys[..len].iter_mut().enumerate() {
+            *slot = self.physical(addr.checked_add(i).ok_or(MachineError::InvalidMemAddr)?, access)?;
+        }
+        Ok(phys)
+    }
+
+    /// Load `buf.len()` bytes at `addr` on behalf of an instruction, and
+    /// return their physical addresses to notify the observers.
+    fn read_data(&self, addr: usize, buf: &mut [u8]) -> Result<[usize; 12], MachineError> {
+        let phys = self.physical_range(addr, buf.len(), Access::Read)?;
+        for (byte, &addr) in buf.iter_mut().zip(&phys) {
+            *byte = self.byte(addr);
+        }
+        Ok(phys)
+    }
+
+    /// Store `data` at `addr` on behalf of an instruction, notifying the
+    /// observers. Nothing is written when a byte cannot be.
+    fn write_data(&mut self, addr: usize, data: &[u8]) -> Result<(), MachineError> {
+        let phys = self.physical_range(addr, data.len(), Access::Write)?;
+        for (&byte, &addr) in data.iter().zip(&phys) {
+            self.set_byte(addr, byte);
+        }
+        self.notify_access(&phys[..data.len()], data, true);
+        Ok(())
+    }
+
+    /// Byte at the physical address `addr`, in the memory or in the banks.
+    fn byte(&self, addr: usize) -> u8 {
+        #[cfg(feature = "std")]
+        if addr >= MEMORY_SIZE {
+            return self.banks.read(addr);
+        }
+        self.mem[addr]
+    }
+
+    /// Write the byte at the physical address `addr`.
+    fn set_byte(&mut self, addr: usize, byte: u8) {
+        #[cfg(feature = "std")]
+        if addr >= MEMORY_SIZE {
+            return self.banks.write(addr, byte);
+        }
+        self.mem[addr] = byte;
+    }
+
+    /// Notify the observers of an access to the bytes at the physical
+    /// addresses `phys`, once per contiguous run.
+    fn notify_access(&mut self, phys: &[usize], data: &[u8], write: bool) {
+        let mut start = 0;
+        for end in 1..=phys.len() {
+            if end < phys.len() && phys[end] == phys[end - 1] + 1 {
+                continue;
+            }
+            let (addr, bytes) = (phys[start] as u32, &data[start..end]);
+            self.observers.notify(|observer| match write {
+                true => observer.on_mem_write(addr, bytes),
+                false => observer.on_mem_read(addr, bytes),
+            });
+            start = end;
         }
     }
 
@@ -300,3 +1199,39 @@ impl Machine {
     }
 
 }
+
+/// Write the decimal representation of `number` at the end of `buf`, which
+/// is large enough for `i32::MIN`, and return it.
+fn format_decimal(number: i32, buf: &mut [u8; 11]) -> &[u8] {
+    let mut value = number.unsigned_abs();
+    let mut start = buf.len();
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    if number < 0 {
+        start -= 1;
+        buf[start] = b'-';
+    }
+    &buf[start..]
+}
+
+/// Fixed-size buffer to format text without allocating. Every float fits in
+/// 64 bytes, the longest being the smallest subnormals.
+struct TextBuffer {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl Write for TextBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use crate::AsmError;
+use std::collections::HashMap;
+
+/// Register clobbered by the expansions, as in the listings.
+const SCRATCH: &str = "r3";
+
+/// A line of the source, or a block construct along with its body.
+enum Node {
+    Line(usize, String),
+    If { line: usize, reg: String, nonzero: Vec<Node>, zero: Vec<Node> },
+    While { line: usize, reg: String, nonzero: bool, body: Vec<Node> },
+}
+
+/// Block being parsed, waiting for its closing brace.
+enum Open {
+    If { line: usize, reg: String, negated: bool, then: Vec<Node>, otherwise: Option<Vec<Node>> },
+    While { line: usize, reg: String, nonzero: bool },
+}
+
+/// Expand the structured macros into primitive instructions. `lines` holds
+/// the source lines, stripped of comments and addresses, along with their
+/// line numbers. Every generated line keeps the number of the macro it comes
+/// from.
+///
+/// The following macros are understood, using r2 as the stack pointer and
+/// r3 as a scratch register:
+///
+/// ```text
+///     push rX
+///     pop rX
+///     call label
+///     ret
+///     if rX != 0 {        ; or rX == 0
+///     } else {
+///     }
+///     while rX != 0 {     ; or rX == 0
+///     }
+/// ```
+///
+/// The expansions only use the opcodes of the original machine, and the
+/// labels they define are numbered the same way as in the listings. They
+/// start with a `.`, such as `.ite_then_1` or `.return_from_print_3`, which
+/// the labels of the source cannot, so that they never clash.
+pub(crate) fn expand(lines: Vec<(usize, String)>) -> Result<Vec<(usize, String)>, AsmError> {
+    let mut stack: Vec<(Open, Vec<Node>)> = vec![];
+    let mut current: Vec<Node> = vec![];
+
+    for (line, text) in lines {
+        let err = |message: String| AsmError { line, message };
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words[..] {
+            [kw @ ("if" | "while"), reg, op @ ("!=" | "=="), "0", "{"] => {
+                check_reg(reg).map_err(err)?;
+                let reg = reg.to_string();
+                let open = match kw {
+                    "if" => Open::If { line, reg, negated: op == "==", then: vec![], otherwise: None },
+                    _ => Open::While { line, reg, nonzero: op == "!=" },
+                };
+                stack.push((open, std::mem::take(&mut current)));
+            }
+            ["}", "else", "{"] => match stack.last_mut() {
+                Some((Open::If { then, otherwise: otherwise @ None, .. }, _)) => {
+                    *then = std::mem::take(&mut current);
+                    *otherwise = Some(vec![]);
+                }
+                _ => return Err(err("`else` without a matching `if`".to_string())),
+            },
+            ["}"] => {
+                let (open, outer) = stack.pop().ok_or_else(|| err("unmatched `}`".to_string()))?;
+                let body = std::mem::replace(&mut current, outer);
+                current.push(match open {
+                    Open::If { line, reg, negated, then, otherwise } => {
+                        let (then, otherwise) = match otherwise {
+                            Some(_) => (then, body),
+                            None => (body, vec![]),
+                        };
+                        let (nonzero, zero) = if negated { (otherwise, then) } else { (then, otherwise) };
+                        Node::If { line, reg, nonzero, zero }
+                    }
+                    Open::While { line, reg, nonzero } => Node::While { line, reg, nonzero, body },
+                });
+            }
+            _ => current.push(Node::Line(line, text)),
+        }
+    }
+    if let Some((open, _)) = stack.last() {
+        let line = match open {
+            Open::If { line, .. } | Open::While { line, .. } => *line,
+        };
+        return Err(AsmError { line, message: "missing `}`".to_string() });
+    }
+
+    let mut expander = Expander { counters: HashMap::new(), out: vec![] };
+    expander.nodes(current)?;
+    Ok(expander.out)
+}
+
+fn check_reg(reg: &str) -> Result<(), String> {
+    if reg == SCRATCH {
+        return Err(format!("{SCRATCH} is used as a scratch register by the macros"));
+    }
+    Ok(())
+}
+
+struct Expander {
+    /// Last number given to each kind of generated label.
+    counters: HashMap<String, usize>,
+    out: Vec<(usize, String)>,
+}
+
+impl Expander {
+    fn fresh(&mut self, kind: &str) -> usize {
+        let counter = self.counters.entry(kind.to_string()).or_default();
+        *counter += 1;
+        *counter
+    }
+
+    fn nodes(&mut self, nodes: Vec<Node>) -> Result<(), AsmError> {
+        for node in nodes {
+            match node {
+                Node::Line(line, text) => self.line(line, &text)?,
+                Node::If { line, reg, nonzero, zero } => {
+                    let n = self.fresh("ite");
+                    if nonzero.is_empty() {
+                        self.emit(line, &[
+                            format!("loadimm {SCRATCH} <- #.ite_end_{n}"),
+                            format!("move r0 <- {SCRATCH} if {reg} != 0"),
+                        ]);
+                        self.nodes(zero)?;
+                    } else {
+                        self.emit(line, &[
+                            format!("loadimm {SCRATCH} <- #.ite_then_{n}"),
+                            format!("move r0 <- {SCRATCH} if {reg} != 0"),
+                        ]);
+                        self.nodes(zero)?;
+                        self.emit(line, &[format!("loadimm r0 <- #.ite_end_{n}"), format!(".ite_then_{n}:")]);
+                        self.nodes(nonzero)?;
+                    }
+                    self.emit(line, &[format!(".ite_end_{n}:")]);
+                }
+                Node::While { line, reg, nonzero, body } => {
+                    let n = self.fresh("while");
+                    self.emit(line, &[format!(".while_start_{n}:")]);
+                    if nonzero {
+                        self.emit(line, &[
+                            format!("loadimm {SCRATCH} <- #.while_body_{n}"),
+                            format!("move r0 <- {SCRATCH} if {reg} != 0"),
+                            format!("loadimm r0 <- #.while_end_{n}"),
+                            format!(".while_body_{n}:"),
+                        ]);
+                    } else {
+                        self.emit(line, &[
+                            format!("loadimm {SCRATCH} <- #.while_end_{n}"),
+                            format!("move r0 <- {SCRATCH} if {reg} != 0"),
+                        ]);
+                    }
+                    self.nodes(body)?;
+                    self.emit(line, &[format!("loadimm r0 <- #.while_start_{n}"), format!(".while_end_{n}:")]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand the macros which fit on a single line, and copy the other
+    /// lines as they are.
+    fn line(&mut self, line: usize, text: &str) -> Result<(), AsmError> {
+        let err = |message: String| AsmError { line, message };
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words[..] {
+            ["push", reg] => {
+                check_reg(reg).map_err(err)?;
+                self.emit(line, &[
+                    format!("loadimm {SCRATCH} <- #4"),
+                    format!("sub r2 <- r2 - {SCRATCH}"),
+                    format!("store [r2] <- {reg}"),
+                ]);
+            }
+            ["pop", reg] => self.pop(line, reg),
+            ["ret"] => self.pop(line, "r0"),
+            ["call", label] => {
+                let n = self.fresh(&format!("return_from_{label}"));
+                self.emit(line, &[
+                    format!("loadimm {SCRATCH} <- #4"),
+                    format!("sub r2 <- r2 - {SCRATCH}"),
+                    format!("loadimm {SCRATCH} <- #.return_from_{label}_{n}"),
+                    format!("store [r2] <- {SCRATCH}"),
+                    format!("loadimm r0 <- #{label}"),
+                    format!(".return_from_{label}_{n}:"),
+                ]);
+            }
+            ["push" | "pop" | "call" | "ret", ..] => return Err(err(format!("cannot parse `{text}`"))),
+            _ => self.out.push((line, text.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Unlike the other macros, `pop r3` is allowed, the scratch register
+    /// being overwritten by the popped value last.
+    fn pop(&mut self, line: usize, reg: &str) {
+        self.emit(line, &[
+            format!("loadimm {SCRATCH} <- #-4"),
+            format!("sub r2 <- r2 - {SCRATCH}"),
+            format!("loadimm {SCRATCH} <- #4"),
+            format!("sub {SCRATCH} <- r2 - {SCRATCH}"),
+            format!("load {reg} <- [{SCRATCH}]"),
+        ]);
+    }
+
+    fn emit(&mut self, line: usize, texts: &[String]) {
+        self.out.extend(texts.iter().map(|text| (line, text.clone())));
+    }
+}
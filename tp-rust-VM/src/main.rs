@@ -1,19 +1,273 @@
-use interpreter::{Machine, MachineError};
+use interpreter::{
+    assemble, bf2vm, compile, decompile, link, optimize, vm2rust, Backtrace, Cfg, Isa, Machine, MachineError, ObjectFile,
+    RangeAnalysis, StackAnalysis, SymbolTable, MEMORY_SIZE,
+};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Read;
 
-fn main() -> Result<(), MachineError> {
-    // Take a filename as argument on the command line
-    let filename = std::env::args().nth(1).unwrap();
-
-    // Read content to buffer
-    let mut fs = File::open(&filename).unwrap();
+fn read_file(filename: &str) -> Vec<u8> {
+    let mut fs = File::open(filename).unwrap();
     let mut buffer = Vec::new();
     fs.read_to_end(&mut buffer).unwrap();
+    buffer
+}
+
+/// Unwrap `result`, or print its error about `filename` and exit.
+fn or_exit<T, E: std::fmt::Display>(filename: &str, result: Result<T, E>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{filename}: {e}");
+        std::process::exit(1);
+    })
+}
+
+/// Load either an object file or a raw memory image read from `filename`,
+/// returning the memory image and the entry point.
+fn load_program(filename: &str, buffer: &[u8]) -> (Vec<u8>, u32) {
+    if ObjectFile::is_object(buffer) {
+        let object = or_exit(filename, ObjectFile::from_bytes(buffer));
+        (or_exit(filename, object.image()), object.entry)
+    } else {
+        (buffer.to_vec(), 0)
+    }
+}
+
+/// Parse an address, given either as a number or as a symbol.
+fn parse_address(word: &str, symbols: Option<&SymbolTable>) -> Option<usize> {
+    match word.parse() {
+        Ok(addr) => Some(addr),
+        Err(_) => Some(symbols?.address_of(word)? as usize),
+    }
+}
+
+/// Parse a `FUNCTION=DEPTH` bound, the function being a symbol or an
+/// address.
+fn parse_bound(bound: &str, symbols: Option<&SymbolTable>) -> Option<(usize, u32)> {
+    let (function, depth) = bound.split_once('=')?;
+    Some((parse_address(function, symbols)?, depth.parse().ok()?))
+}
+
+/// Run a program, after `setup` configured the machine.
+fn run_file(filename: &str, setup: impl FnOnce(&mut Machine, Option<&SymbolTable>)) -> Result<(), MachineError> {
+    // Read content to buffer
+    let buffer = read_file(filename);
 
     // Create a machine with this memory content
-    let mut machine = Machine::new(&buffer);
+    let (mut machine, symbols) = if ObjectFile::is_object(&buffer) {
+        let symbols = or_exit(filename, ObjectFile::from_bytes(&buffer)).symbol_table();
+        (or_exit(filename, Machine::load_object(&buffer)), Some(symbols))
+    } else {
+        (Machine::new(&buffer), None)
+    };
+    setup(&mut machine, symbols.as_ref());
+
+    // Run the machine until the end, showing the call chain on error
+    let result = machine.run();
+    for report in machine.sanitizer_reports() {
+        eprintln!("uninitialized read of {:04} by load at {:04}", report.addr, report.ip);
+    }
+    if let Err(e) = &result {
+        eprintln!("error: {e:?}");
+        eprint!("{}", Backtrace::capture(&machine).format(symbols.as_ref()));
+    }
+    result
+}
 
-    // Run the machine until the end
-    machine.run()
+fn main() -> Result<(), MachineError> {
+    // Take a filename as argument on the command line, optionally preceded
+    // by a command:
+    //   --cfg FILE          print the control-flow graph in DOT format
+    //   --decompile FILE    print C-like pseudo-code
+    //   --check FILE        warn about memory accesses and jumps which
+    //                       cannot be proven valid
+    //   --stack FILE [FUNCTION=DEPTH]...
+    //                       print the worst-case stack usage, given the
+    //                       maximum depth of the recursive functions
+    //   --asm SOURCE OUTPUT assemble a listing into an object file
+    //   --link OUTPUT OBJECT... link object files into an executable one
+    //   --opt INPUT OUTPUT  optimize an object file
+    //   --rust FILE OUTPUT  translate a program into a Rust module
+    //   --sanitize FILE     run and report reads of uninitialized memory
+    //   --float FILE        run with the floating-point instructions
+    //   --traps TABLE FILE  run with the fault-vector table at TABLE, an
+    //                       address or a symbol
+    //   --banks BANK,... FILE
+    //                       run with the raw images BANK, ... loaded into
+    //                       banks 0, 1, ...
+    //   --supervisor TABLE FILE
+    //                       run in supervisor mode with paging, and with
+    //                       the fault-vector table at TABLE
+    //   --bf FILE           translate a Brainfuck program and run it on
+    //                       the standard input
+    //   --compile [--isa extended] SOURCE OUTPUT
+    //                       compile a program into an object file
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match &args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["--cfg", filename] => {
+            let (image, entry) = load_program(filename, &read_file(filename));
+            print!("{}", Cfg::build(&image, entry as usize).to_dot());
+            Ok(())
+        }
+        ["--decompile", filename] => {
+            let buffer = read_file(filename);
+            let (image, entry) = load_program(filename, &buffer);
+            let symbols = ObjectFile::from_bytes(&buffer).ok().map(|object| object.symbol_table());
+            print!("{}", decompile(&image, entry, symbols.as_ref()));
+            Ok(())
+        }
+        ["--check", filename] => {
+            let (image, entry) = load_program(filename, &read_file(filename));
+            let analysis = RangeAnalysis::run(&image, entry as usize);
+            for warning in analysis.warnings() {
+                eprintln!("warning: {warning}");
+            }
+            Ok(())
+        }
+        ["--stack", filename, bounds @ ..] => {
+            let buffer = read_file(filename);
+            let (image, entry) = load_program(filename, &buffer);
+            let symbols = ObjectFile::from_bytes(&buffer).ok().map(|object| object.symbol_table());
+            let mut recursion = BTreeMap::new();
+            for bound in bounds {
+                match parse_bound(bound, symbols.as_ref()) {
+                    Some((function, depth)) => recursion.insert(function, depth),
+                    None => {
+                        eprintln!("invalid bound `{bound}`, expected FUNCTION=DEPTH");
+                        std::process::exit(1);
+                    }
+                };
+            }
+            let analysis = StackAnalysis::run(&image, entry as usize, &recursion);
+            for function in analysis.functions() {
+                let name = match &symbols {
+                    Some(symbols) => symbols.describe(function.entry as u32),
+                    None => format!("{:04}", function.entry),
+                };
+                match function.total {
+                    Some(total) => println!("{name}: {} bytes, {total} with callees", function.frame),
+                    None => println!("{name}: {} bytes, unbounded with callees", function.frame),
+                }
+            }
+            for issue in analysis.issues() {
+                eprintln!("warning: {issue}");
+            }
+            if let Some(lowest) = analysis.lowest() {
+                println!("lowest stack address: {lowest}, code and data end at {}", image.len());
+            }
+            if !analysis.fits() {
+                eprintln!("error: the stack may grow into the code and data");
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        ["--asm", source, output] => {
+            let source = std::fs::read_to_string(source).unwrap();
+            match assemble(&source) {
+                Ok(object) => std::fs::write(output, object.to_bytes()).unwrap(),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+            Ok(())
+        }
+        ["--link", output, inputs @ ..] if !inputs.is_empty() => {
+            let objects: Vec<ObjectFile> =
+                inputs.iter().map(|input| or_exit(input, ObjectFile::from_bytes(&read_file(input)))).collect();
+            match link(&objects) {
+                Ok(object) => std::fs::write(output, object.to_bytes()).unwrap(),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+            Ok(())
+        }
+        ["--opt", input, output] => {
+            let object = or_exit(input, ObjectFile::from_bytes(&read_file(input)));
+            std::fs::write(output, optimize(&object).to_bytes()).unwrap();
+            Ok(())
+        }
+        ["--rust", filename, output] => {
+            let (image, entry) = load_program(filename, &read_file(filename));
+            std::fs::write(output, vm2rust(&image, entry)).unwrap();
+            Ok(())
+        }
+        ["--compile", rest @ ..] => {
+            let (isa, source, output) = match rest {
+                [source, output] => (Isa::Base, source, output),
+                ["--isa", "extended", source, output] => (Isa::Extended, source, output),
+                _ => {
+                    eprintln!("usage: tp-rust-vm --compile [--isa extended] SOURCE OUTPUT");
+                    std::process::exit(1);
+                }
+            };
+            let source = std::fs::read_to_string(source).unwrap();
+            let object = compile(&source, isa).map_err(|e| e.to_string()).and_then(|asm| {
+                assemble(&asm).map_err(|e| e.to_string())
+            });
+            match object {
+                Ok(object) => std::fs::write(output, object.to_bytes()).unwrap(),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+            Ok(())
+        }
+        ["--bf", filename] => {
+            let source = std::fs::read_to_string(filename).unwrap();
+            let image = bf2vm(&source)
+                .map_err(|e| e.to_string())
+                .and_then(|object| object.image().map_err(|e| e.to_string()));
+            let image = match image {
+                Ok(image) => image,
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            };
+            let mut machine = Machine::new(&image);
+            let result = machine.run_with(&mut std::io::stdin().lock(), &mut std::io::stdout().lock());
+            if let Err(e) = &result {
+                eprintln!("error: {e:?}");
+            }
+            result
+        }
+        [filename] => run_file(filename, |_, _| ()),
+        ["--sanitize", filename] => run_file(filename, |machine, _| machine.enable_sanitizer()),
+        ["--float", filename] => run_file(filename, |machine, _| machine.enable_float()),
+        ["--banks", banks, filename] => run_file(filename, |machine, _| {
+            let banks: Vec<&str> = banks.split(',').collect();
+            machine.enable_banks(banks.len());
+            for (index, bank) in banks.into_iter().enumerate() {
+                let data = read_file(bank);
+                match machine.bank_mut(index).unwrap().get_mut(..data.len()) {
+                    Some(content) => content.copy_from_slice(&data),
+                    None => {
+                        eprintln!("bank `{bank}` is larger than {MEMORY_SIZE} bytes");
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }),
+        [mode @ ("--traps" | "--supervisor"), table, filename] => {
+            run_file(filename, |machine, symbols| match parse_address(table, symbols) {
+                Some(table) => {
+                    machine.enable_traps(table as u32);
+                    if *mode == "--supervisor" {
+                        machine.enable_paging();
+                    }
+                }
+                None => {
+                    eprintln!("invalid trap table `{table}`, expected an address or a symbol");
+                    std::process::exit(1);
+                }
+            })
+        }
+        _ => {
+            eprintln!("usage: tp-rust-vm [--cfg FILE | --decompile FILE | --check FILE | --stack FILE [FUNCTION=DEPTH]... | --asm SOURCE OUTPUT | --link OUTPUT OBJECT... | --opt INPUT OUTPUT | --rust FILE OUTPUT | --compile [--isa extended] SOURCE OUTPUT | --bf FILE | [--sanitize | --float | --banks BANK,... | --traps TABLE | --supervisor TABLE] FILE]");
+            std::process::exit(1);
+        }
+    }
 }
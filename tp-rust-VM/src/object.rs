@@ -0,0 +1,357 @@
+use crate::MEMORY_SIZE;
+use std::fmt;
+
+/// Magic number found at the beginning of every object file.
+pub const OBJECT_MAGIC: [u8; 4] = *b"VMOB";
+
+/// Current version of the object file format.
+pub const OBJECT_VERSION: u16 = 2;
+
+/// Maximum length in bytes of the names of sections and symbols, which are
+/// prefixed by their length on one byte.
+pub const MAX_NAME_LEN: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Code,
+    Data,
+}
+
+/// Bytes to be copied at a given address of the machine memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub kind: SectionKind,
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+/// A named address inside a section. Global symbols are visible from other
+/// object files when linking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    pub section: u16,
+    pub global: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+    /// The 16-bit immediate of a `loadimm` instruction.
+    Imm16,
+    /// A 32-bit little-endian word.
+    Word32,
+    /// The 16-bit offset of a relative jump or branch, counted from the end
+    /// of the field, which is also the end of the instruction.
+    Rel16,
+}
+
+/// A field of a section which holds the address of a symbol, and must be
+/// patched when the symbol moves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: u16,
+    /// Offset of the field from the beginning of the section.
+    pub offset: u32,
+    pub kind: RelocationKind,
+    pub symbol: String,
+}
+
+/// Association between an address and the source line it comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineInfo {
+    pub addr: u32,
+    pub line: u32,
+}
+
+/// A relocatable program: sections with their load addresses, an entry
+/// point, a symbol table, optional line information and the relocations
+/// needed to move sections or to resolve symbols from other object files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectFile {
+    pub entry: u32,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub lines: Vec<LineInfo>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ObjectError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidName,
+    InvalidSectionKind(u8),
+    InvalidRelocationKind(u8),
+    InvalidSectionIndex(u16),
+    InvalidRelocationOffset(u32),
+    SymbolOutsideSection(String),
+    SectionOutOfMemory(String),
+    UnresolvedSymbol(String),
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectError::BadMagic => write!(f, "not an object file"),
+            ObjectError::UnsupportedVersion(v) => write!(f, "unsupported object file version {v}"),
+            ObjectError::Truncated => write!(f, "truncated object file"),
+            ObjectError::InvalidName => write!(f, "invalid name in object file"),
+            ObjectError::InvalidSectionKind(k) => write!(f, "invalid section kind {k}"),
+            ObjectError::InvalidRelocationKind(k) => write!(f, "invalid relocation kind {k}"),
+            ObjectError::InvalidSectionIndex(i) => write!(f, "invalid section index {i}"),
+            ObjectError::InvalidRelocationOffset(o) => write!(f, "relocation offset {o} is outside its section"),
+            ObjectError::SymbolOutsideSection(name) => write!(f, "symbol {name} is outside its section"),
+            ObjectError::SectionOutOfMemory(name) => write!(f, "section {name} does not fit in memory"),
+            ObjectError::UnresolvedSymbol(name) => write!(f, "unresolved symbol {name}"),
+        }
+    }
+}
+
+impl ObjectFile {
+    /// Check if `bytes` looks like an object file rather than a raw image.
+    pub fn is_object(bytes: &[u8]) -> bool {
+        bytes.starts_with(&OBJECT_MAGIC)
+    }
+
+    /// Serialize the object file. All integers are stored in little-endian
+    /// order, names are prefixed by their length on one byte.
+    ///
+    /// # Panics
+    /// This function panics when a name is longer than [MAX_NAME_LEN] bytes,
+    /// which the assembler rejects.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = OBJECT_MAGIC.to_vec();
+        out.extend(OBJECT_VERSION.to_le_bytes());
+        out.extend(self.entry.to_le_bytes());
+
+        out.extend((self.sections.len() as u16).to_le_bytes());
+        for section in &self.sections {
+            out.push(match section.kind {
+                SectionKind::Code => 0,
+                SectionKind::Data => 1,
+            });
+            put_name(&mut out, &section.name);
+            out.extend(section.addr.to_le_bytes());
+            out.extend((section.data.len() as u32).to_le_bytes());
+            out.extend(&section.data);
+        }
+
+        out.extend((self.symbols.len() as u32).to_le_bytes());
+        for symbol in &self.symbols {
+            put_name(&mut out, &symbol.name);
+            out.extend(symbol.addr.to_le_bytes());
+            out.extend(symbol.section.to_le_bytes());
+            out.push(symbol.global as u8);
+        }
+
+        out.extend((self.lines.len() as u32).to_le_bytes());
+        for line in &self.lines {
+            out.extend(line.addr.to_le_bytes());
+            out.extend(line.line.to_le_bytes());
+        }
+
+        out.extend((self.relocations.len() as u32).to_le_bytes());
+        for reloc in &self.relocations {
+            out.extend(reloc.section.to_le_bytes());
+            out.extend(reloc.offset.to_le_bytes());
+            out.push(match reloc.kind {
+                RelocationKind::Imm16 => 0,
+                RelocationKind::Word32 => 1,
+                RelocationKind::Rel16 => 2,
+            });
+            put_name(&mut out, &reloc.symbol);
+        }
+        out
+    }
+
+    /// Parse an object file produced by [to_bytes](ObjectFile::to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectFile, ObjectError> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(4)? != OBJECT_MAGIC {
+            return Err(ObjectError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != OBJECT_VERSION {
+            return Err(ObjectError::UnsupportedVersion(version));
+        }
+        let mut object = ObjectFile { entry: r.u32()?, ..Default::default() };
+
+        for _ in 0..r.u16()? {
+            let kind = match r.u8()? {
+                0 => SectionKind::Code,
+                1 => SectionKind::Data,
+                k => return Err(ObjectError::InvalidSectionKind(k)),
+            };
+            let name = r.name()?;
+            let addr = r.u32()?;
+            let len = r.u32()? as usize;
+            let data = r.take(len)?.to_vec();
+            object.sections.push(Section { name, kind, addr, data });
+        }
+
+        for _ in 0..r.u32()? {
+            let name = r.name()?;
+            let addr = r.u32()?;
+            let section = r.u16()?;
+            let global = r.u8()? != 0;
+            object.symbols.push(Symbol { name, addr, section, global });
+        }
+
+        for _ in 0..r.u32()? {
+            object.lines.push(LineInfo { addr: r.u32()?, line: r.u32()? });
+        }
+
+        for _ in 0..r.u32()? {
+            let section = r.u16()?;
+            let offset = r.u32()?;
+            let kind = match r.u8()? {
+                0 => RelocationKind::Imm16,
+                1 => RelocationKind::Word32,
+                2 => RelocationKind::Rel16,
+                k => return Err(ObjectError::InvalidRelocationKind(k)),
+            };
+            let symbol = r.name()?;
+            object.relocations.push(Relocation { section, offset, kind, symbol });
+        }
+
+        // Symbols and relocations must refer to existing sections, symbols
+        // being inside theirs or at its end
+        let section = |index: u16| object.sections.get(index as usize).ok_or(ObjectError::InvalidSectionIndex(index));
+        for symbol in &object.symbols {
+            let section = section(symbol.section)?;
+            let offset = symbol.addr.checked_sub(section.addr);
+            if offset.is_none_or(|offset| offset as usize > section.data.len()) {
+                return Err(ObjectError::SymbolOutsideSection(symbol.name.clone()));
+            }
+        }
+        for reloc in &object.relocations {
+            let width = match reloc.kind {
+                RelocationKind::Imm16 | RelocationKind::Rel16 => 2,
+                RelocationKind::Word32 => 4,
+            };
+            if reloc.offset as usize + width > section(reloc.section)?.data.len() {
+                return Err(ObjectError::InvalidRelocationOffset(reloc.offset));
+            }
+        }
+        Ok(object)
+    }
+
+    /// Memory image obtained by copying every section at its load address.
+    /// The image stops after the last byte of the last section.
+    ///
+    /// Relocations against symbols which are not defined in this object
+    /// file must have been resolved by the linker first.
+    pub fn image(&self) -> Result<Vec<u8>, ObjectError> {
+        if let Some(reloc) = self.relocations.iter().find(|r| self.symbol(&r.symbol).is_none()) {
+            return Err(ObjectError::UnresolvedSymbol(reloc.symbol.clone()));
+        }
+        let mut image = vec![];
+        for section in &self.sections {
+            let start = section.addr as usize;
+            let end = start + section.data.len();
+            if end > MEMORY_SIZE {
+                return Err(ObjectError::SectionOutOfMemory(section.name.clone()));
+            }
+            if image.len() < end {
+                image.resize(end, 0);
+            }
+            image[start..end].copy_from_slice(&section.data);
+        }
+        Ok(image)
+    }
+
+    /// Address of the symbol called `name`.
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.addr)
+    }
+
+    /// Symbol table sorted by address, to symbolize addresses.
+    pub fn symbol_table(&self) -> SymbolTable {
+        SymbolTable::new(self.symbols.clone())
+    }
+
+    /// Source line which produced the byte at `addr`.
+    pub fn line_of(&self, addr: u32) -> Option<u32> {
+        self.lines.iter().rev().find(|l| l.addr <= addr).map(|l| l.line)
+    }
+}
+
+/// Symbols sorted by address.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|s| s.addr);
+        SymbolTable { symbols }
+    }
+
+    /// Closest symbol located at or before `addr`, along with the offset of
+    /// `addr` from this symbol.
+    pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+        let idx = self.symbols.partition_point(|s| s.addr <= addr);
+        let symbol = &self.symbols[idx.checked_sub(1)?];
+        Some((&symbol.name, addr - symbol.addr))
+    }
+
+    /// Symbolic representation of `addr`, such as `afact_loop` or
+    /// `mult+8`, or the address itself when no symbol precedes it.
+    pub fn describe(&self, addr: u32) -> String {
+        match self.lookup(addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{name}+{offset}"),
+            None => format!("{addr:04}"),
+        }
+    }
+
+    /// Names of all the symbols located exactly at `addr`.
+    pub fn names_at(&self, addr: u32) -> impl Iterator<Item = &str> {
+        self.symbols.iter().filter(move |s| s.addr == addr).map(|s| s.name.as_str())
+    }
+
+    /// Address of the symbol called `name`.
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.addr)
+    }
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+    assert!(name.len() <= MAX_NAME_LEN, "The name `{name}` is longer than {MAX_NAME_LEN} bytes");
+    out.push(name.len() as u8);
+    out.extend(name.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ObjectError> {
+        let slice = self.bytes.get(self.pos..self.pos + n).ok_or(ObjectError::Truncated)?;
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjectError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn name(&mut self) -> Result<String, ObjectError> {
+        let len = self.u8()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| ObjectError::InvalidName)
+    }
+}
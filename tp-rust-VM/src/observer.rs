@@ -0,0 +1,71 @@
+use crate::Instruction;
+use core::any::Any;
+
+/// Callbacks invoked by a [Machine](crate::Machine) while it runs, to build
+/// tracers, profilers, coverage tools or checkers outside of the
+/// interpreter. Every callback does nothing by default.
+///
+/// Observers are attached with [attach](crate::Machine::attach), and are
+/// notified in the order they were attached. A machine without observers
+/// does not decode instructions nor record old values for them.
+pub trait MachineObserver: Any + Send {
+    /// The instruction `instr` located at `ip` is about to run. This is not
+    /// called when the next instruction cannot be decoded, in which case the
+    /// step fails.
+    fn on_fetch(&mut self, _ip: u32, _instr: &Instruction) {}
+
+    /// An instruction changed `reg` from `old` to `new`. The increment of
+    /// the IP done by every instruction is not reported, but jumps are.
+    fn on_reg_write(&mut self, _reg: usize, _old: u32, _new: u32) {}
+
+    /// An instruction read `data` at `addr`.
+    fn on_mem_read(&mut self, _addr: u32, _data: &[u8]) {}
+
+    /// An instruction wrote `data` at `addr`.
+    fn on_mem_write(&mut self, _addr: u32, _data: &[u8]) {}
+
+    /// An output instruction printed `bytes`.
+    fn on_output(&mut self, _bytes: &[u8]) {}
+}
+
+/// Observers attached to a machine. Without the `std` feature, none can be
+/// attached and notifying them does nothing.
+#[derive(Default)]
+pub(crate) struct Observers {
+    #[cfg(feature = "std")]
+    list: Vec<Box<dyn MachineObserver>>,
+}
+
+impl Observers {
+    pub(crate) fn is_empty(&self) -> bool {
+        #[cfg(feature = "std")]
+        return self.list.is_empty();
+        #[cfg(not(feature = "std"))]
+        return true;
+    }
+
+    /// Call `event` on every observer, in the order they were attached.
+    pub(crate) fn notify(&mut self, mut event: impl FnMut(&mut dyn MachineObserver)) {
+        #[cfg(feature = "std")]
+        for observer in &mut self.list {
+            event(observer.as_mut());
+        }
+        #[cfg(not(feature = "std"))]
+        let _ = &mut event;
+    }
+}
+
+#[cfg(feature = "std")]
+impl Observers {
+    pub(crate) fn push(&mut self, observer: Box<dyn MachineObserver>) {
+        self.list.push(observer);
+    }
+
+    pub(crate) fn find<O: MachineObserver>(&self) -> Option<&O> {
+        self.list.iter().find_map(|observer| (observer.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    pub(crate) fn find_mut<O: MachineObserver>(&mut self) -> Option<&mut O> {
+        self.list.iter_mut().find_map(|observer| (observer.as_mut() as &mut dyn Any).downcast_mut())
+    }
+}
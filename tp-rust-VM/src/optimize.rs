@@ -0,0 +1,467 @@
+use crate::{Cfg, Instruction, LineInfo, ObjectFile, Relocation, RelocationKind, SectionKind};
+use std::collections::{BTreeMap, BTreeSet};
+
+const IP: u8 = 0;
+const SP: u8 = 2;
+const SCRATCH: u8 = 3;
+
+/// Reference to a symbol from the field of an instruction.
+#[derive(Debug, Clone)]
+struct Reloc {
+    kind: RelocationKind,
+    symbol: String,
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Instr(Instruction, Option<Reloc>),
+    /// Bytes which are not reached from the entry point, such as strings,
+    /// with the relocations they contain and their offset.
+    Raw(Vec<u8>, Vec<(usize, Reloc)>),
+}
+
+#[derive(Debug, Clone)]
+struct Located {
+    addr: usize,
+    item: Item,
+    /// Whether control may reach this item from elsewhere than the previous
+    /// one, in which case no pattern may span across it.
+    boundary: bool,
+}
+
+/// Peephole optimizer over the code section of an object file.
+///
+/// The instructions reachable from the entry point and from the global
+/// symbols are decoded, the other bytes of the section are kept as they are.
+/// The following rewrites are then applied until none matches:
+///
+/// - a jump to the next instruction is removed,
+/// - a push of `rX` directly followed by a pop into `rY` becomes
+///   `move rY <- rX if r0 != 0`, or nothing when both are the same register,
+/// - two consecutive adjustments of the same register by constants, such as
+///   `loadimm r3 <- #4; sub r2 <- r2 - r3`, are folded into one, if their sum
+///   fits in `loadimm` or if the code already uses the extended instructions,
+/// - the load of a constant already held by the register is removed,
+/// - writes to registers which are overwritten before being read are removed.
+///
+/// Symbols, line information and every relocated field are moved along with
+/// the code, so the object file can still be linked. Addresses written as
+/// plain numbers are not updated, so labels must be used for every code
+/// address. The memory below r2 is considered free, and registers are only
+/// considered dead when overwritten before the next jump, so the final state
+/// of the registers is preserved.
+pub fn optimize(object: &ObjectFile) -> ObjectFile {
+    let mut object = object.clone();
+    while let Some(optimized) = pass(&object) {
+        object = optimized;
+    }
+    object
+}
+
+/// Run every rewrite once, returning `None` when nothing changed or when the
+/// object cannot be optimized safely.
+fn pass(object: &ObjectFile) -> Option<ObjectFile> {
+    let text = object.sections.iter().position(|s| s.kind == SectionKind::Code)?;
+    let items = decode(object, text)?;
+    let output = rewrite(object, &items);
+    if output.iter().all(Option::is_none) {
+        return None;
+    }
+    Some(relayout(object, text, &items, &output))
+}
+
+fn decode(object: &ObjectFile, text: usize) -> Option<Vec<Located>> {
+    let section = &object.sections[text];
+    let base = section.addr as usize;
+    let end = base + section.data.len();
+    let mut code = vec![0; base];
+    code.extend(&section.data);
+
+    let mut roots = vec![object.entry as usize];
+    roots.extend(object.symbols.iter().filter(|s| s.global && s.section as usize == text).map(|s| s.addr as usize));
+    let mut instrs = BTreeMap::new();
+    let mut boundaries: BTreeSet<usize> = object.symbols.iter().map(|s| s.addr as usize).collect();
+    for root in roots.into_iter().filter(|root| (base..end).contains(root)) {
+        let cfg = Cfg::build(&code, root);
+        for block in cfg.blocks() {
+            boundaries.insert(block.start);
+            instrs.extend(block.instructions.iter().copied());
+        }
+    }
+    for (&addr, instr) in &instrs {
+        if let Some(target) = instr.relative_target(addr) {
+            boundaries.insert(target);
+        }
+    }
+
+    let mut relocs: BTreeMap<usize, Reloc> = object
+        .relocations
+        .iter()
+        .filter(|r| r.section as usize == text)
+        .map(|r| (base + r.offset as usize, Reloc { kind: r.kind, symbol: r.symbol.clone() }))
+        .collect();
+
+    let mut items: Vec<Located> = vec![];
+    let mut addr = base;
+    while addr < end {
+        let boundary = boundaries.contains(&addr);
+        match instrs.get(&addr) {
+            Some(&instr) if addr + instr.size() <= end => {
+                let size = instr.size();
+                let mut fields = relocs.range(addr..addr + size);
+                let reloc = match (fields.next(), fields.next()) {
+                    (None, _) => None,
+                    (Some((&field, reloc)), None) if field == addr + size - relocated_width(reloc.kind) => {
+                        let expected = matches!(
+                            (instr, reloc.kind),
+                            (Instruction::LoadImm { .. }, RelocationKind::Imm16)
+                                | (Instruction::LoadImm32 { .. }, RelocationKind::Word32)
+                                | (Instruction::Jump { .. } | Instruction::Branch { .. }, RelocationKind::Rel16)
+                        );
+                        if !expected {
+                            return None;
+                        }
+                        relocs.remove(&field)
+                    }
+                    // A relocation in the middle of an instruction
+                    _ => return None,
+                };
+                items.push(Located { addr, item: Item::Instr(instr, reloc), boundary });
+                addr += size;
+            }
+            Some(_) => return None,
+            None => {
+                let start = addr;
+                while addr < end && !instrs.contains_key(&addr) {
+                    addr += 1;
+                }
+                let fields = relocs.range(start..addr).map(|(&field, reloc)| (field - start, reloc.clone())).collect();
+                items.push(Located { addr: start, item: Item::Raw(code[start..addr].to_vec(), fields), boundary });
+            }
+        }
+    }
+    Some(items)
+}
+
+fn relocated_width(kind: RelocationKind) -> usize {
+    match kind {
+        RelocationKind::Imm16 | RelocationKind::Rel16 => 2,
+        RelocationKind::Word32 => 4,
+    }
+}
+
+/// Whether an instruction may transfer the control elsewhere than to the
+/// next one.
+fn is_control(instr: &Instruction) -> bool {
+    matches!(instr, Instruction::Exit | Instruction::Jump { .. } | Instruction::Branch { .. })
+        || instr.written_reg() == Some(IP)
+}
+
+/// Whether `reg` is overwritten before being read, starting at `items[from]`.
+/// Registers are considered live after a jump or at the end of the program.
+fn dead_from(items: &[Located], from: usize, reg: u8) -> bool {
+    for located in &items[from..] {
+        let Item::Instr(instr, _) = &located.item else {
+            return false;
+        };
+        if instr.read_regs().contains(&reg) || is_control(instr) {
+            return false;
+        }
+        let conditional = matches!(
+            instr,
+            Instruction::MoveIf { .. } | Instruction::MoveIfZero { .. } | Instruction::MoveIfNeg { .. }
+        );
+        if instr.written_reg() == Some(reg) && !conditional {
+            return true;
+        }
+    }
+    false
+}
+
+/// Old address of the target of a jump to a constant address.
+fn jump_target(object: &ObjectFile, addr: usize, instr: &Instruction, reloc: &Option<Reloc>) -> Option<usize> {
+    let local = |reloc: &Reloc| object.symbol(&reloc.symbol).map(|a| a as usize);
+    match (instr, reloc) {
+        (Instruction::LoadImm { dst: IP, .. } | Instruction::LoadImm32 { dst: IP, .. }, None) => {
+            instr.constant_load().map(|(_, value)| value as usize)
+        }
+        (Instruction::Jump { .. }, None) => instr.relative_target(addr),
+        (Instruction::LoadImm { dst: IP, .. } | Instruction::LoadImm32 { dst: IP, .. } | Instruction::Jump { .. }, Some(reloc)) => {
+            local(reloc)
+        }
+        _ => None,
+    }
+}
+
+/// Instructions of `items[i..i + n]`, when none of them but the first may be
+/// reached from elsewhere and none holds a relocation.
+fn window(items: &[Located], i: usize, n: usize) -> Option<Vec<Instruction>> {
+    let window = items.get(i..i + n)?;
+    window
+        .iter()
+        .enumerate()
+        .map(|(k, located)| match &located.item {
+            Item::Instr(instr, None) if k == 0 || !located.boundary => Some(*instr),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Load of `value` into `dst`, using `loadimm32` only when `extended` says
+/// that the program already needs the extended instruction set.
+fn load_constant(dst: u8, value: i64, extended: bool) -> Option<Instruction> {
+    match i16::try_from(value) {
+        Ok(imm) => Some(Instruction::LoadImm { dst, imm }),
+        Err(_) if extended => Some(Instruction::LoadImm32 { dst, imm: value as u32 }),
+        Err(_) => None,
+    }
+}
+
+/// Whether the code uses instructions other than the eight original ones.
+fn uses_extended(items: &[Located]) -> bool {
+    items.iter().any(|located| match &located.item {
+        Item::Instr(instr, _) => {
+            let mut code = vec![];
+            instr.encode(&mut code);
+            code[0] > 8
+        }
+        Item::Raw(..) => false,
+    })
+}
+
+/// Apply the rewrites in a single sweep. The result holds, for every item,
+/// the items replacing it: `None` when it is kept as is, and an empty list
+/// when it is removed.
+fn rewrite(object: &ObjectFile, items: &[Located]) -> Vec<Option<Vec<Item>>> {
+    let mut output: Vec<Option<Vec<Item>>> = vec![None; items.len()];
+    // Constants held by the registers in the optimized code
+    let mut known: [Option<u32>; 16] = [None; 16];
+    let extended = uses_extended(items);
+    let mut i = 0;
+    while i < items.len() {
+        if items[i].boundary {
+            known = [None; 16];
+        }
+        let (instr, reloc) = match &items[i].item {
+            Item::Instr(instr, reloc) => (*instr, reloc),
+            Item::Raw(..) => {
+                known = [None; 16];
+                i += 1;
+                continue;
+            }
+        };
+        let next = items[i].addr + instr.size();
+
+        // Jump to the next instruction
+        if jump_target(object, items[i].addr, &instr, reloc) == Some(next) {
+            output[i] = Some(vec![]);
+            i += 1;
+            continue;
+        }
+
+        // push rX; pop rY
+        if let Some(
+            [Instruction::LoadImm { dst: SCRATCH, imm: 4 }, Instruction::Sub { dst: SP, lhs: SP, rhs: SCRATCH }, Instruction::Store { addr: SP, src }, Instruction::LoadImm { dst: SCRATCH, imm: -4 }, Instruction::Sub { dst: SP, lhs: SP, rhs: SCRATCH }, Instruction::LoadImm { dst: SCRATCH, imm: 4 }, Instruction::Sub { dst: SCRATCH, lhs: SP, rhs: SCRATCH }, Instruction::Load { dst, addr: SCRATCH }],
+        ) = window(items, i, 8).as_deref()
+        {
+            let (src, dst) = (*src, *dst);
+            let valid = src != SP && src != SCRATCH && dst != SP && dst != IP && (src as usize) < 16 && (dst as usize) < 16;
+            if valid && (dst == SCRATCH || dead_from(items, i + 8, SCRATCH)) {
+                let moved = Instruction::MoveIf { dst, src, cond: IP };
+                output[i] = Some(if src == dst { vec![] } else { vec![Item::Instr(moved, None)] });
+                for slot in &mut output[i + 1..i + 8] {
+                    *slot = Some(vec![]);
+                }
+                known[dst as usize] = known[src as usize];
+                i += 8;
+                continue;
+            }
+        }
+
+        // loadimm rT <- #a; sub rS <- rS - rT; loadimm rT <- #b; sub rS <- rS - rT
+        if let Some(
+            [Instruction::LoadImm { dst: t1, imm: a }, Instruction::Sub { dst: s1, lhs: s2, rhs: t2 }, Instruction::LoadImm { dst: t3, imm: b }, Instruction::Sub { dst: s3, lhs: s4, rhs: t4 }],
+        ) = window(items, i, 4).as_deref()
+        {
+            let (t, s) = (*t1, *s1);
+            let same = [*t2, *t3, *t4] == [t; 3] && [*s2, *s3, *s4] == [s; 3];
+            let sum = *a as i64 + *b as i64;
+            let folded = match load_constant(t, sum, extended) {
+                _ if sum == 0 => Some(vec![]),
+                Some(load) => Some(vec![Item::Instr(load, None), Item::Instr(Instruction::Sub { dst: s, lhs: s, rhs: t }, None)]),
+                None => None,
+            };
+            let valid = same && t != s && t != IP && s != IP && (t as usize) < 16 && (s as usize) < 16;
+            if let Some(folded) = folded.filter(|_| valid && dead_from(items, i + 4, t)) {
+                output[i] = Some(folded);
+                for slot in &mut output[i + 1..i + 4] {
+                    *slot = Some(vec![]);
+                }
+                if sum != 0 {
+                    known[t as usize] = Some(sum as u32);
+                    known[s as usize] = None;
+                }
+                i += 4;
+                continue;
+            }
+        }
+
+        let writes = instr.written_reg().filter(|&reg| reg != IP && (reg as usize) < 16);
+
+        // Constant already loaded
+        if let (Some((dst, value)), None) = (instr.constant_load(), reloc) {
+            if dst != IP && known.get(dst as usize) == Some(&Some(value)) {
+                output[i] = Some(vec![]);
+                i += 1;
+                continue;
+            }
+        }
+
+        // Write to a dead register. Loads are kept as they may fault.
+        let pure = matches!(
+            instr,
+            Instruction::LoadImm { .. }
+                | Instruction::LoadImm32 { .. }
+                | Instruction::LoadHi { .. }
+                | Instruction::Sub { .. }
+                | Instruction::Compare { .. }
+                | Instruction::MoveIf { .. }
+                | Instruction::MoveIfZero { .. }
+                | Instruction::MoveIfNeg { .. }
+        );
+        if let Some(reg) = writes.filter(|_| pure) {
+            if dead_from(items, i + 1, reg) {
+                output[i] = Some(vec![]);
+                i += 1;
+                continue;
+            }
+        }
+
+        // Keep the instruction, and track the constants it leaves
+        match (instr.constant_load(), reloc, instr) {
+            (Some((dst, value)), None, _) if (dst as usize) < 16 => known[dst as usize] = Some(value),
+            (_, _, Instruction::Sub { dst, lhs, rhs }) if (dst.max(lhs).max(rhs) as usize) < 16 => {
+                known[dst as usize] = match (known[lhs as usize], known[rhs as usize]) {
+                    (Some(l), Some(r)) => Some(l.wrapping_sub(r)),
+                    _ => None,
+                }
+            }
+            _ => {
+                if let Some(reg) = writes {
+                    known[reg as usize] = None;
+                }
+            }
+        }
+        if is_control(&instr) {
+            known = [None; 16];
+        }
+        i += 1;
+    }
+    output
+}
+
+/// Lay the rewritten items out, and move the symbols, the relocations and
+/// the other sections accordingly.
+fn relayout(object: &ObjectFile, text: usize, items: &[Located], output: &[Option<Vec<Item>>]) -> ObjectFile {
+    let section = &object.sections[text];
+    let base = section.addr as usize;
+    let old_end = base + section.data.len();
+
+    // New address of every old item, and new content
+    let mut data = vec![];
+    let mut new_addrs = vec![];
+    let mut fields: Vec<(usize, Reloc)> = vec![];
+    for (located, replacement) in items.iter().zip(output) {
+        new_addrs.push(base + data.len());
+        let replacement = match replacement {
+            Some(items) => items.clone(),
+            None => vec![located.item.clone()],
+        };
+        for item in replacement {
+            match item {
+                Item::Instr(instr, reloc) => {
+                    let start = data.len();
+                    instr.encode(&mut data);
+                    if let Some(reloc) = reloc {
+                        fields.push((data.len() - relocated_width(reloc.kind), reloc));
+                    }
+                    debug_assert_eq!(start + instr.size(), data.len());
+                }
+                Item::Raw(bytes, relocs) => {
+                    let start = data.len();
+                    data.extend(&bytes);
+                    fields.extend(relocs.into_iter().map(|(offset, reloc)| (start + offset, reloc)));
+                }
+            }
+        }
+    }
+    let new_end = base + data.len();
+    let shift = |addr: usize| (addr as i64 - old_end as i64 + new_end as i64) as usize;
+
+    // Map an old address of the code section to the new one
+    let remap_text = |addr: usize| -> usize {
+        if addr >= old_end {
+            return shift(addr);
+        }
+        match items.partition_point(|l| l.addr <= addr).checked_sub(1) {
+            None => addr,
+            Some(idx) => match (&items[idx].item, &output[idx]) {
+                (Item::Raw(..), None) => new_addrs[idx] + addr - items[idx].addr,
+                _ => new_addrs[idx],
+            },
+        }
+    };
+    let remap = |section: usize, addr: u32| -> u32 {
+        let addr = addr as usize;
+        if section == text {
+            remap_text(addr) as u32
+        } else if object.sections[section].addr as usize >= old_end {
+            shift(addr) as u32
+        } else {
+            addr as u32
+        }
+    };
+
+    let mut optimized = object.clone();
+    optimized.sections[text].data = data;
+    for (k, section) in optimized.sections.iter_mut().enumerate() {
+        if k != text && section.addr as usize >= old_end {
+            section.addr = shift(section.addr as usize) as u32;
+        }
+    }
+    for symbol in &mut optimized.symbols {
+        symbol.addr = remap(symbol.section as usize, symbol.addr);
+    }
+    optimized.entry = remap(text, object.entry);
+    optimized.lines = object
+        .lines
+        .iter()
+        .map(|line| LineInfo { addr: remap_text(line.addr as usize) as u32, line: line.line })
+        .collect();
+    optimized.relocations = object.relocations.iter().filter(|r| r.section as usize != text).cloned().collect();
+    optimized.relocations.extend(fields.into_iter().map(|(offset, reloc)| Relocation {
+        section: text as u16,
+        offset: offset as u32,
+        kind: reloc.kind,
+        symbol: reloc.symbol,
+    }));
+
+    // Patch the fields referring to local symbols with their new address
+    for reloc in &optimized.relocations {
+        let Some(target) = optimized.symbol(&reloc.symbol) else {
+            continue;
+        };
+        let section = &mut optimized.sections[reloc.section as usize];
+        let field = reloc.offset as usize;
+        let bytes = match reloc.kind {
+            RelocationKind::Imm16 => (target as u16).to_le_bytes().to_vec(),
+            RelocationKind::Word32 => target.to_le_bytes().to_vec(),
+            RelocationKind::Rel16 => {
+                let next = section.addr as i64 + field as i64 + 2;
+                ((target as i64 - next) as u16).to_le_bytes().to_vec()
+            }
+        };
+        section.data[field..field + bytes.len()].copy_from_slice(&bytes);
+    }
+    optimized
+}
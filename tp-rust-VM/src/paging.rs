@@ -0,0 +1,76 @@
+use crate::{MachineError, MEMORY_SIZE};
+
+/// Size of a page of virtual memory, and of the physical frames they are
+/// mapped to, in bytes.
+pub const PAGE_SIZE: usize = 256;
+/// Number of pages of the address space, which is also the number of
+/// entries of a page table.
+pub const PAGE_COUNT: usize = MEMORY_SIZE / PAGE_SIZE;
+
+/// Bit of a page-table entry set when the page is mapped.
+pub const PAGE_VALID: u32 = 1;
+/// Bit of a page-table entry allowing loads from the page.
+pub const PAGE_READ: u32 = 2;
+/// Bit of a page-table entry allowing stores to the page.
+pub const PAGE_WRITE: u32 = 4;
+/// Bit of a page-table entry allowing the execution of the page.
+pub const PAGE_EXEC: u32 = 8;
+/// Bit of a page-table entry making the page accessible in user mode.
+pub const PAGE_USER: u32 = 16;
+
+/// Kind of memory access checked against the bits of a page-table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    /// Bit of a page-table entry allowing this access.
+    pub fn bit(self) -> u32 {
+        match self {
+            Access::Read => PAGE_READ,
+            Access::Write => PAGE_WRITE,
+            Access::Execute => PAGE_EXEC,
+        }
+    }
+}
+
+/// Privilege and address-translation state of a machine with paging.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Paging {
+    /// Whether the machine runs in user mode rather than supervisor mode.
+    pub user: bool,
+    /// Physical address of the page table, 0 when the translation is
+    /// disabled.
+    pub table: u32,
+    /// Stack pointer of the supervisor, loaded into r2 by a trap from user
+    /// mode.
+    pub kernel_sp: u32,
+}
+
+impl Paging {
+    /// Physical address of the virtual address `addr`, accessed by `access`
+    /// in user mode when `user` is set.
+    pub fn translate(&self, mem: &[u8; MEMORY_SIZE], addr: usize, access: Access, user: bool) -> Result<usize, MachineError> {
+        if addr >= MEMORY_SIZE {
+            return Err(MachineError::InvalidMemAddr);
+        }
+        if self.table == 0 {
+            return Ok(addr);
+        }
+        let fault = MachineError::PageFault { addr: addr as u32 };
+        let slot = (self.table as usize).checked_add(4 * (addr / PAGE_SIZE)).ok_or(fault)?;
+        let entry = match mem.get(slot..slot.saturating_add(4)) {
+            Some(&[a, b, c, d]) => u32::from_le_bytes([a, b, c, d]),
+            _ => return Err(fault),
+        };
+        let required = PAGE_VALID | access.bit() | if user { PAGE_USER } else { 0 };
+        let frame = (entry as usize) & !(PAGE_SIZE - 1);
+        if entry & required != required || frame >= MEMORY_SIZE {
+            return Err(fault);
+        }
+        Ok(frame + addr % PAGE_SIZE)
+    }
+}
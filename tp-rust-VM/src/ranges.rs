@@ -0,0 +1,493 @@
+use crate::{Cfg, Condition, EdgeKind, Instruction, Terminator, MEMORY_SIZE, NREGS};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+const IP: u8 = 0;
+const SP: u8 = 2;
+/// Number of times a block is joined into from a higher address before its
+/// registers are widened.
+const WIDENING_DELAY: usize = 3;
+const SIGN: u32 = 1 << 31;
+
+/// Set of the values `lo..=hi` a register may hold, as unsigned integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub lo: u32,
+    pub hi: u32,
+}
+
+impl Interval {
+    /// Any value.
+    pub const FULL: Interval = Interval { lo: 0, hi: u32::MAX };
+
+    /// # Panics
+    /// This function panics when `lo` is greater than `hi`.
+    pub fn new(lo: u32, hi: u32) -> Interval {
+        assert!(lo <= hi, "empty interval");
+        Interval { lo, hi }
+    }
+
+    pub fn constant(value: u32) -> Interval {
+        Interval { lo: value, hi: value }
+    }
+
+    /// The only value of the interval, if any.
+    pub fn as_constant(self) -> Option<u32> {
+        (self.lo == self.hi).then_some(self.lo)
+    }
+
+    pub fn contains(self, value: u32) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    /// Smallest interval containing both intervals.
+    pub fn join(self, other: Interval) -> Interval {
+        Interval { lo: self.lo.min(other.lo), hi: self.hi.max(other.hi) }
+    }
+
+    /// Values of both intervals, or `None` when there are none.
+    pub fn meet(self, other: Interval) -> Option<Interval> {
+        let (lo, hi) = (self.lo.max(other.lo), self.hi.min(other.hi));
+        (lo <= hi).then_some(Interval { lo, hi })
+    }
+
+    /// Join `other` into `self`, sending every bound which moved to the next
+    /// threshold so that loops reach a fixpoint. A lower bound stops at 1
+    /// first, so that decrementing a counter tested against 0 does not wrap
+    /// around, and an upper bound stops at the memory size and at the
+    /// largest positive value.
+    fn widen(self, other: Interval) -> Interval {
+        const LOWER: [u32; 2] = [1, 0];
+        const UPPER: [u32; 3] = [MEMORY_SIZE as u32, SIGN - 1, u32::MAX];
+        Interval {
+            lo: if other.lo < self.lo { *LOWER.iter().find(|&&t| t <= other.lo).unwrap() } else { self.lo },
+            hi: if other.hi > self.hi { *UPPER.iter().find(|&&t| t >= other.hi).unwrap() } else { self.hi },
+        }
+    }
+
+    /// Wrapping difference of the values of both intervals, which is
+    /// [FULL](Interval::FULL) when the result may or may not wrap around.
+    pub fn wrapping_sub(self, rhs: Interval) -> Interval {
+        let lo = self.lo as i64 - rhs.hi as i64;
+        let hi = self.hi as i64 - rhs.lo as i64;
+        if hi < 0 {
+            Interval { lo: (lo + (1 << 32)) as u32, hi: (hi + (1 << 32)) as u32 }
+        } else if lo >= 0 {
+            Interval { lo: lo as u32, hi: hi as u32 }
+        } else {
+            Interval::FULL
+        }
+    }
+
+    /// Whether `cond` holds for all the values (`Some(true)`), for none of
+    /// them (`Some(false)`), or only for some of them (`None`).
+    fn test(self, cond: Condition) -> Option<bool> {
+        let holds = self.refine(cond, true).is_some();
+        let fails = self.refine(cond, false).is_some();
+        (holds != fails).then_some(holds)
+    }
+
+    /// Values for which `cond` holds, or fails when `holds` is false.
+    fn refine(self, cond: Condition, holds: bool) -> Option<Interval> {
+        match (cond, holds) {
+            (Condition::Zero, true) | (Condition::NonZero, false) => self.meet(Interval::constant(0)),
+            (Condition::Zero, false) | (Condition::NonZero, true) => {
+                // Only a bound equal to 0 can be removed
+                if self.lo == 0 {
+                    (self.hi > 0).then_some(Interval { lo: 1, hi: self.hi })
+                } else {
+                    Some(self)
+                }
+            }
+            (Condition::Negative, true) => self.meet(Interval { lo: SIGN, hi: u32::MAX }),
+            (Condition::Negative, false) => self.meet(Interval { lo: 0, hi: SIGN - 1 }),
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.as_constant() {
+            Some(value) => write!(f, "{value}"),
+            None if *self == Interval::FULL => write!(f, "any value"),
+            None => write!(f, "[{}, {}]", self.lo, self.hi),
+        }
+    }
+}
+
+/// Possible values of the registers at some point of the program.
+pub type Ranges = [Interval; NREGS];
+
+/// Instruction which may fault or go astray, found by [RangeAnalysis].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeWarning {
+    /// A load or store whose address may be outside of the memory, given
+    /// the possible values of its address register.
+    MemoryAccess { addr: usize, instr: Instruction, range: Interval },
+    /// A write to the IP whose target is not a single known address. The
+    /// code it jumps to is not analyzed.
+    UnknownJump { addr: usize, instr: Instruction, range: Interval },
+    /// An instruction which cannot be decoded, or which uses an invalid
+    /// register.
+    InvalidInstruction { addr: usize },
+}
+
+impl RangeWarning {
+    /// Address of the offending instruction.
+    pub fn addr(&self) -> usize {
+        match *self {
+            RangeWarning::MemoryAccess { addr, .. }
+            | RangeWarning::UnknownJump { addr, .. }
+            | RangeWarning::InvalidInstruction { addr } => addr,
+        }
+    }
+}
+
+impl fmt::Display for RangeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeWarning::MemoryAccess { addr, instr, range } => {
+                let always = range.lo as usize + access_width(*instr).unwrap_or(1) > MEMORY_SIZE;
+                let verb = if always { "always accesses" } else { "may access" };
+                write!(f, "{addr:04}: `{instr}` {verb} outside of the memory, with an address in {range}")
+            }
+            RangeWarning::UnknownJump { addr, instr, range } => {
+                write!(f, "{addr:04}: `{instr}` jumps to an unknown address in {range}")
+            }
+            RangeWarning::InvalidInstruction { addr } => write!(f, "{addr:04}: invalid instruction"),
+        }
+    }
+}
+
+/// Abstract interpretation of the register values over the control-flow
+/// graph of a program.
+///
+/// Every register is approximated by an interval of unsigned values, so
+/// that the analysis can prove that the addresses of loads and stores stay
+/// inside the memory and that jumps have known targets, and warn about the
+/// instructions for which it cannot. Memory contents are not tracked, so a
+/// loaded register may hold any value. Calls are followed into the callee,
+/// whose returns flow back to every return address of its call sites with
+/// the stack pointer of the caller, as the functions of the listings pop
+/// everything they push.
+#[derive(Debug, Clone)]
+pub struct RangeAnalysis {
+    states: BTreeMap<usize, Ranges>,
+    warnings: Vec<RangeWarning>,
+}
+
+impl RangeAnalysis {
+    /// Analyze the program stored in `code` from `entry`, starting with all
+    /// the registers but the IP cleared as in [Machine::new](crate::Machine::new).
+    pub fn run(code: &[u8], entry: usize) -> RangeAnalysis {
+        let mut regs = [Interval::constant(0); NREGS];
+        regs[IP as usize] = Interval::constant(entry as u32);
+        RangeAnalysis::run_with(code, entry, regs)
+    }
+
+    /// Analyze the program stored in `code` from `entry`, with registers
+    /// initially holding values in `regs`.
+    pub fn run_with(code: &[u8], entry: usize, regs: Ranges) -> RangeAnalysis {
+        let cfg = Cfg::build(code, entry);
+        let returns = return_edges(&cfg);
+
+        let mut entries: BTreeMap<usize, Ranges> = BTreeMap::from([(entry, regs)]);
+        let mut exits: BTreeMap<usize, Ranges> = BTreeMap::new();
+        let mut joins: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut work = BTreeSet::from([entry]);
+        while let Some(start) = work.pop_first() {
+            let Some(block) = cfg.block(start) else { continue };
+            let mut regs = entries[&start];
+            if !block.instructions.iter().all(|&(addr, instr)| transfer(&mut regs, addr, instr)) {
+                continue;
+            }
+            exits.insert(start, regs);
+
+            let mut edges = vec![];
+            for (succ, kind) in block.successors() {
+                if kind != EdgeKind::CallReturn {
+                    edges.extend(refine(regs, block.instructions.last(), kind).map(|regs| (succ, regs)));
+                }
+            }
+            // The callee returns with the stack of the caller, once the
+            // return address is popped
+            for ret in returns.iter().filter(|ret| ret.call == start || ret.ret == start) {
+                if let (Some(call), Some(&(mut regs))) = (exits.get(&ret.call), exits.get(&ret.ret)) {
+                    regs[SP as usize] = call[SP as usize].wrapping_sub(Interval::constant(4u32.wrapping_neg()));
+                    edges.push((ret.to, regs));
+                }
+            }
+
+            for (succ, incoming) in edges {
+                let state = match entries.get(&succ) {
+                    None => incoming,
+                    Some(old) => {
+                        // Every loop goes back to a lower address at some
+                        // point, where widening ensures termination.
+                        let mut widen = false;
+                        if succ <= start {
+                            let count = joins.entry(succ).or_default();
+                            *count += 1;
+                            widen = *count > WIDENING_DELAY;
+                        }
+                        let mut state = *old;
+                        for (reg, value) in state.iter_mut().zip(incoming) {
+                            *reg = if widen { reg.widen(value) } else { reg.join(value) };
+                        }
+                        if state == *old {
+                            continue;
+                        }
+                        state
+                    }
+                };
+                entries.insert(succ, state);
+                work.insert(succ);
+            }
+        }
+
+        // Replay the blocks from their final state to record the state
+        // before every instruction, and check it.
+        let mut states = BTreeMap::new();
+        let mut warnings = vec![];
+        for (start, &regs) in &entries {
+            let Some(block) = cfg.block(*start) else { continue };
+            let mut regs = regs;
+            let mut valid = true;
+            for &(addr, instr) in &block.instructions {
+                regs[IP as usize] = Interval::constant((addr + instr.size()) as u32);
+                states.insert(addr, regs);
+                if let Some(warning) = check(&regs, addr, instr) {
+                    warnings.push(warning);
+                }
+                if !transfer(&mut regs, addr, instr) {
+                    warnings.push(RangeWarning::InvalidInstruction { addr });
+                    valid = false;
+                    break;
+                }
+            }
+            if valid && block.terminator == Terminator::Invalid {
+                warnings.push(RangeWarning::InvalidInstruction { addr: block.end() });
+            }
+        }
+        warnings.sort_by_key(RangeWarning::addr);
+
+        RangeAnalysis { states, warnings }
+    }
+
+    /// Possible values of the registers before the instruction at `addr`
+    /// runs, or `None` if the instruction is never reached. The IP already
+    /// holds the address of the next instruction.
+    pub fn before(&self, addr: usize) -> Option<&Ranges> {
+        self.states.get(&addr)
+    }
+
+    /// Warnings about the reachable instructions, sorted by address.
+    pub fn warnings(&self) -> &[RangeWarning] {
+        &self.warnings
+    }
+}
+
+/// A return from a function to one of its call sites.
+struct ReturnEdge {
+    /// Block ending with the call.
+    call: usize,
+    /// Block of the function ending with a return.
+    ret: usize,
+    /// Return address of the call.
+    to: usize,
+}
+
+/// Pairs every block ending with a return with the calls to the functions
+/// containing it.
+fn return_edges(cfg: &Cfg) -> Vec<ReturnEdge> {
+    let mut callers: BTreeMap<usize, Vec<(usize, usize)>> = BTreeMap::new();
+    for block in cfg.blocks() {
+        if let Terminator::Call { target, return_to } = block.terminator {
+            callers.entry(target).or_default().push((block.start, return_to));
+        }
+    }
+
+    let mut returns = vec![];
+    for (&function, calls) in &callers {
+        // Blocks of the function, not following calls
+        let mut seen = BTreeSet::from([function]);
+        let mut work = vec![function];
+        while let Some(addr) = work.pop() {
+            let Some(block) = cfg.block(addr) else { continue };
+            if block.terminator == Terminator::Return {
+                returns.extend(calls.iter().map(|&(call, to)| ReturnEdge { call, ret: addr, to }));
+            }
+            for (succ, kind) in block.successors() {
+                if kind != EdgeKind::Call && seen.insert(succ) {
+                    work.push(succ);
+                }
+            }
+        }
+    }
+    returns
+}
+
+/// Width of the memory access done by `instr`, if any.
+fn access_width(instr: Instruction) -> Option<usize> {
+    match instr {
+        Instruction::Load { .. } | Instruction::Store { .. } => Some(4),
+        Instruction::Load16 { .. } | Instruction::Store16 { .. } => Some(2),
+        Instruction::Load8 { .. } | Instruction::Store8 { .. } => Some(1),
+        _ => None,
+    }
+}
+
+/// Check the memory access or the jump done by `instr` with the registers
+/// holding `regs`.
+fn check(regs: &Ranges, addr: usize, instr: Instruction) -> Option<RangeWarning> {
+    let value = |reg: u8| regs.get(reg as usize).copied().unwrap_or(Interval::FULL);
+    match instr {
+        Instruction::Load { addr: reg, .. }
+        | Instruction::Load8 { addr: reg, .. }
+        | Instruction::Load16 { addr: reg, .. }
+        | Instruction::Store { addr: reg, .. }
+        | Instruction::Store8 { addr: reg, .. }
+        | Instruction::Store16 { addr: reg, .. } => {
+            let range = value(reg);
+            let width = access_width(instr).unwrap();
+            (range.hi as usize + width > MEMORY_SIZE).then_some(RangeWarning::MemoryAccess { addr, instr, range })
+        }
+        // Returns, which load the IP, go back to the call sites, and known
+        // targets are part of the control-flow graph
+        _ if instr.written_reg() != Some(IP) || instr.constant_load().is_some() => None,
+        Instruction::Jump { .. } | Instruction::Branch { .. } => None,
+        // Like returns, the trap goes back to the faulting instruction
+        Instruction::ReturnFromTrap => None,
+        Instruction::MoveIf { src, .. } | Instruction::MoveIfZero { src, .. } | Instruction::MoveIfNeg { src, .. } => {
+            let range = value(src);
+            range.as_constant().is_none().then_some(RangeWarning::UnknownJump { addr, instr, range })
+        }
+        _ => {
+            let mut after = *regs;
+            transfer(&mut after, addr, instr);
+            let range = after[IP as usize];
+            range.as_constant().is_none().then_some(RangeWarning::UnknownJump { addr, instr, range })
+        }
+    }
+}
+
+/// Update `regs` with the effect of `instr`, located at `addr`. Returns
+/// false when the instruction uses an invalid register and faults.
+fn transfer(regs: &mut Ranges, addr: usize, instr: Instruction) -> bool {
+    let used = instr.read_regs().into_iter().chain(instr.written_reg());
+    if used.into_iter().any(|reg| reg as usize >= NREGS) {
+        return false;
+    }
+    let next = addr + instr.size();
+    regs[IP as usize] = Interval::constant(next as u32);
+    let value = |reg: u8| regs[reg as usize];
+
+    let (dst, result) = match instr {
+        Instruction::MoveIf { dst, src, cond } => (dst, moved(value(dst), value(src), value(cond).test(Condition::NonZero))),
+        Instruction::MoveIfZero { dst, src, cond } => (dst, moved(value(dst), value(src), value(cond).test(Condition::Zero))),
+        Instruction::MoveIfNeg { dst, src, cond } => (dst, moved(value(dst), value(src), value(cond).test(Condition::Negative))),
+        Instruction::LoadImm { .. } | Instruction::LoadImm32 { .. } => {
+            let (dst, imm) = instr.constant_load().unwrap();
+            (dst, Interval::constant(imm))
+        }
+        Instruction::LoadHi { dst, imm } => {
+            let high = (imm as u32) << 16;
+            let old = value(dst);
+            if old.lo >> 16 == old.hi >> 16 {
+                (dst, Interval { lo: high | (old.lo & 0xffff), hi: high | (old.hi & 0xffff) })
+            } else {
+                (dst, Interval { lo: high, hi: high | 0xffff })
+            }
+        }
+        Instruction::Sub { dst, lhs, rhs } if lhs == rhs => (dst, Interval::constant(0)),
+        Instruction::Sub { dst, lhs, rhs } => (dst, value(lhs).wrapping_sub(value(rhs))),
+        Instruction::Compare { op, dst, lhs, rhs } => match (value(lhs).as_constant(), value(rhs).as_constant()) {
+            (Some(l), Some(r)) => (dst, Interval::constant(op.holds(l, r) as u32)),
+            _ if lhs == rhs => (dst, Interval::constant(op.holds(0, 0) as u32)),
+            _ => (dst, Interval::new(0, 1)),
+        },
+        Instruction::Load { dst, .. } => (dst, Interval::FULL),
+        Instruction::Load8 { dst, signed: false, .. } => (dst, Interval::new(0, 0xff)),
+        Instruction::Load16 { dst, signed: false, .. } => (dst, Interval::new(0, 0xffff)),
+        // Sign-extended values and the -1 of the end of the input wrap
+        // around
+        Instruction::Load8 { dst, .. } | Instruction::Load16 { dst, .. } | Instruction::In { dst } => {
+            (dst, Interval::FULL)
+        }
+        Instruction::FloatArith { op, dst, lhs, rhs } => match (value(lhs).as_constant(), value(rhs).as_constant()) {
+            (Some(l), Some(r)) => (dst, Interval::constant(op.apply(l, r))),
+            _ => (dst, Interval::FULL),
+        },
+        Instruction::IntToFloat { dst, src } => match value(src).as_constant() {
+            Some(v) => (dst, Interval::constant((v as i32 as f32).to_bits())),
+            None => (dst, Interval::FULL),
+        },
+        Instruction::FloatToInt { dst, src } => match value(src).as_constant() {
+            Some(v) => (dst, Interval::constant(f32::from_bits(v) as i32 as u32)),
+            None => (dst, Interval::FULL),
+        },
+        Instruction::Div { dst, lhs, rhs } => match (value(lhs).as_constant(), value(rhs).as_constant()) {
+            (Some(l), Some(r)) if r != 0 => (dst, Interval::constant((l as i32).wrapping_div(r as i32) as u32)),
+            _ => (dst, Interval::FULL),
+        },
+        Instruction::ReturnFromTrap => {
+            regs[SP as usize] = Interval::FULL;
+            (IP, Interval::FULL)
+        }
+        Instruction::FloatCompare { op, dst, lhs, rhs } => match (value(lhs).as_constant(), value(rhs).as_constant()) {
+            (Some(l), Some(r)) => (dst, Interval::constant(op.holds(l, r) as u32)),
+            _ => (dst, Interval::new(0, 1)),
+        },
+        Instruction::Jump { offset } | Instruction::Branch { offset, .. } => {
+            let target = (next as u32).wrapping_add(offset as u32);
+            let taken = match instr {
+                Instruction::Branch { cond, src, .. } => value(src).test(cond),
+                _ => Some(true),
+            };
+            (IP, moved(value(IP), Interval::constant(target), taken))
+        }
+        Instruction::Store { .. }
+        | Instruction::Store8 { .. }
+        | Instruction::Store16 { .. }
+        | Instruction::Out { .. }
+        | Instruction::OutNumber { .. }
+        | Instruction::OutFloat { .. }
+        | Instruction::SetPageTable { .. }
+        | Instruction::Exit => return true,
+    };
+    regs[dst as usize] = result;
+    true
+}
+
+/// Value of the destination of a conditional move, given whether the
+/// condition is known to hold.
+fn moved(dst: Interval, src: Interval, holds: Option<bool>) -> Interval {
+    match holds {
+        Some(true) => src,
+        Some(false) => dst,
+        None => dst.join(src),
+    }
+}
+
+/// Registers along an edge leaving a block whose last instruction is
+/// `last`, narrowing the register tested by a conditional branch. Returns
+/// `None` when the edge cannot be taken.
+fn refine(mut regs: Ranges, last: Option<&(usize, Instruction)>, kind: EdgeKind) -> Option<Ranges> {
+    let holds = match kind {
+        EdgeKind::Taken => true,
+        EdgeKind::NotTaken => false,
+        _ => return Some(regs),
+    };
+    let (cond, reg) = match last.map(|&(_, instr)| instr) {
+        Some(Instruction::MoveIf { cond, .. }) => (Condition::NonZero, cond),
+        Some(Instruction::MoveIfZero { cond, .. }) => (Condition::Zero, cond),
+        Some(Instruction::MoveIfNeg { cond, .. }) => (Condition::Negative, cond),
+        Some(Instruction::Branch { cond, src, .. }) => (cond, src),
+        _ => return Some(regs),
+    };
+    // The IP was set to the target by the branch itself
+    if reg != IP {
+        regs[reg as usize] = regs[reg as usize].refine(cond, holds)?;
+    }
+    Some(regs)
+}
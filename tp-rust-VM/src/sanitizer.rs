@@ -0,0 +1,64 @@
+use crate::{Instruction, MachineObserver, MEMORY_SIZE};
+
+/// A `load` which read at least one byte never written since the program
+/// was loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitializedRead {
+    /// Address of the `load` instruction.
+    pub ip: u32,
+    /// Address of the first uninitialized byte read.
+    pub addr: u32,
+}
+
+/// Shadow memory with one bit per byte of the machine memory, set when the
+/// byte has been written either by the loaded image or by a `store`. The
+/// banks, filled by the host, are not tracked.
+pub(crate) struct Sanitizer {
+    shadow: [u8; MEMORY_SIZE / 8],
+    reports: Vec<UninitializedRead>,
+    /// Address of the running instruction.
+    ip: u32,
+}
+
+impl Sanitizer {
+    /// Create a sanitizer where the first `initialized` bytes are considered
+    /// as written.
+    pub(crate) fn new(initialized: usize) -> Self {
+        let mut sanitizer = Sanitizer { shadow: [0; MEMORY_SIZE / 8], reports: vec![], ip: 0 };
+        sanitizer.mark(0, initialized);
+        sanitizer
+    }
+
+    /// Mark `len` bytes starting at `addr` as written.
+    pub(crate) fn mark(&mut self, addr: usize, len: usize) {
+        for a in addr..(addr + len).min(MEMORY_SIZE) {
+            self.shadow[a / 8] |= 1 << (a % 8);
+        }
+    }
+
+    /// Check that `len` bytes starting at `addr` have been written, and
+    /// record a report for the instruction at `ip` otherwise.
+    pub(crate) fn check(&mut self, ip: u32, addr: usize, len: usize) {
+        if let Some(a) = (addr..(addr + len).min(MEMORY_SIZE)).find(|a| self.shadow[a / 8] & (1 << (a % 8)) == 0) {
+            self.reports.push(UninitializedRead { ip, addr: a as u32 });
+        }
+    }
+
+    pub(crate) fn reports(&self) -> &[UninitializedRead] {
+        &self.reports
+    }
+}
+
+impl MachineObserver for Sanitizer {
+    fn on_fetch(&mut self, ip: u32, _instr: &Instruction) {
+        self.ip = ip;
+    }
+
+    fn on_mem_read(&mut self, addr: u32, data: &[u8]) {
+        self.check(self.ip, addr as usize, data.len());
+    }
+
+    fn on_mem_write(&mut self, addr: u32, data: &[u8]) {
+        self.mark(addr as usize, data.len());
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{Cfg, EdgeKind, Instruction, Terminator};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+const SP: u8 = 2;
+/// Depth of a function returning through the r2 stack, relative to its
+/// entry: the return address pushed by the caller has been popped.
+const RETURN_DEPTH: i64 = -4;
+
+/// Stack usage of a function, in bytes below the stack pointer it is
+/// called with, which points to its return address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionStack {
+    pub entry: usize,
+    /// Deepest point reached by the function itself.
+    pub frame: u32,
+    /// Deepest point reached by the function and the functions it calls, or
+    /// `None` when it cannot be bounded.
+    pub total: Option<u32>,
+}
+
+/// Reason why the stack usage of a program cannot be bounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackIssue {
+    /// A recursive function whose depth is not annotated.
+    Recursion { function: usize },
+    /// A write to r2 which is neither a constant nor a push or pop.
+    UnknownWrite { addr: usize },
+    /// A block reached with different stack depths, such as a loop pushing
+    /// on every iteration.
+    InconsistentDepth { addr: usize },
+    /// A return which does not leave the stack as the function found it.
+    UnbalancedReturn { addr: usize, left: i64 },
+    /// A write to the IP whose target is not statically known.
+    IndirectJump { addr: usize },
+}
+
+impl StackIssue {
+    /// Address of the offending instruction or function.
+    pub fn addr(&self) -> usize {
+        match *self {
+            StackIssue::Recursion { function: addr }
+            | StackIssue::UnknownWrite { addr }
+            | StackIssue::InconsistentDepth { addr }
+            | StackIssue::UnbalancedReturn { addr, .. }
+            | StackIssue::IndirectJump { addr } => addr,
+        }
+    }
+}
+
+impl fmt::Display for StackIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackIssue::Recursion { function } => write!(f, "{function:04}: recursive function without a depth bound"),
+            StackIssue::UnknownWrite { addr } => write!(f, "{addr:04}: the stack pointer is set to an unknown value"),
+            StackIssue::InconsistentDepth { addr } => write!(f, "{addr:04}: reached with different stack depths"),
+            StackIssue::UnbalancedReturn { addr, left } => {
+                write!(f, "{addr:04}: returns with {left} bytes left on the stack")
+            }
+            StackIssue::IndirectJump { addr } => write!(f, "{addr:04}: jump to an unknown address"),
+        }
+    }
+}
+
+/// Worst-case stack usage of a program, following the r2 conventions of
+/// the listings.
+///
+/// Functions are the targets of calls. Their depth is tracked through the
+/// `sub r2 <- r2 - rX` pushes and pops with a constant `rX`, and through the
+/// `loadimm r2 <- #top` setting the stack up, from which the lowest address
+/// of the stack is known. Every call adds the usage of the callee to the
+/// depth of the call site. Recursive functions are unbounded, unless given
+/// the maximum number of their activations at any time: a group of mutually
+/// recursive functions then uses at most the deepest of their recursive call
+/// sites for every activation but the last one.
+#[derive(Debug, Clone)]
+pub struct StackAnalysis {
+    functions: BTreeMap<usize, FunctionStack>,
+    issues: Vec<StackIssue>,
+    lowest: Option<i64>,
+    code_end: usize,
+}
+
+/// A call, at `depth` below the entry of the caller or at the absolute
+/// stack address `depth` when `absolute`.
+#[derive(Debug, Clone, Copy)]
+struct Call {
+    target: usize,
+    depth: i64,
+    absolute: bool,
+}
+
+/// Stack pointer along a path, relative to the entry of the function or to
+/// the top it was set to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Depth {
+    top: Option<i64>,
+    depth: i64,
+}
+
+/// Stack operations of a single function.
+#[derive(Debug, Default)]
+struct Walk {
+    frame: i64,
+    lowest: Option<i64>,
+    calls: Vec<Call>,
+    issues: Vec<StackIssue>,
+}
+
+impl StackAnalysis {
+    /// Analyze the program stored in `code` from `entry`, which starts with
+    /// r2 cleared as in [Machine::new](crate::Machine::new). `recursion`
+    /// gives the maximum number of simultaneous activations of recursive
+    /// functions, by address.
+    pub fn run(code: &[u8], entry: usize, recursion: &BTreeMap<usize, u32>) -> StackAnalysis {
+        let cfg = Cfg::build(code, entry);
+        let mut walks = BTreeMap::from([(entry, walk(&cfg, entry, Some(0)))]);
+        let mut work = vec![entry];
+        while let Some(function) = work.pop() {
+            for call in walks[&function].calls.clone() {
+                if let Entry::Vacant(slot) = walks.entry(call.target) {
+                    slot.insert(walk(&cfg, call.target, None));
+                    work.push(call.target);
+                }
+            }
+        }
+
+        let mut issues: Vec<StackIssue> = walks.values().flat_map(|w| w.issues.iter().copied()).collect();
+        let mut totals = BTreeMap::new();
+        for &function in walks.keys() {
+            total(&walks, recursion, function, &mut totals, &mut issues);
+        }
+        issues.sort_by_key(StackIssue::addr);
+        issues.dedup();
+
+        let mut lowest = Some(i64::MAX);
+        for walk in walks.values() {
+            let calls = walk.calls.iter().filter(|call| call.absolute);
+            for point in walk.lowest.into_iter().map(Some).chain(calls.map(|call| {
+                totals[&call.target].map(|total| call.depth - total as i64)
+            })) {
+                lowest = lowest.zip(point).map(|(lowest, point)| lowest.min(point));
+            }
+        }
+        let functions = walks
+            .iter()
+            .map(|(&entry, walk)| {
+                let frame = walk.frame.clamp(0, u32::MAX as i64) as u32;
+                (entry, FunctionStack { entry, frame, total: totals[&entry] })
+            })
+            .collect();
+        StackAnalysis {
+            functions,
+            issues: issues.clone(),
+            lowest: lowest.filter(|&lowest| issues.is_empty() && lowest != i64::MAX),
+            code_end: code.len(),
+        }
+    }
+
+    /// Stack usage of every function, sorted by address.
+    pub fn functions(&self) -> impl Iterator<Item = &FunctionStack> {
+        self.functions.values()
+    }
+
+    /// Stack usage of the function starting at `entry`.
+    pub fn function(&self, entry: usize) -> Option<&FunctionStack> {
+        self.functions.get(&entry)
+    }
+
+    /// Reasons why the stack usage cannot be bounded, sorted by address.
+    pub fn issues(&self) -> &[StackIssue] {
+        &self.issues
+    }
+
+    /// Lowest address written by the stack, which is negative when the
+    /// stack pointer wraps around. This is `None` when the usage cannot be
+    /// bounded, or when r2 is never written.
+    pub fn lowest(&self) -> Option<i64> {
+        self.lowest
+    }
+
+    /// Whether the stack is known to never grow into the code and data of
+    /// the program.
+    pub fn fits(&self) -> bool {
+        self.issues.is_empty() && self.lowest.is_none_or(|lowest| lowest >= self.code_end as i64)
+    }
+}
+
+/// Follow the stack pointer through the blocks of `function`, without
+/// entering the functions it calls. `top` is the stack pointer at the entry
+/// when known, as for the entry point of the program.
+fn walk(cfg: &Cfg, function: usize, top: Option<i64>) -> Walk {
+    let mut result = Walk::default();
+    let reach = |result: &mut Walk, at: Depth| match at.top {
+        Some(top) => result.lowest = Some(result.lowest.map_or(top - at.depth, |l| l.min(top - at.depth))),
+        None => result.frame = result.frame.max(at.depth),
+    };
+
+    let mut depths = BTreeMap::from([(function, Depth { top, depth: 0 })]);
+    let mut work = vec![function];
+    while let Some(start) = work.pop() {
+        let Some(block) = cfg.block(start) else { continue };
+        let mut at = depths[&start];
+        // Constants loaded in registers along the block
+        let mut consts: [Option<u32>; 16] = [None; 16];
+        let mut valid = true;
+        for &(addr, instr) in &block.instructions {
+            let konst = |reg: u8| consts.get(reg as usize).copied().flatten();
+            if instr.written_reg() == Some(SP) {
+                match (instr, instr.constant_load()) {
+                    (_, Some((_, value))) => at = Depth { top: Some(value as i64), depth: 0 },
+                    (Instruction::Sub { lhs: SP, rhs, .. }, _) if konst(rhs).is_some() => {
+                        at.depth += konst(rhs).unwrap() as i32 as i64;
+                    }
+                    _ => {
+                        result.issues.push(StackIssue::UnknownWrite { addr });
+                        valid = false;
+                        break;
+                    }
+                }
+                reach(&mut result, at);
+            }
+            if let Some(dst) = instr.written_reg() {
+                let value = match instr {
+                    Instruction::Sub { lhs, rhs, .. } => konst(lhs).zip(konst(rhs)).map(|(l, r)| l.wrapping_sub(r)),
+                    _ => instr.constant_load().map(|(_, value)| value),
+                };
+                if let Some(slot) = consts.get_mut(dst as usize) {
+                    *slot = value;
+                }
+            }
+        }
+        if !valid {
+            continue;
+        }
+
+        let last = block.instructions.last().map_or(block.start, |&(addr, _)| addr);
+        match block.terminator {
+            Terminator::Call { target, .. } => {
+                let (depth, absolute) = match at.top {
+                    Some(top) => (top - at.depth, true),
+                    None => (at.depth, false),
+                };
+                result.calls.push(Call { target, depth, absolute });
+            }
+            Terminator::Return if at.depth != RETURN_DEPTH => {
+                result.issues.push(StackIssue::UnbalancedReturn { addr: last, left: at.depth - RETURN_DEPTH });
+            }
+            Terminator::Indirect { .. } => result.issues.push(StackIssue::IndirectJump { addr: last }),
+            _ => {}
+        }
+        for (succ, kind) in block.successors() {
+            let next = match kind {
+                EdgeKind::Call => continue,
+                // The callee popped the return address
+                EdgeKind::CallReturn => Depth { depth: at.depth + RETURN_DEPTH, ..at },
+                _ => at,
+            };
+            match depths.get(&succ) {
+                None => {
+                    depths.insert(succ, next);
+                    work.push(succ);
+                }
+                Some(&previous) if previous != next => {
+                    result.issues.push(StackIssue::InconsistentDepth { addr: succ });
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    result
+}
+
+/// Compute the total usage of `function` and of the functions which are
+/// mutually recursive with it, reporting the recursions without bound.
+fn total(
+    walks: &BTreeMap<usize, Walk>,
+    recursion: &BTreeMap<usize, u32>,
+    function: usize,
+    totals: &mut BTreeMap<usize, Option<u32>>,
+    issues: &mut Vec<StackIssue>,
+) -> Option<u32> {
+    if let Some(&total) = totals.get(&function) {
+        return total;
+    }
+    let group: BTreeSet<usize> =
+        walks.keys().copied().filter(|&other| calls(walks, function, other) && calls(walks, other, function)).collect();
+    let recursive = calls(walks, function, function);
+
+    // Mark the group as unbounded while computing it, which only matters
+    // for malformed call graphs
+    for &member in &group {
+        totals.insert(member, None);
+    }
+    let mut bounded = true;
+    let mut activations = 0u64;
+    let (mut recursive_depth, mut last) = (0i64, 0i64);
+    for &member in group.iter().chain(Some(&function).filter(|_| !recursive)) {
+        let walk = &walks[&member];
+        bounded &= walk.issues.is_empty();
+        if recursive {
+            match recursion.get(&member) {
+                Some(&bound) => activations += bound as u64,
+                None => {
+                    issues.push(StackIssue::Recursion { function: member });
+                    bounded = false;
+                }
+            }
+        } else {
+            activations = 1;
+        }
+        last = last.max(walk.frame);
+        for call in walk.calls.iter().filter(|call| !call.absolute) {
+            if group.contains(&call.target) {
+                recursive_depth = recursive_depth.max(call.depth);
+            } else {
+                match total(walks, recursion, call.target, totals, issues) {
+                    Some(callee) => last = last.max(call.depth + callee as i64),
+                    None => bounded = false,
+                }
+            }
+        }
+    }
+    let total = bounded.then(|| {
+        let total = activations.saturating_sub(1) as i64 * recursive_depth + last;
+        total.clamp(0, u32::MAX as i64) as u32
+    });
+    for &member in group.iter().chain(Some(&function)) {
+        totals.insert(member, total);
+    }
+    total
+}
+
+/// Whether `from` may call `to`, directly or not.
+fn calls(walks: &BTreeMap<usize, Walk>, from: usize, to: usize) -> bool {
+    let mut seen = BTreeSet::new();
+    let mut work = vec![from];
+    while let Some(function) = work.pop() {
+        for call in walks.get(&function).into_iter().flat_map(|walk| &walk.calls) {
+            if call.target == to {
+                return true;
+            }
+            if seen.insert(call.target) {
+                work.push(call.target);
+            }
+        }
+    }
+    false
+}
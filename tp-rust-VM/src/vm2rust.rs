@@ -0,0 +1,315 @@
+use crate::{Cfg, Comparison, Condition, FloatComparison, Instruction, MEMORY_SIZE, NREGS};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Helper functions of the generated module, emitted when used.
+const HELPERS: [(&str, &str); 8] = [
+    (
+        "load",
+        "
+/// Read `width` bytes in little-endian order, as `load`, `load8` and `load16`.
+fn load(mem: &[u8; MEMORY_SIZE], addr: u32, width: usize) -> Result<u32, MachineError> {
+    let addr = addr as usize;
+    if addr + width > MEMORY_SIZE {
+        return Err(MachineError::InvalidMemAddr);
+    }
+    Ok(mem[addr..addr + width].iter().rev().fold(0, |value, &byte| (value << 8) | byte as u32))
+}
+",
+    ),
+    (
+        "store",
+        "
+/// Write the `width` lowest bytes of `value` in little-endian order.
+fn store(mem: &mut [u8; MEMORY_SIZE], addr: u32, value: u32, width: usize) -> Result<(), MachineError> {
+    let addr = addr as usize;
+    if addr + width > MEMORY_SIZE {
+        return Err(MachineError::InvalidMemAddr);
+    }
+    mem[addr..addr + width].copy_from_slice(&value.to_le_bytes()[..width]);
+    Ok(())
+}
+",
+    ),
+    (
+        "out",
+        "
+fn out<W: Write>(output: &mut W, value: u32) -> Result<(), MachineError> {
+    let text = (value as u8 as char).to_string();
+    output.write_all(text.as_bytes()).map_err(|_| MachineError::WriteError)
+}
+",
+    ),
+    (
+        "out_number",
+        "
+fn out_number<W: Write>(output: &mut W, value: u32) -> Result<(), MachineError> {
+    write!(output, \"{}\", value as i32).map_err(|_| MachineError::WriteError)
+}
+",
+    ),
+    (
+        "out_float",
+        "
+fn out_float<W: Write>(output: &mut W, value: u32) -> Result<(), MachineError> {
+    write!(output, \"{}\", f32::from_bits(value)).map_err(|_| MachineError::WriteError)
+}
+",
+    ),
+    (
+        "div",
+        "
+/// Signed division truncating toward zero, as `div`.
+fn div(lhs: u32, rhs: u32) -> Result<u32, MachineError> {
+    match rhs {
+        0 => Err(MachineError::DivisionByZero),
+        _ => Ok((lhs as i32).wrapping_div(rhs as i32) as u32),
+    }
+}
+",
+    ),
+    (
+        "rti",
+        "
+/// Pop the cause and the IP pushed by a trap, as `rti`.
+fn rti(reg: &mut [u32; NREGS], mem: &[u8; MEMORY_SIZE]) -> Result<(), MachineError> {
+    let sp = reg[2] as usize;
+    if sp + 8 > MEMORY_SIZE {
+        return Err(MachineError::InvalidMemAddr);
+    }
+    reg[0] = u32::from_le_bytes(mem[sp + 4..sp + 8].try_into().unwrap());
+    reg[2] += 8;
+    Ok(())
+}
+",
+    ),
+    (
+        "read",
+        "
+/// Read a byte, or -1 at the end of the input.
+fn read<R: Read>(input: &mut R) -> Result<u32, MachineError> {
+    let mut byte = [0u8];
+    match input.read(&mut byte) {
+        Ok(0) => Ok(u32::MAX),
+        Ok(_) => Ok(byte[0] as u32),
+        Err(_) => Err(MachineError::ReadError),
+    }
+}
+",
+    ),
+];
+
+/// Translate a memory image into the source of a Rust module, which runs the
+/// program natively on the same registers and memory as [Machine](crate::Machine).
+///
+/// The module exports the image as `IMAGE`, the entry point as `ENTRY`, and
+/// a `run` function working like [run_with](crate::Machine::run_with):
+///
+/// ```text
+/// pub fn run<R: Read, W: Write>(
+///     reg: &mut [u32; NREGS],
+///     mem: &mut [u8; MEMORY_SIZE],
+///     input: &mut R,
+///     output: &mut W,
+/// ) -> Result<(), MachineError>
+/// ```
+///
+/// Every basic block reachable from the entry point becomes an arm of a
+/// `match` on the IP, with the same semantics as the interpreter, faults
+/// included. The IP is updated before each instruction, so that it holds the
+/// same value as in the interpreter when read or when a fault happens. Code
+/// which is not found statically, such as the target of a computed jump, and
+/// instructions using invalid registers are run one instruction at a time
+/// by the interpreter. The code must not be modified by the program, as the
+/// translation would then be stale. Floating-point instructions are always
+/// enabled, as with [enable_float](crate::Machine::enable_float), while
+/// traps, paging and banks are not: faults always end the run.
+///
+/// # Panics
+/// This function panics when `image` is larger than the machine memory.
+pub fn vm2rust(image: &[u8], entry: u32) -> String {
+    assert!(image.len() <= MEMORY_SIZE, "The memory is larger than the machine memory");
+    let mut mem = image.to_vec();
+    mem.resize(MEMORY_SIZE, 0);
+    let cfg = Cfg::build(&mem, entry as usize);
+
+    let mut helpers = BTreeSet::new();
+    let mut arms = String::new();
+    for block in cfg.blocks() {
+        let mut body = String::new();
+        for &(addr, instr) in &block.instructions {
+            let Some(lines) = translate(addr, instr, &mut helpers) else {
+                break;
+            };
+            writeln!(body, "                // {addr:04}   {instr}").unwrap();
+            for line in lines {
+                writeln!(body, "                {line}").unwrap();
+            }
+        }
+        // Blocks starting with an untranslated instruction are left to the
+        // interpreter
+        if !body.is_empty() {
+            writeln!(arms, "            {} => {{\n{body}            }}", block.start).unwrap();
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("//! Translation of a VM program, generated by `vm2rust`.\n\n");
+    out.push_str("use interpreter::{Machine, MachineError, MEMORY_SIZE, NREGS};\n");
+    out.push_str("use std::io::{Read, Write};\n\n");
+    out.push_str("/// Memory image of the program.\npub const IMAGE: &[u8] = &[\n");
+    for chunk in image.chunks(16) {
+        let bytes: Vec<String> = chunk.iter().map(u8::to_string).collect();
+        writeln!(out, "    {},", bytes.join(", ")).unwrap();
+    }
+    out.push_str("];\n\n");
+    writeln!(out, "/// Address of the first instruction.\npub const ENTRY: u32 = {entry};\n").unwrap();
+    out.push_str(
+        "/// Run the program from the IP held in `reg[0]` until it exits or faults.
+pub fn run<R: Read, W: Write>(
+    reg: &mut [u32; NREGS],
+    mem: &mut [u8; MEMORY_SIZE],
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), MachineError> {
+    loop {
+        match reg[0] {
+",
+    );
+    out.push_str(&arms);
+    out.push_str(
+        "            _ => {
+                if interpret(reg, mem, input, output)? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Run a single instruction with the interpreter.
+fn interpret<R: Read, W: Write>(
+    reg: &mut [u32; NREGS],
+    mem: &mut [u8; MEMORY_SIZE],
+    input: &mut R,
+    output: &mut W,
+) -> Result<bool, MachineError> {
+    let mut machine = Machine::new(&mem[..]);
+    machine.enable_float();
+    for (r, &value) in reg.iter().enumerate() {
+        machine.set_reg(r, value)?;
+    }
+    let result = machine.step_with(input, output);
+    reg.copy_from_slice(machine.regs());
+    mem.copy_from_slice(machine.memory());
+    result
+}
+",
+    );
+    for (name, source) in HELPERS {
+        if helpers.contains(name) {
+            out.push_str(source);
+        }
+    }
+    out
+}
+
+/// Statements implementing `instr`, located at `addr`, or `None` when it
+/// uses an invalid register or paging and is left to the interpreter.
+fn translate(addr: usize, instr: Instruction, helpers: &mut BTreeSet<&'static str>) -> Option<Vec<String>> {
+    let regs = instr.read_regs().into_iter().chain(instr.written_reg());
+    if regs.into_iter().any(|reg| reg as usize >= NREGS) {
+        return None;
+    }
+    let next = addr + instr.size();
+    let mut lines = match instr {
+        Instruction::Jump { .. } => vec![],
+        _ => vec![format!("reg[0] = {next};")],
+    };
+    let mut helper = |name: &'static str, line: String| {
+        helpers.insert(name);
+        line
+    };
+
+    let line = match instr {
+        Instruction::MoveIf { dst, src, cond } => moved(dst, src, format!("reg[{cond}] != 0")),
+        Instruction::MoveIfZero { dst, src, cond } => moved(dst, src, format!("reg[{cond}] == 0")),
+        Instruction::MoveIfNeg { dst, src, cond } => moved(dst, src, format!("(reg[{cond}] as i32) < 0")),
+        Instruction::Store { addr, src } => helper("store", format!("store(mem, reg[{addr}], reg[{src}], 4)?;")),
+        Instruction::Store8 { addr, src } => helper("store", format!("store(mem, reg[{addr}], reg[{src}], 1)?;")),
+        Instruction::Store16 { addr, src } => helper("store", format!("store(mem, reg[{addr}], reg[{src}], 2)?;")),
+        Instruction::Load { dst, addr } => helper("load", format!("reg[{dst}] = load(mem, reg[{addr}], 4)?;")),
+        Instruction::Load8 { dst, addr, signed } => {
+            let extend = if signed { " as u8 as i8 as u32" } else { "" };
+            helper("load", format!("reg[{dst}] = load(mem, reg[{addr}], 1)?{extend};"))
+        }
+        Instruction::Load16 { dst, addr, signed } => {
+            let extend = if signed { " as u16 as i16 as u32" } else { "" };
+            helper("load", format!("reg[{dst}] = load(mem, reg[{addr}], 2)?{extend};"))
+        }
+        Instruction::LoadImm { .. } | Instruction::LoadImm32 { .. } => {
+            let (dst, value) = instr.constant_load().unwrap();
+            format!("reg[{dst}] = {value};")
+        }
+        Instruction::LoadHi { dst, imm: 0 } => format!("reg[{dst}] &= 0xffff;"),
+        Instruction::LoadHi { dst, imm } => format!("reg[{dst}] = {:#x} | (reg[{dst}] & 0xffff);", (imm as u32) << 16),
+        Instruction::Sub { dst, lhs, rhs } if lhs == rhs => format!("reg[{dst}] = 0;"),
+        Instruction::Sub { dst, lhs, rhs } => format!("reg[{dst}] = reg[{lhs}].wrapping_sub(reg[{rhs}]);"),
+        Instruction::Compare { op, dst, lhs, rhs } if lhs == rhs => format!("reg[{dst}] = {};", op.holds(0, 0) as u32),
+        Instruction::Compare { op, dst, lhs, rhs } => {
+            let (l, r) = match op {
+                Comparison::Slt | Comparison::Sle => (format!("(reg[{lhs}] as i32)"), format!("(reg[{rhs}] as i32)")),
+                Comparison::Ult | Comparison::Ule | Comparison::Eq => (format!("reg[{lhs}]"), format!("reg[{rhs}]")),
+            };
+            let cmp = match op {
+                Comparison::Slt | Comparison::Ult => "<",
+                Comparison::Sle | Comparison::Ule => "<=",
+                Comparison::Eq => "==",
+            };
+            format!("reg[{dst}] = ({l} {cmp} {r}) as u32;")
+        }
+        Instruction::Out { src } => helper("out", format!("out(output, reg[{src}])?;")),
+        Instruction::OutNumber { src } => helper("out_number", format!("out_number(output, reg[{src}])?;")),
+        Instruction::In { dst } => helper("read", format!("reg[{dst}] = read(input)?;")),
+        Instruction::FloatArith { op, dst, lhs, rhs } => format!(
+            "reg[{dst}] = (f32::from_bits(reg[{lhs}]) {} f32::from_bits(reg[{rhs}])).to_bits();",
+            op.symbol()
+        ),
+        Instruction::IntToFloat { dst, src } => format!("reg[{dst}] = (reg[{src}] as i32 as f32).to_bits();"),
+        Instruction::FloatToInt { dst, src } => format!("reg[{dst}] = f32::from_bits(reg[{src}]) as i32 as u32;"),
+        Instruction::FloatCompare { op, dst, lhs, rhs } => {
+            let cmp = match op {
+                FloatComparison::Lt => "<",
+                FloatComparison::Le => "<=",
+                FloatComparison::Eq => "==",
+            };
+            format!("reg[{dst}] = (f32::from_bits(reg[{lhs}]) {cmp} f32::from_bits(reg[{rhs}])) as u32;")
+        }
+        Instruction::OutFloat { src } => helper("out_float", format!("out_float(output, reg[{src}])?;")),
+        Instruction::Div { dst, lhs, rhs } => helper("div", format!("reg[{dst}] = div(reg[{lhs}], reg[{rhs}])?;")),
+        Instruction::ReturnFromTrap => helper("rti", "rti(reg, mem)?;".to_string()),
+        Instruction::SetPageTable { .. } => return None,
+        Instruction::Exit => "return Ok(());".to_string(),
+        Instruction::Jump { offset } => format!("reg[0] = {};", (next as u32).wrapping_add(offset as u32)),
+        Instruction::Branch { cond, src, offset } => {
+            let test = match cond {
+                Condition::Zero => format!("reg[{src}] == 0"),
+                Condition::NonZero => format!("reg[{src}] != 0"),
+                Condition::Negative => format!("(reg[{src}] as i32) < 0"),
+            };
+            format!("if {test} {{ reg[0] = {}; }}", (next as u32).wrapping_add(offset as u32))
+        }
+    };
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    Some(lines)
+}
+
+/// Conditional move, which does nothing when both registers are the same.
+fn moved(dst: u8, src: u8, test: String) -> String {
+    if dst == src {
+        return String::new();
+    }
+    format!("if {test} {{ reg[{dst}] = reg[{src}]; }}")
+}
@@ -1,3 +1,5 @@
+#![cfg(feature = "std")]
+
 use interpreter::Machine;
 
 fn create_machine(code: &[u8]) -> (Machine, Vec<u8>) {
@@ -0,0 +1,66 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Backtrace, Frame, Machine};
+
+// In rfact.dis, mult is at 24, rfact at 87, the recursive call to rfact
+// jumps from 145 and the call to mult jumps from 183. The initial call
+// to rfact jumps from 19.
+fn faulty_rfact() -> Vec<u8> {
+    let mut code = include_bytes!("rfact.bin").to_vec();
+    // Make mult fault with an invalid opcode
+    code[24] = 0;
+    code
+}
+
+#[test]
+fn recursive_backtrace() {
+    let mut machine = Machine::new(&faulty_rfact());
+    machine.set_reg(10, 4).unwrap();
+    assert!(machine.run_on(&mut vec![]).is_err());
+    assert_eq!(24, machine.last_ip());
+
+    // rfact(4) -> rfact(3) -> rfact(2) -> rfact(1) has returned, rfact(2)
+    // calls mult
+    let backtrace = Backtrace::capture(&machine);
+    let pcs: Vec<u32> = backtrace.frames.iter().map(|f| f.pc).collect();
+    assert_eq!(vec![24, 183, 145, 145, 19], pcs);
+    let functions: Vec<Option<u32>> = backtrace.frames.iter().map(|f| f.function).collect();
+    assert_eq!(vec![Some(24), Some(87), Some(87), Some(87), None], functions);
+    assert_eq!(
+        Frame { pc: 183, function: Some(87), return_slot: Some(machine.regs()[2]) },
+        backtrace.frames[1]
+    );
+}
+
+#[test]
+fn symbolized_backtrace() {
+    let mut machine = Machine::new(&faulty_rfact());
+    machine.set_reg(10, 3).unwrap();
+    assert!(machine.run_on(&mut vec![]).is_err());
+
+    let symbols = assemble(include_str!("rfact.dis")).unwrap().symbol_table();
+    let text = Backtrace::capture(&machine).format(Some(&symbols));
+    let expected = "\
+#0  0024 in mult (mult)
+#1  0183 in rfact (return_from_rfact_2+34)
+#2  0145 in rfact (ite_then_2+34)
+#3  0019 (0019)
+";
+    assert_eq!(expected, text);
+    assert!(Backtrace::capture(&machine).format(None).starts_with("#0  0024 in 0024\n"));
+}
+
+#[test]
+fn saved_values_are_not_frames() {
+    // 0: loadimm r1 <- #0
+    // 4: exit
+    // The stack holds 0 and the address of an instruction which does not
+    // follow a call
+    let mut memory = vec![0; 4096];
+    memory[..5].copy_from_slice(&[4, 1, 0, 0, 7]);
+    memory[4092] = 4;
+    let mut machine = Machine::new(&memory);
+    machine.set_reg(2, 4088).unwrap();
+    machine.run_on(&mut vec![]).unwrap();
+    assert_eq!(1, Backtrace::capture(&machine).frames.len());
+}
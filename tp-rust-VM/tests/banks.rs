@@ -0,0 +1,88 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Machine, MachineError, BANK_SELECT, BANK_WINDOW};
+
+fn machine(source: &str, banks: usize) -> Machine {
+    let object = assemble(source).unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    machine.enable_banks(banks);
+    machine
+}
+
+#[test]
+fn switch_banks() {
+    // Print the strings at the start of the banks 1 and 0
+    let mut machine = machine(
+        "
+        loadimm r2 <- #4096
+        loadimm r1 <- #1
+        call print
+        loadimm r1 <- #0
+        call print
+        exit
+    print:
+        loadimm r3 <- #8192
+        store [r3] <- r1
+        loadimm r4 <- #4096
+        loadimm r5 <- #-1
+    print_loop:
+        load8 r6 <- [r4]
+        bz r6, print_end
+        out r6
+        sub r4 <- r4 - r5
+        jmp print_loop
+    print_end:
+        ret
+    ",
+        2,
+    );
+    machine.bank_mut(0).unwrap()[..6].copy_from_slice(b"world\0");
+    machine.bank_mut(1).unwrap()[..7].copy_from_slice(b"hello \0");
+    machine.enable_sanitizer();
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    assert_eq!("hello world", String::from_utf8(out).unwrap());
+    assert_eq!(0, machine.selected_bank());
+    assert!(machine.sanitizer_reports().is_empty());
+}
+
+#[test]
+fn stores_and_port() {
+    let mut machine = machine(
+        "
+        loadimm r1 <- #8192
+        loadimm r2 <- #2
+        store8 [r1] <- r2
+        loadimm r3 <- #8191
+        loadimm32 r4 <- #0x11223344
+        store [r3] <- r4
+        load r5 <- [r1]
+        load16 r6 <- [r1]
+        exit
+    ",
+        3,
+    );
+    machine.run_on(&mut vec![]).unwrap();
+    // The last byte of the window went to the bank 2, and the others to the
+    // port, selecting another bank
+    assert_eq!(0x44, machine.bank(2).unwrap()[4095]);
+    assert_eq!(0x112233, machine.selected_bank());
+    assert_eq!([0x112233, 0x2233], machine.regs()[5..7]);
+    assert_eq!(BANK_WINDOW + 4096, BANK_SELECT);
+}
+
+#[test]
+fn invalid_accesses() {
+    // Without banks, the window is outside of the memory
+    let source = "loadimm r1 <- #4096\nload r1 <- [r1]\nexit\n";
+    assert_eq!(Err(MachineError::InvalidMemAddr), machine(source, 0).run_on(&mut vec![]));
+    assert_eq!(Ok(()), machine(source, 1).run_on(&mut vec![]));
+
+    // Nor is a bank which does not exist visible, or anything above the port
+    let mut machine = machine("loadimm r1 <- #8192\nstore [r1] <- r1\nload r1 <- [r1]\nloadimm r2 <- #4096\nload r2 <- [r2]\n", 2);
+    assert_eq!(Err(MachineError::InvalidMemAddr), machine.run_on(&mut vec![]));
+    assert_eq!(8192, machine.regs()[1]);
+    assert_eq!(14, machine.last_ip());
+    assert_eq!(Err(MachineError::InvalidMemAddr), machine.read_mem(BANK_SELECT + 4));
+    assert_eq!(None, machine.bank(2));
+}
@@ -1,3 +1,5 @@
+#![cfg(feature = "std")]
+
 use interpreter::Machine;
 use std::io::{self, Write};
 
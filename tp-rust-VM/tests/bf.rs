@@ -0,0 +1,77 @@
+#![cfg(feature = "std")]
+
+use interpreter::{bf2vm, Instruction, Machine, MachineError};
+
+const HELLO: &str = "
+    Prints Hello World! with a newline
+    ++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.
+";
+
+fn run(source: &str, input: &[u8]) -> Result<Vec<u8>, MachineError> {
+    let mut machine = Machine::new(&bf2vm(source).unwrap().image().unwrap());
+    let mut out = vec![];
+    machine.run_with(&mut &input[..], &mut out)?;
+    Ok(out)
+}
+
+#[test]
+fn hello_world() {
+    assert_eq!(&b"Hello World!\n"[..], &run(HELLO, b"").unwrap()[..]);
+}
+
+#[test]
+fn input_and_end_of_input() {
+    // Copy the input, reversed
+    assert_eq!(&b"olleh"[..], &run(">,[>,]<[.<]", b"hello").unwrap()[..]);
+    // A cell read at the end of the input is set to 0
+    assert_eq!(&[0, 6][..], &run("+++++,.++++++.", b"").unwrap()[..]);
+}
+
+#[test]
+fn cells_are_bytes() {
+    // 0 - 191 wraps to 65, and 256 increments wrap to 0
+    let source = format!("{}.", "-".repeat(191));
+    assert_eq!(&b"A"[..], &run(&source, b"").unwrap()[..]);
+    let source = format!("{}.", "+".repeat(256 + 65));
+    assert_eq!(&b"A"[..], &run(&source, b"").unwrap()[..]);
+}
+
+#[test]
+fn runs_are_folded() {
+    let object = bf2vm("++++++++>>>>").unwrap();
+    let code = &object.sections[0].data;
+    // loadimm r10, loadimm r13, then a single load/add/store and a single
+    // pointer move
+    assert_eq!(Instruction::LoadImm { dst: 12, imm: -8 }, Instruction::decode(code, 11).unwrap());
+    assert_eq!(Instruction::LoadImm { dst: 12, imm: -4 }, Instruction::decode(code, 22).unwrap());
+    assert_eq!(Instruction::Exit, Instruction::decode(code, 30).unwrap());
+}
+
+#[test]
+fn tape_bounds() {
+    // Moving left of the tape reaches the code, moving right past the end
+    // of the memory faults
+    assert!(matches!(run("+[>+]", b""), Err(MachineError::InvalidMemAddr)));
+}
+
+#[test]
+fn unbalanced_brackets() {
+    assert_eq!(2, bf2vm("+\n]").unwrap_err().line);
+    assert_eq!(1, bf2vm("[\n[]").unwrap_err().line);
+    assert!(bf2vm("[[]").unwrap_err().message.contains("`[`"));
+}
+
+#[test]
+fn input_instruction() {
+    // 0: in r1
+    // 2: in r2
+    let mut machine = Machine::new(&[28, 1, 28, 2]);
+    machine.step_with(&mut &b"A"[..], &mut vec![]).unwrap();
+    machine.step_with(&mut &b""[..], &mut vec![]).unwrap();
+    assert_eq!(&[65, u32::MAX], &machine.regs()[1..3]);
+    assert_eq!("in r1", Instruction::decode(&[28, 1], 0).unwrap().to_string());
+    // Programs run without input see the end of the input
+    let mut machine = Machine::new(&[28, 1, 7]);
+    machine.run_on(&mut vec![]).unwrap();
+    assert_eq!(u32::MAX, machine.regs()[1]);
+}
@@ -0,0 +1,76 @@
+#![cfg(feature = "std")]
+
+use interpreter::{Cfg, EdgeKind, Instruction, Terminator};
+
+#[test]
+fn decode_listing_format() {
+    let code = [4, 2, 0x00, 0x10, 1, 0, 5, 4, 3, 11, 3, 8, 7];
+    let mut addr = 0;
+    let mut text = vec![];
+    while addr < code.len() {
+        let instr = Instruction::decode(&code, addr).unwrap();
+        let mut bytes = vec![];
+        instr.encode(&mut bytes);
+        assert_eq!(&code[addr..addr + instr.size()], &bytes[..]);
+        text.push(instr.to_string());
+        addr += instr.size();
+    }
+    assert_eq!(
+        vec!["loadimm r2 <- #4096", "move r0 <- r5 if r4 != 0", "load r11 <- [r3]", "out_number r7"],
+        &text[..4]
+    );
+    assert!(Instruction::decode(&[4, 1, 2], 0).is_err());
+    assert!(Instruction::decode(&[0], 0).is_err());
+}
+
+#[test]
+fn hello_world_cfg() {
+    // See tests/examples/hello_world.dis for the labels
+    let cfg = Cfg::build(include_bytes!("examples/hello_world.bin"), 0);
+
+    let entry = cfg.block(0).unwrap();
+    assert_eq!(Terminator::Call { target: 92, return_to: 53 }, entry.terminator);
+    assert_eq!(Terminator::Exit, cfg.block(53).unwrap().terminator);
+
+    // print_loop_1
+    assert_eq!(Terminator::Branch { taken: 104, fallthrough: 100 }, cfg.block(92).unwrap().terminator);
+    assert_eq!(Terminator::Jump(129), cfg.block(100).unwrap().terminator);
+    assert_eq!(Terminator::Jump(92), cfg.block(104).unwrap().terminator);
+    assert_eq!(Terminator::Return, cfg.block(129).unwrap().terminator);
+
+    // The string after the code is never decoded
+    assert_eq!(148, cfg.blocks().map(|b| b.end()).max().unwrap());
+    assert_eq!(6, cfg.blocks().count());
+}
+
+#[test]
+fn fallthrough_into_branch_target() {
+    // 0: loadimm r1 <- #12
+    // 4: move r0 <- r1 if r5 != 0
+    // 8: out r5
+    // 10: out r5
+    // 12: exit
+    let code = [4, 1, 12, 0, 1, 0, 1, 5, 6, 5, 6, 5, 7];
+    let cfg = Cfg::build(&code, 0);
+    assert_eq!(Terminator::Fallthrough(12), cfg.block(8).unwrap().terminator);
+    assert_eq!(
+        vec![(12, EdgeKind::Taken), (8, EdgeKind::NotTaken)],
+        cfg.block(0).unwrap().successors()
+    );
+}
+
+#[test]
+fn invalid_target() {
+    // 0: loadimm r0 <- #100
+    let cfg = Cfg::build(&[4, 0, 100, 0], 0);
+    assert_eq!(Terminator::Invalid, cfg.block(100).unwrap().terminator);
+}
+
+#[test]
+fn dot_export() {
+    let dot = Cfg::build(include_bytes!("examples/99bottles.bin"), 0).to_dot();
+    assert!(dot.starts_with("digraph cfg {"));
+    assert!(dot.contains("b0000 [label=\"0000   loadimm r2 <- #4096\\l0004   loadimm r7 <- #99\\l\"];"));
+    assert!(dot.contains("b0182 -> b0244 [label=\"taken\"];"));
+    assert!(dot.trim_end().ends_with('}'));
+}
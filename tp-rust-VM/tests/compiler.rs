@@ -0,0 +1,215 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, compile, Isa, Machine};
+
+fn run(source: &str, isa: Isa) -> String {
+    let object = assemble(&compile(source, isa).unwrap()).unwrap();
+    let mut machine = Machine::load_object(&object.to_bytes()).unwrap();
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn run_image(code: &[u8]) -> String {
+    let mut machine = Machine::new(code);
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Every opcode of the assembled program, in order.
+fn opcodes(source: &str, isa: Isa) -> Vec<u8> {
+    let code = assemble(&compile(source, isa).unwrap()).unwrap().sections.remove(0).data;
+    let mut addr = 0;
+    let mut opcodes = vec![];
+    while addr < code.len() {
+        opcodes.push(code[addr]);
+        addr += interpreter::Instruction::decode(&code, addr).unwrap().size();
+    }
+    opcodes
+}
+
+const FACTORIAL: &str = r#"
+fn fact(n) {
+    if (n == 0) {
+        return 1;
+    }
+    return n * fact(n - 1);
+}
+
+fn main() {
+    print("I will compute some factorials for you\n");
+    var i = 1;
+    while (i != 11) {
+        print("fact(", i, ") = ", fact(i), "\n");
+        i = i + 1;
+    }
+    print("I'm done!\n");
+}
+"#;
+
+const FIBONACCI: &str = r#"
+fn main() {
+    print("I will compute some Fibonacci numbers for you\n");
+    var a = 0;
+    var b = 1;
+    var i = 1;
+    while (i != 24) {
+        print("fibo(", i, ") = ", b, "\n");
+        var next = a + b;
+        a = b;
+        b = next;
+        i = i + 1;
+    }
+    print("I'm done!\n");
+}
+"#;
+
+const BOTTLES: &str = r#"
+fn bottles(n, capital) {
+    if (n == 0) {
+        if (capital) { print("No more bottles"); } else { print("no more bottles"); }
+    } else if (n == 1) {
+        if (capital) { print("One bottle"); } else { print("one bottle"); }
+    } else {
+        print(n, " bottles");
+    }
+    print(" of beer");
+}
+
+fn main() {
+    var n = 99;
+    while (n != -1) {
+        bottles(n, 1);
+        print(" on the wall, ");
+        bottles(n, 0);
+        if (n != 0) {
+            print(".\nTake one down, pass it around, ");
+            n = n - 1;
+        } else {
+            print(".\nGo to the store and buy some more, ");
+            n = 99;
+        }
+        bottles(n, 1);
+        print(" on the wall...\n");
+        if (n == 99) { return; }
+        print("\n");
+    }
+}
+"#;
+
+#[test]
+fn factorial() {
+    assert_eq!(run_image(include_bytes!("examples/factorial.bin")), run(FACTORIAL, Isa::Base));
+    assert_eq!(run_image(include_bytes!("examples/factorial.bin")), run(FACTORIAL, Isa::Extended));
+}
+
+#[test]
+fn fibonacci() {
+    assert_eq!(run_image(include_bytes!("examples/fibonacci.bin")), run(FIBONACCI, Isa::Base));
+}
+
+#[test]
+fn bottles() {
+    assert_eq!(run_image(include_bytes!("examples/99bottles.bin")), run(BOTTLES, Isa::Base));
+}
+
+#[test]
+fn base_isa_only_uses_original_opcodes() {
+    for source in [FACTORIAL, FIBONACCI, BOTTLES] {
+        assert!(opcodes(source, Isa::Base).iter().all(|&op| (1..=8).contains(&op)));
+    }
+
+    // Constants which do not fit in 16 bits are built without `loadimm32`
+    let source = "
+    fn main() {
+        print(100000, \" \", 32768, \" \", -32769, \" \", 2147483647, \" \", 4294967295, \" \", 98304);
+    }
+    ";
+    assert!(opcodes(source, Isa::Base).iter().all(|&op| (1..=8).contains(&op)));
+    assert_eq!("100000 32768 -32769 2147483647 -1 98304", run(source, Isa::Base));
+    assert!(opcodes(source, Isa::Extended).contains(&9));
+}
+
+#[test]
+fn expressions() {
+    let source = "
+    fn sub3(a, b, c) { return a - b - c; }
+    fn main() {
+        var x = 6;
+        print(x * 7, \" \", -x + 2, \" \", sub3(10, 2, 3), \" \", !x, !0, \" \", 2 * (3 + 4) - 1);
+        print(\" \", x == 6, x != 6, (x == 5) == 0);
+    }
+    ";
+    assert_eq!("42 -4 5 01 13 101", run(source, Isa::Base));
+}
+
+#[test]
+fn negative_multiplications() {
+    let source = "
+    fn main() {
+        print(-3 * 4, \" \", 3 * -4, \" \", -3 * -4, \" \", 0 * -4, \" \", -1 * 0);
+    }
+    ";
+    assert_eq!("-12 -12 12 0 0", run(source, Isa::Base));
+    assert_eq!("-12 -12 12 0 0", run(source, Isa::Extended));
+}
+
+#[test]
+fn ordered_comparisons() {
+    let source = "
+    fn main() {
+        var min = -2147483647 - 1;
+        print(min < 1, 1 < min, min <= min, 3 > 2, 2 >= 3, -3 * 4, 4 * -3);
+    }
+    ";
+    assert_eq!("10110-12-12", run(source, Isa::Extended));
+    let error = compile(source, Isa::Base).unwrap_err();
+    assert_eq!(4, error.line);
+    assert!(error.message.contains("extended"));
+}
+
+#[test]
+fn comments() {
+    let source = "
+    // Comments end the line, but not inside strings
+    fn main() {
+        print(\"a//b\"); // print(\"c\");
+        print(1);// 2
+    }
+    ";
+    assert_eq!("a//b1", run(source, Isa::Base));
+}
+
+#[test]
+fn scopes_and_recursion() {
+    let source = "
+    fn fib(n) {
+        if (n == 0) { return 0; }
+        if (n == 1) { return 1; }
+        return fib(n - 1) + fib(n - 2);
+    }
+    fn main() {
+        var x = 1;
+        if (x) {
+            var x = 2;
+            print(x);
+        }
+        print(x, fib(15));
+    }
+    ";
+    assert_eq!("21610", run(source, Isa::Base));
+}
+
+#[test]
+fn errors() {
+    let error = |source: &str| compile(source, Isa::Base).unwrap_err();
+    assert_eq!(2, error("fn main() {\n    print(y);\n}").line);
+    assert!(error("fn main() { f(); }").message.contains("unknown function"));
+    assert!(error("fn f(a) {}\nfn main() { f(); }").message.contains("expects 1"));
+    assert!(error("fn f() {}").message.contains("main"));
+    assert_eq!(3, error("fn main() {\n\n    var = 3;\n}").line);
+    assert!(error("fn main() { print(\"abc); }").message.contains("unterminated"));
+    assert!(error("fn main() {}\nfn main() {}").message.contains("twice"));
+    assert!(error("fn main() { x = 99999999999; }").message.contains("32 bits"));
+}
@@ -0,0 +1,136 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, decompile, ObjectFile};
+
+fn decompile_object(object: &ObjectFile) -> String {
+    decompile(&object.image().unwrap(), object.entry, Some(&object.symbol_table()))
+}
+
+#[test]
+fn recursive_factorial() {
+    let expected = "\
+fn main() {
+    r2 = 4096;
+    f_0087();
+    exit();
+}
+
+fn f_0024() {
+    r13 = r1 - r11;
+    r14 = r12;
+    while ((r8 = r14 - 1) != 0) {
+        r11 -= r13;
+        r14 -= 1;
+    }
+    return;
+}
+
+fn f_0087() {
+    r8 = r10 - 1;
+    if (r8 != 0) {
+        push(r10);
+        r10 -= 1;
+        f_0087();
+        r12 = pop();
+        return f_0024();
+    }
+    r11 = 1;
+    return;
+}
+";
+    assert_eq!(expected, decompile(include_bytes!("rfact_tr.bin"), 0, None));
+}
+
+#[test]
+fn functions_are_named_after_symbols() {
+    let text = decompile_object(&assemble(include_str!("rfact_tr.dis")).unwrap());
+    assert!(text.contains("fn mult() {"));
+    assert!(text.contains("    rfact_tr();\n"));
+    assert!(text.contains("        return mult();\n"));
+}
+
+#[test]
+fn loops_and_saved_registers() {
+    let text = decompile(include_bytes!("examples/factorial.bin"), 0, None);
+    // print, with its loop over the characters
+    assert!(text.contains(
+        "
+fn f_0640() {
+    while (r11 != 0) {
+        r3 = mem32[r10];
+        out(r3);
+        r10 += 1;
+        r11 -= 1;
+    }
+    return;
+}
+"
+    ));
+    // The main loop, saving the registers around the calls to print
+    assert!(text.contains(
+        "
+    do {
+        r7 += 1;
+        save (r10, r11) {
+            r10 = 735;
+            r11 = 5;
+            f_0640();
+        }
+"
+    ));
+    assert!(text.contains("    } while (r4 != 0);\n"));
+}
+
+#[test]
+fn nested_conditionals() {
+    let text = decompile(include_bytes!("examples/99bottles.bin"), 0, None);
+    assert!(text.contains(
+        "
+fn f_0537() {
+    r8 = r7 - 1;
+    if (r8 != 0) {
+        if (r7 != 0) {
+            out_number(r7);
+"
+    ));
+}
+
+#[test]
+fn relative_branches_and_gotos() {
+    // A loop with two entries cannot be structured
+    let object = assemble(
+        "
+        in r1
+        bz r1, second
+    first:
+        out r1
+        bneg r1, end
+    second:
+        in r1
+        bnz r1, first
+    end:
+        exit
+    ",
+    )
+    .unwrap();
+    let expected = "\
+fn main() {
+    r1 = in();
+    if (r1 == 0) {
+    second:
+        r1 = in();
+        if (r1 != 0) {
+        first:
+            out(r1);
+            if (r1 >= 0) {
+                goto second;
+            }
+        }
+    } else {
+        goto first;
+    }
+    exit();
+}
+";
+    assert_eq!(expected, decompile_object(&object));
+}
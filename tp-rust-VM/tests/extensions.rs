@@ -0,0 +1,330 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Cfg, Comparison, Condition, Instruction, Machine, RelocationKind, Terminator};
+
+fn run(source: &str) -> (Machine, Vec<u8>) {
+    let object = assemble(source).unwrap();
+    let mut machine = Machine::load_object(&object.to_bytes()).unwrap();
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    (machine, out)
+}
+
+#[test]
+fn loadimm32() {
+    // 0: loadimm32 r1 <- #0x12345678
+    // 6: loadimm32 r0 <- #12
+    // 12: exit
+    let mut machine = Machine::new(&[9, 1, 0x78, 0x56, 0x34, 0x12, 9, 0, 12, 0, 0, 0, 7]);
+    machine.step_on(&mut vec![]).unwrap();
+    assert_eq!(0x12345678, machine.regs()[1]);
+    assert_eq!(6, machine.regs()[0]);
+    machine.step_on(&mut vec![]).unwrap();
+    assert_eq!(12, machine.regs()[0]);
+}
+
+#[test]
+fn loadhi() {
+    // 0: loadhi r1 <- #0xdead
+    let mut machine = Machine::new(&[10, 1, 0xad, 0xde]);
+    machine.set_reg(1, 0x1234beef).unwrap();
+    machine.step_on(&mut vec![]).unwrap();
+    assert_eq!(0xdeadbeef, machine.regs()[1]);
+    assert_eq!(4, machine.regs()[0]);
+}
+
+#[test]
+fn assembler_picks_encoding() {
+    let object = assemble("loadimm r1 <- #-32768\nloadimm r1 <- #32768\nloadimm r2 <- #-40000\n").unwrap();
+    let code = &object.sections[0].data;
+    assert_eq!(Instruction::LoadImm { dst: 1, imm: -32768 }, Instruction::decode(code, 0).unwrap());
+    assert_eq!(Instruction::LoadImm32 { dst: 1, imm: 32768 }, Instruction::decode(code, 4).unwrap());
+    assert_eq!(Instruction::LoadImm32 { dst: 2, imm: -40000i32 as u32 }, Instruction::decode(code, 10).unwrap());
+    assert_eq!("loadimm32 r2 <- #-40000", Instruction::decode(code, 10).unwrap().to_string());
+
+    let (machine, out) = run("
+        loadimm r1 <- #2000000000
+        loadimm32 r2 <- #done       ; forced wide encoding
+        loadimm r3 <- #1
+        loadhi r3 <- #65535
+        out_number r1
+        move r0 <- r2 if r1 != 0
+        exit
+    done:
+        exit
+    ");
+    assert_eq!(&b"2000000000"[..], &out[..]);
+    assert_eq!(0xffff0001, machine.regs()[3]);
+    assert_eq!(28, machine.regs()[0]);
+}
+
+#[test]
+fn wide_label_relocation() {
+    // Labels referenced by wide loadimm are relocated as 32-bit words
+    let object = assemble(".extern far\nloadimm32 r1 <- #far\n").unwrap();
+    let far = assemble(".global far\nfar:\nexit\n").unwrap();
+    let program = interpreter::link(&[object, far]).unwrap();
+    assert_eq!(
+        Instruction::LoadImm32 { dst: 1, imm: 6 },
+        Instruction::decode(&program.image().unwrap(), 0).unwrap()
+    );
+}
+
+#[test]
+fn narrow_loads() {
+    // 0: load8 r1 <- [r5]
+    // 3: load8s r2 <- [r5]
+    // 6: load16 r3 <- [r5]
+    // 9: load16s r4 <- [r5]
+    // 12: exit
+    // 13: 0xfe 0x80
+    let mut machine = Machine::new(&[11, 1, 5, 12, 2, 5, 13, 3, 5, 14, 4, 5, 7, 0xfe, 0x80]);
+    machine.set_reg(5, 13).unwrap();
+    machine.run_on(&mut vec![]).unwrap();
+    assert_eq!(&[0xfe, 0xfffffffe, 0x80fe, 0xffff80fe], &machine.regs()[1..5]);
+
+    // A byte can be loaded from the last address, but not a halfword
+    let mut machine = Machine::new(&[11, 1, 5]);
+    machine.set_reg(5, 4095).unwrap();
+    assert!(machine.step().is_ok());
+    let mut machine = Machine::new(&[13, 1, 5]);
+    machine.set_reg(5, 4095).unwrap();
+    assert!(machine.step().is_err());
+}
+
+#[test]
+fn narrow_stores() {
+    // 0: store8 [r5] <- r1
+    // 3: store16 [r6] <- r1
+    // 6: exit
+    // 7: 0x11 0x22 0x33 0x44 0x55
+    let mut machine = Machine::new(&[15, 5, 1, 16, 6, 1, 7, 0x11, 0x22, 0x33, 0x44, 0x55]);
+    machine.set_reg(1, 0xaabbccdd).unwrap();
+    machine.set_reg(5, 8).unwrap();
+    machine.set_reg(6, 10).unwrap();
+    machine.run_on(&mut vec![]).unwrap();
+    assert_eq!(&[0x11, 0xdd, 0x33, 0xdd, 0xcc], &machine.memory()[7..12]);
+
+    let mut machine = Machine::new(&[16, 5, 1]);
+    machine.set_reg(5, 4095).unwrap();
+    assert!(machine.step().is_err());
+}
+
+#[test]
+fn byte_string_processing() {
+    // Uppercase a string in place, and print it one byte at a time
+    let source = "
+        loadimm r10 <- #text
+        loadimm r11 <- #5
+        loadimm r12 <- #32
+        loadimm r13 <- #1
+        loadimm r14 <- #-1
+    loop:
+        load8 r3 <- [r10]
+        sub r3 <- r3 - r12
+        store8 [r10] <- r3
+        out r3
+        sub r10 <- r10 - r14
+        sub r11 <- r11 - r13
+        loadimm r4 <- #loop
+        move r0 <- r4 if r11 != 0
+        exit
+    text:
+        b'hello!'
+    ";
+    let text = assemble(source).unwrap().symbol("text").unwrap() as usize;
+    let (machine, out) = run(source);
+    assert_eq!(&b"HELLO"[..], &out[..]);
+    assert_eq!(&b"HELLO!"[..], &machine.memory()[text..text + 6]);
+}
+
+#[test]
+fn narrow_accesses_and_sanitizer() {
+    // 0: store8 [r5] <- r1
+    // 3: load16 r1 <- [r5]
+    // 6: exit
+    let mut machine = Machine::new(&[15, 5, 1, 13, 1, 5, 7]);
+    machine.enable_sanitizer();
+    machine.set_reg(5, 100).unwrap();
+    machine.run_on(&mut vec![]).unwrap();
+    assert_eq!(1, machine.sanitizer_reports().len());
+    assert_eq!(101, machine.sanitizer_reports()[0].addr);
+}
+
+#[test]
+fn relative_branches() {
+    // 0: jmp #1
+    // 3: exit
+    // 4: bz r1, #-5
+    // 8: bnz r1, #2
+    // 12: exit
+    // 13: exit
+    // 14: bneg r2, #-15
+    let code = [17, 1, 0, 7, 18, 1, 0xfb, 0xff, 19, 1, 2, 0, 7, 7, 20, 2, 0xf1, 0xff];
+    let mut machine = Machine::new(&code);
+    machine.set_reg(1, 1).unwrap();
+    machine.set_reg(2, -1i32 as u32).unwrap();
+    machine.step_on(&mut vec![]).unwrap();
+    assert_eq!(4, machine.regs()[0]);
+    machine.step_on(&mut vec![]).unwrap();
+    assert_eq!(8, machine.regs()[0]);
+    machine.step_on(&mut vec![]).unwrap();
+    assert_eq!(14, machine.regs()[0]);
+    machine.step_on(&mut vec![]).unwrap();
+    assert_eq!(3, machine.regs()[0]);
+
+    assert_eq!("bz r1, #-5", Instruction::decode(&code, 4).unwrap().to_string());
+    assert_eq!(Some(3), Instruction::decode(&code, 4).unwrap().relative_target(4));
+    assert_eq!(Some(3), Instruction::decode(&code, 14).unwrap().relative_target(14));
+}
+
+// Count down from 3, and call a subroutine printing the counter at each
+// iteration. No instruction depends on the address the code is loaded at,
+// except the return address pushed on the stack.
+const COUNTDOWN: &str = "
+        loadimm r1 <- #3
+        loadimm r4 <- #1
+    loop:
+        jmp print_r1_call
+    print_r1_return:
+        sub r1 <- r1 - r4
+        bnz r1, loop
+        exit
+    print_r1_call:
+        out_number r1
+        jmp print_r1_return
+";
+
+#[test]
+fn position_independent_code() {
+    let code = assemble(COUNTDOWN).unwrap().image().unwrap();
+    for base in [0, 1, 1000] {
+        let mut memory = vec![0; base];
+        memory.extend(&code);
+        let mut machine = Machine::new(&memory);
+        machine.set_reg(0, base as u32).unwrap();
+        let mut out = vec![];
+        machine.run_on(&mut out).unwrap();
+        assert_eq!(&b"321"[..], &out[..]);
+    }
+}
+
+#[test]
+fn relative_branch_assembly() {
+    let object = assemble("start:\nbneg r3, #-4\njmp start\nbz r1, end\nend:\n").unwrap();
+    let code = &object.sections[0].data;
+    assert_eq!(Instruction::Branch { cond: Condition::Negative, src: 3, offset: -4 }, Instruction::decode(code, 0).unwrap());
+    assert_eq!(Instruction::Jump { offset: -7 }, Instruction::decode(code, 4).unwrap());
+    assert_eq!(Instruction::Branch { cond: Condition::Zero, src: 1, offset: 0 }, Instruction::decode(code, 7).unwrap());
+    assert!(assemble("jmp #40000").is_err());
+    assert!(assemble("bz r1 end\nend:").is_err());
+}
+
+#[test]
+fn relative_call_across_objects() {
+    let main = assemble(
+        "
+        .extern say_hi
+        loadimm r2 <- #4096
+        loadimm r3 <- #4
+        sub r2 <- r2 - r3
+        loadimm r3 <- #back
+        store [r2] <- r3
+        jmp say_hi
+    back:
+        exit
+    ",
+    )
+    .unwrap();
+    assert_eq!(RelocationKind::Rel16, main.relocations.last().unwrap().kind);
+    let lib = assemble(
+        "
+        .global say_hi
+        [0, 0, 0]
+    say_hi:
+        loadimm r1 <- #72
+        out r1
+        load r0 <- [r2]
+    ",
+    )
+    .unwrap();
+    let lib = interpreter::ObjectFile::from_bytes(&lib.to_bytes()).unwrap();
+    let program = interpreter::link(&[main, lib]).unwrap();
+    let mut machine = Machine::new(&program.image().unwrap());
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"H"[..], &out[..]);
+
+    let cfg = Cfg::build(&program.image().unwrap(), 0);
+    let call = cfg.blocks().find_map(|b| match b.terminator {
+        Terminator::Call { target, return_to } => Some((target, return_to)),
+        _ => None,
+    });
+    let symbol = |name| program.symbol(name).unwrap() as usize;
+    assert_eq!(Some((symbol("say_hi"), symbol("back"))), call);
+}
+
+#[test]
+fn comparisons() {
+    let values = [i32::MIN as u32, -1i32 as u32, 0, 1, i32::MAX as u32, u32::MAX - 1];
+    for op in Comparison::ALL {
+        for &lhs in &values {
+            for &rhs in &values {
+                let mut code = vec![];
+                Instruction::Compare { op, dst: 1, lhs: 2, rhs: 3 }.encode(&mut code);
+                let mut machine = Machine::new(&code);
+                machine.set_reg(2, lhs).unwrap();
+                machine.set_reg(3, rhs).unwrap();
+                machine.step_on(&mut vec![]).unwrap();
+                let expected = match op {
+                    Comparison::Slt => (lhs as i32) < (rhs as i32),
+                    Comparison::Sle => (lhs as i32) <= (rhs as i32),
+                    Comparison::Ult => lhs < rhs,
+                    Comparison::Ule => lhs <= rhs,
+                    Comparison::Eq => lhs == rhs,
+                };
+                assert_eq!(expected as u32, machine.regs()[1], "{op:?} {lhs} {rhs}");
+            }
+        }
+    }
+}
+
+#[test]
+fn signed_less_than_without_overflow() {
+    // Subtracting 1 from i32::MIN overflows and gives a positive number,
+    // slt does not
+    let (machine, out) = run("
+        loadimm32 r1 <- #-2147483648
+        loadimm r2 <- #1
+        sub r3 <- r1 - r2
+        slt r4 <- r1, r2
+        ult r5 <- r1, r2
+        eq r6 <- r2, r2
+        out_number r4
+        exit
+    ");
+    assert_eq!(&b"1"[..], &out[..]);
+    assert!((machine.regs()[3] as i32) > 0);
+    assert_eq!(&[1, 0, 1], &machine.regs()[4..7]);
+}
+
+#[test]
+fn conditional_moves() {
+    let (machine, _) = run("
+        loadimm r1 <- #0
+        loadimm r2 <- #-5
+        loadimm r3 <- #7
+        move r4 <- r3 if r1 == 0
+        move r5 <- r3 if r2 == 0
+        move r6 <- r3 if r2 < 0
+        move r7 <- r3 if r3 < 0
+        exit
+    ");
+    assert_eq!(&[7, 0, 7, 0], &machine.regs()[4..8]);
+
+    let object = assemble("sle r1 <- r2, r3\nmove r0 <- r1 if r2 < 0\nmove r0 <- r1 if r2 == 0\n").unwrap();
+    let code = &object.sections[0].data;
+    assert_eq!(&[22, 1, 2, 3, 27, 0, 1, 2, 26, 0, 1, 2], &code[..]);
+    assert_eq!("sle r1 <- r2, r3", Instruction::decode(code, 0).unwrap().to_string());
+    assert_eq!("move r0 <- r1 if r2 < 0", Instruction::decode(code, 4).unwrap().to_string());
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, decompile, FloatOp, Instruction, Machine, MachineError, RangeAnalysis};
+
+fn run(source: &str) -> (Machine, String) {
+    let object = assemble(source).unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    machine.enable_float();
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    (machine, String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn disabled_by_default() {
+    let object = assemble("loadimm32 r1 <- #1.5\nfadd r1 <- r1 + r1\nexit\n").unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    assert_eq!(Err(MachineError::InvalidOpcode), machine.run_on(&mut vec![]));
+    assert_eq!(6, machine.last_ip());
+    assert_eq!(1.5f32.to_bits(), machine.regs()[1]);
+}
+
+#[test]
+fn arithmetic() {
+    let (machine, out) = run("
+        loadimm32 r1 <- #1.5
+        loadimm32 r2 <- #-0.25
+        fadd r3 <- r1 + r2
+        fsub r4 <- r1 - r2
+        fmul r5 <- r1 * r2
+        fdiv r6 <- r1 / r2
+        out_float r3
+        loadimm r7 <- #32
+        out r7
+        out_float r6
+        out r7
+        loadimm r8 <- #0
+        fdiv r8 <- r1 / r8
+        out_float r8
+        exit
+    ");
+    assert_eq!("1.25 -6 inf", out);
+    let regs = machine.regs();
+    assert_eq!([1.25, 1.75, -0.375, -6.0], [3, 4, 5, 6].map(|r| f32::from_bits(regs[r])));
+}
+
+#[test]
+fn conversions() {
+    let (machine, out) = run("
+        loadimm r1 <- #-7
+        itof r2 <- r1
+        loadimm32 r3 <- #2.99
+        ftoi r4 <- r3
+        loadimm32 r5 <- #-1e10
+        ftoi r6 <- r5
+        loadimm32 r7 <- #0x7fc00000     ; NaN
+        ftoi r8 <- r7
+        out_float r2
+        out_float r7
+        exit
+    ");
+    assert_eq!("-7NaN", out);
+    let regs = machine.regs();
+    assert_eq!(-7.0, f32::from_bits(regs[2]));
+    assert_eq!([2, i32::MIN, 0], [4, 6, 8].map(|r| regs[r] as i32));
+}
+
+#[test]
+fn comparisons() {
+    let (machine, _) = run("
+        loadimm32 r1 <- #-2.5
+        loadimm32 r2 <- #0.5
+        loadimm32 r3 <- #0x7fc00000     ; NaN
+        flt r4 <- r1, r2
+        flt r5 <- r2, r1
+        fle r6 <- r2, r2
+        feq r7 <- r3, r3
+        fle r8 <- r3, r2
+        exit
+    ");
+    assert_eq!([1, 0, 1, 0, 0], machine.regs()[4..9]);
+}
+
+#[test]
+fn gamma_table() {
+    // Gamma correction of 0.5 on 8 bits with x^2.5 = x * x * sqrt(x), the
+    // square root being computed with Newton iterations
+    let (_, out) = run("
+        loadimm32 r1 <- #0.5
+        fmul r4 <- r1 * r1
+        ; r5 = sqrt(r1), starting from 1
+        loadimm32 r5 <- #1.0
+        loadimm32 r9 <- #0.5
+        loadimm r10 <- #8
+        loadimm r11 <- #1
+    loop:
+        fdiv r6 <- r1 / r5
+        fadd r6 <- r5 + r6
+        fmul r5 <- r6 * r9
+        sub r10 <- r10 - r11
+        bnz r10, loop
+        fmul r4 <- r4 * r5
+        loadimm32 r12 <- #255.0
+        fmul r4 <- r4 * r12
+        ftoi r4 <- r4
+        out_number r4
+        exit
+    ");
+    assert_eq!("45", out);
+}
+
+#[test]
+fn encoding_and_listing() {
+    let object = assemble("fmul r1 <- r2 * r3\nitof r4 <- r5\nfeq r1 <- r2, r3\nout_float r1\n.word 1.0\n").unwrap();
+    let code = &object.sections[0].data;
+    assert_eq!(&[31, 1, 2, 3, 33, 4, 5, 37, 1, 2, 3, 38, 1, 0, 0, 0x80, 0x3f], &code[..]);
+    let instr = Instruction::decode(code, 0).unwrap();
+    assert_eq!(Instruction::FloatArith { op: FloatOp::Mul, dst: 1, lhs: 2, rhs: 3 }, instr);
+    let listing: Vec<String> =
+        [0, 4, 7, 11].iter().map(|&addr| Instruction::decode(code, addr).unwrap().to_string()).collect();
+    assert_eq!(["fmul r1 <- r2 * r3", "itof r4 <- r5", "feq r1 <- r2, r3", "out_float r1"], &listing[..]);
+    assert!(assemble("fadd r1 <- r2 - r3\n").is_err());
+
+    let code = assemble("fdiv r1 <- r2 / r3\nftoi r4 <- r1\nexit\n").unwrap().image().unwrap();
+    let pseudo = decompile(&code, 0, None);
+    assert!(pseudo.contains("r1 = bits(float(r2) / float(r3))"), "{pseudo}");
+    assert!(pseudo.contains("r4 = (int)float(r1)"), "{pseudo}");
+}
+
+#[test]
+fn constant_ranges() {
+    // The converted index is known, so the store is checked
+    let code = assemble("
+        loadimm32 r1 <- #5000.7
+        ftoi r1 <- r1
+        store8 [r1] <- r1
+        exit
+    ")
+    .unwrap()
+    .image()
+    .unwrap();
+    let analysis = RangeAnalysis::run(&code, 0);
+    assert_eq!(1, analysis.warnings().len());
+    assert_eq!(Some(5000), analysis.before(9).unwrap()[1].as_constant());
+}
@@ -0,0 +1,101 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, HandleError, Machine, MachineError, MachineHandle, MachineObserver, Status};
+
+fn spawn(source: &str) -> MachineHandle {
+    let object = assemble(source).unwrap();
+    MachineHandle::spawn(Machine::new(&object.image().unwrap()))
+}
+
+const COUNTER: &str = "
+        loadimm r3 <- #-1
+    loop:
+        sub r1 <- r1 - r3
+        jmp loop
+";
+
+#[test]
+fn run_to_completion() {
+    let handle = MachineHandle::spawn(Machine::new(include_bytes!("examples/factorial.bin")));
+    assert_eq!(Ok(Status::Exited), handle.wait());
+    let output: Vec<u8> = handle.output().try_iter().flatten().collect();
+
+    let mut expected = vec![];
+    Machine::new(include_bytes!("examples/factorial.bin")).run_on(&mut expected).unwrap();
+    assert_eq!(String::from_utf8(expected).unwrap(), String::from_utf8(output).unwrap());
+
+    let machine = handle.stop().unwrap();
+    assert_eq!(&include_bytes!("examples/factorial.bin")[..], &machine.memory()[..755]);
+}
+
+#[test]
+fn pause_step_and_resume() {
+    let handle = spawn(COUNTER);
+    assert_eq!(Ok(Status::Paused), handle.pause());
+    assert_eq!(Ok(Status::Paused), handle.status());
+
+    // A full iteration of the loop, starting at 4, increments r1
+    while handle.regs().unwrap()[0] != 4 {
+        assert_eq!(Ok(Status::Paused), handle.step());
+    }
+    let regs = handle.regs().unwrap();
+    assert_eq!(Ok(Status::Paused), handle.step());
+    assert_eq!(Ok(Status::Paused), handle.step());
+    assert_eq!([4, regs[1] + 1], handle.regs().unwrap()[..2]);
+    assert_eq!(4, handle.memory().unwrap()[0]);
+
+    assert_eq!(Ok(Status::Running), handle.resume());
+    assert_eq!(Err(HandleError::NotPaused), handle.regs());
+    assert_eq!(Err(HandleError::NotPaused), handle.step());
+    assert_eq!(Ok(Status::Paused), handle.pause());
+    assert!(handle.regs().unwrap()[1] > regs[1]);
+
+    // The machine keeps its state once stopped
+    let r1 = handle.regs().unwrap()[1];
+    assert_eq!(r1, handle.stop().unwrap().regs()[1]);
+}
+
+#[test]
+fn faults() {
+    let handle = spawn("loadimm r1 <- #4096\nload r2 <- [r1]\n");
+    assert_eq!(Ok(Status::Faulted(MachineError::InvalidMemAddr)), handle.wait());
+    assert_eq!(Ok(Status::Faulted(MachineError::InvalidMemAddr)), handle.step());
+    assert_eq!(Ok(Status::Faulted(MachineError::InvalidMemAddr)), handle.resume());
+    assert_eq!(4096, handle.regs().unwrap()[1]);
+}
+
+#[test]
+fn streamed_output() {
+    let handle = spawn(
+        "
+        loadimm r1 <- #65
+    loop:
+        out r1
+        jmp loop
+    ",
+    );
+    // The output arrives while the machine is running
+    for _ in 0..3 {
+        assert_eq!(b"A".to_vec(), handle.output().recv().unwrap());
+    }
+    assert_eq!(Ok(Status::Running), handle.status());
+    assert!(handle.stop().is_ok());
+}
+
+struct Panic;
+
+impl MachineObserver for Panic {
+    fn on_output(&mut self, _bytes: &[u8]) {
+        panic!("observer failure");
+    }
+}
+
+#[test]
+fn panicked_thread() {
+    let object = assemble("loadimm r1 <- #65\nout r1\nexit\n").unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    machine.attach(Panic);
+    let handle = MachineHandle::spawn(machine);
+    assert_eq!(Err(HandleError::Disconnected), handle.wait());
+    assert!(matches!(handle.stop(), Err(HandleError::Disconnected)));
+}
@@ -0,0 +1,152 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, link, LinkError, Machine, ObjectError, ObjectFile};
+
+// Runtime library: mult (r11 <- r11 * r12) and print (r11 bytes at r10)
+const RUNTIME: &str = "
+    .global mult
+    .global print
+mult:
+    sub r13 <- r1 - r11
+    move r14 <- r12 if r0 != 0
+mult_loop:
+    loadimm r8 <- #1
+    sub r8 <- r14 - r8
+    loadimm r9 <- #ite_then_1
+    move r0 <- r9 if r8 != 0
+    loadimm r0 <- #ite_end_1
+ite_then_1:
+    sub r11 <- r11 - r13
+    loadimm r3 <- #1
+    sub r14 <- r14 - r3
+    loadimm r0 <- #mult_loop
+ite_end_1:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r0 <- [r3]
+print:
+    loadimm r8 <- #ite_then_2
+    move r0 <- r8 if r11 != 0
+    loadimm r0 <- #ite_end_2
+ite_then_2:
+    load r3 <- [r10]
+    out r3
+    loadimm r3 <- #-1
+    sub r10 <- r10 - r3
+    loadimm r3 <- #1
+    sub r11 <- r11 - r3
+    loadimm r0 <- #print
+ite_end_2:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r0 <- [r3]
+";
+
+const MAIN: &str = "
+    .extern mult
+    .extern print
+    loadimm r2 <- #4096
+    loadimm r10 <- #message
+    loadimm r11 <- #8
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #ite_then_1
+    store [r2] <- r3
+    loadimm r0 <- #print
+ite_then_1:
+    loadimm r11 <- #6
+    loadimm r12 <- #7
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_mult
+    store [r2] <- r3
+    loadimm r0 <- #mult
+return_from_mult:
+    out_number r11
+    exit
+    .data
+message:
+    b'6 * 7 = '
+";
+
+fn run(object: &ObjectFile) -> Vec<u8> {
+    let mut machine = Machine::load_object(&object.to_bytes()).unwrap();
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    out
+}
+
+#[test]
+fn link_with_runtime() {
+    let main = assemble(MAIN).unwrap();
+    let runtime = assemble(RUNTIME).unwrap();
+
+    // The external references cannot be loaded before linking
+    assert_eq!(Err(ObjectError::UnresolvedSymbol("print".to_string())), main.image());
+
+    let program = link(&[main.clone(), runtime.clone()]).unwrap();
+    assert_eq!(&b"6 * 7 = 42"[..], &run(&program)[..]);
+    assert!(program.relocations.is_empty());
+
+    // The code of both object files comes before the data
+    let code_size = main.sections[0].data.len() + runtime.sections[0].data.len();
+    assert_eq!(code_size as u32, program.sections[1].addr);
+    assert_eq!(Some(main.sections[0].data.len() as u32), program.symbol("mult"));
+
+    // Line entries follow their code, and keep the lines of their own source
+    assert_eq!(main.line_of(0), program.line_of(0));
+    assert_eq!(runtime.line_of(0), program.line_of(program.symbol("mult").unwrap()));
+    assert_eq!(main.line_of(main.symbol("message").unwrap()), program.line_of(program.symbol("message").unwrap()));
+    assert_eq!(main.lines.len() + runtime.lines.len(), program.lines.len());
+
+    // The entry point is the one of the first object file
+    let program = link(&[runtime, main]).unwrap();
+    assert_eq!(Some(0), program.symbol("mult"));
+    assert_eq!(0, program.entry);
+}
+
+#[test]
+fn link_serialized_objects() {
+    let objects: Vec<ObjectFile> = [MAIN, RUNTIME]
+        .iter()
+        .map(|source| ObjectFile::from_bytes(&assemble(source).unwrap().to_bytes()).unwrap())
+        .collect();
+    assert_eq!(&b"6 * 7 = 42"[..], &run(&link(&objects).unwrap())[..]);
+}
+
+#[test]
+fn link_errors() {
+    let main = assemble(MAIN).unwrap();
+    let runtime = assemble(RUNTIME).unwrap();
+    assert_eq!(Err(LinkError::UndefinedSymbol("print".to_string())), link(std::slice::from_ref(&main)));
+    assert_eq!(
+        Err(LinkError::DuplicateSymbol("mult".to_string())),
+        link(&[main, runtime.clone(), runtime])
+    );
+
+    // Symbols outside of their section are rejected before linking
+    let mut broken = assemble(RUNTIME).unwrap();
+    broken.sections[0].addr = 100;
+    assert_eq!(
+        Err(ObjectError::SymbolOutsideSection("mult".to_string())),
+        ObjectFile::from_bytes(&broken.to_bytes())
+    );
+
+    let mut lost = assemble(MAIN).unwrap();
+    lost.entry = 1000;
+    assert_eq!(Err(LinkError::InvalidEntry(1000)), link(&[lost, assemble(RUNTIME).unwrap()]));
+
+    let big = assemble("[0]\n".repeat(4097).as_str()).unwrap();
+    assert_eq!(Err(LinkError::OutOfMemory { size: 4097 }), link(&[big]));
+}
+
+#[test]
+fn local_labels_are_not_exported() {
+    let user = assemble(".extern mult_loop\nloadimm r0 <- #mult_loop\n").unwrap();
+    let runtime = assemble(RUNTIME).unwrap();
+    assert_eq!(Err(LinkError::UndefinedSymbol("mult_loop".to_string())), link(&[user, runtime]));
+}
@@ -0,0 +1,194 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Instruction, Machine};
+
+fn run(source: &str, r10: u32) -> (Machine, Vec<u8>) {
+    let object = assemble(source).unwrap();
+    let mut machine = Machine::load_object(&object.to_bytes()).unwrap();
+    machine.set_reg(10, r10).unwrap();
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    (machine, out)
+}
+
+#[test]
+fn push_pop() {
+    let source = "
+        loadimm r2 <- #4096
+        push r0
+        push r0
+        pop r1
+        pop r2
+        exit
+    ";
+    assert_eq!(&include_bytes!("push_pop.bin")[..], &assemble(source).unwrap().image().unwrap()[..]);
+}
+
+#[test]
+fn call_ret() {
+    let source = "
+        loadimm r2 <- #4096
+        call myfunc
+        exit
+    myfunc:
+        loadimm r10 <- #42
+        ret
+    ";
+    assert_eq!(&include_bytes!("function.bin")[..], &assemble(source).unwrap().image().unwrap()[..]);
+}
+
+// Same program as rfact.dis
+const RFACT: &str = "
+        loadimm r2 <- #4096
+        call rfact
+        exit
+    mult:
+        sub r13 <- r1 - r11
+        move r14 <- r12 if r0 != 0
+    mult_loop:
+        loadimm r8 <- #1
+        sub r8 <- r14 - r8
+        if r8 != 0 {
+            sub r11 <- r11 - r13
+            loadimm r3 <- #1
+            sub r14 <- r14 - r3
+            loadimm r0 <- #mult_loop
+        }
+        ret
+    rfact:
+        loadimm r8 <- #1
+        sub r8 <- r10 - r8
+        if r8 != 0 {
+            push r10
+            loadimm r3 <- #1
+            sub r10 <- r10 - r3
+            call rfact
+            pop r12
+            call mult
+        } else {
+            loadimm r11 <- #1
+        }
+        ret
+";
+
+#[test]
+fn recursive_factorial() {
+    let object = assemble(RFACT).unwrap();
+    // The listing holds the targets of its conditional branches in r9
+    // instead of r3
+    let mut expected = include_bytes!("rfact.bin").to_vec();
+    for addr in [41, 46, 96, 101] {
+        assert_eq!(9, expected[addr]);
+        expected[addr] = 3;
+    }
+    assert_eq!(expected, object.image().unwrap());
+    for name in [".ite_then_1", ".ite_end_1", ".ite_then_2", ".ite_end_2", ".return_from_rfact_1", ".return_from_rfact_2"] {
+        assert!(object.symbol(name).is_some(), "{name}");
+    }
+    let (machine, _) = run(RFACT, 5);
+    assert_eq!(120, machine.regs()[11]);
+}
+
+#[test]
+fn expansions_use_original_opcodes() {
+    let source = "
+        loadimm r2 <- #4096
+        loadimm r1 <- #3
+        loadimm r4 <- #1
+        while r1 != 0 {
+            if r5 == 0 {
+                out_number r1
+            } else {
+                call never
+            }
+            sub r1 <- r1 - r4
+        }
+        loadimm r6 <- #2
+        while r6 == 0 {
+            exit
+        }
+        exit
+    never:
+        ret
+    ";
+    let (_, out) = run(source, 0);
+    assert_eq!(&b"321"[..], &out[..]);
+
+    let code = assemble(source).unwrap().image().unwrap();
+    let mut addr = 0;
+    while addr < code.len() {
+        let instr = Instruction::decode(&code, addr).unwrap();
+        assert!(code[addr] <= 8, "{instr}");
+        addr += instr.size();
+    }
+}
+
+#[test]
+fn nested_loops() {
+    // Print a triangle of stars
+    let (_, out) = run(
+        "
+        loadimm r1 <- #3
+        loadimm r4 <- #1
+        loadimm r5 <- #42
+        loadimm r6 <- #10
+        while r1 != 0 {
+            sub r7 <- r1 - r0
+            move r7 <- r1 if r1 != 0
+            while r7 != 0 {
+                out r5
+                sub r7 <- r7 - r4
+            }
+            out r6
+            sub r1 <- r1 - r4
+        }
+        exit
+    ",
+        0,
+    );
+    assert_eq!(&b"***\n**\n*\n"[..], &out[..]);
+}
+
+#[test]
+fn macro_errors() {
+    let error = |source: &str| {
+        let error = assemble(source).unwrap_err();
+        (error.line, error.message)
+    };
+    assert_eq!(2, error("exit\n}\n").0);
+    assert_eq!(1, error("if r1 != 0 {\nexit\n").0);
+    assert_eq!(1, error("} else {\n").0);
+    assert_eq!(5, error("exit\nexit\nwhile r1 != 0 {\n}\n}\n").0);
+    assert!(error("push r3").1.contains("scratch"));
+    assert!(error("if r3 == 0 {\n}").1.contains("scratch"));
+    assert_eq!(2, error("call f\npush\n").0);
+    // Errors in the expansion point at the macro
+    assert_eq!((2, "undefined label `f`".to_string()), error("exit\ncall f\n"));
+    // The labels of the macros cannot be defined nor used by the source
+    assert_eq!((1, "label `.ite_end_1` is reserved for the macros".to_string()), error(".ite_end_1:\n"));
+    assert_eq!((2, "label `.ite_end_1` is reserved for the macros".to_string()), error("exit\njmp .ite_end_1\n"));
+    assert!(error("call .f\n").1.contains("reserved"));
+}
+
+#[test]
+fn generated_labels_do_not_clash() {
+    // `pop r3` is allowed, the popped value being loaded last
+    let (machine, out) = run(
+        "
+        loadimm r2 <- #4096
+        loadimm r1 <- #7
+        push r1
+        pop r3
+        move r4 <- r3 if r3 != 0
+        if r4 != 0 {
+            out_number r4
+        }
+        exit
+    ite_end_1:
+        exit
+    ",
+        0,
+    );
+    assert_eq!(&b"7"[..], &out[..]);
+    assert_eq!(7, machine.regs()[4]);
+}
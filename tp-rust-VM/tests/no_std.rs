@@ -0,0 +1,82 @@
+// Only built without the `std` feature, on raw images as the assembler is
+// not available then
+#![cfg(not(feature = "std"))]
+
+use interpreter::{Input, IoError, Machine, MachineError, Output};
+
+/// Output into a fixed buffer, like a display without a heap.
+struct Screen {
+    buf: [u8; 32],
+    len: usize,
+}
+
+impl Output for Screen {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        let end = self.len + bytes.len();
+        self.buf.get_mut(self.len..end).ok_or(IoError)?.copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Input counting down from a value.
+struct Countdown(u8);
+
+impl Input for Countdown {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        match self.0 {
+            0 => Ok(None),
+            n => {
+                self.0 -= 1;
+                Ok(Some(n))
+            }
+        }
+    }
+}
+
+fn screen() -> Screen {
+    Screen { buf: [0; 32], len: 0 }
+}
+
+#[test]
+fn custom_output() {
+    //  0: loadimm32 r1 <- #-2147483648
+    //  6: out_number r1
+    //  8: loadimm r1 <- #10
+    // 12: out r1
+    // 14: loadimm r1 <- #233
+    // 18: out r1
+    // 20: out_number r1
+    // 22: exit
+    let code = [9, 1, 0, 0, 0, 128, 8, 1, 4, 1, 10, 0, 6, 1, 4, 1, 233, 0, 6, 1, 8, 1, 7];
+    let mut screen = screen();
+    Machine::new(&code).run_on(&mut screen).unwrap();
+    assert_eq!("-2147483648\né233", std::str::from_utf8(&screen.buf[..screen.len]).unwrap());
+}
+
+#[test]
+fn custom_input() {
+    //  0: in r1
+    //  2: bneg r1, #5
+    //  6: out_number r1
+    //  8: jmp #-11
+    // 11: exit
+    let code = [28, 1, 20, 1, 5, 0, 8, 1, 17, 245, 255, 7];
+    let mut screen = screen();
+    Machine::new(&code).run_with(&mut Countdown(3), &mut screen).unwrap();
+    assert_eq!(b"321", &screen.buf[..screen.len]);
+}
+
+#[test]
+fn output_errors() {
+    //  0: loadimm r1 <- #12345
+    //  4: out_number r1
+    //  6: out_number r1
+    //  8: out_number r1
+    // 10: exit
+    let code = [4, 1, 57, 48, 8, 1, 8, 1, 8, 1, 7];
+    let mut screen = Screen { buf: [0; 32], len: 30 };
+    let mut machine = Machine::new(&code);
+    assert_eq!(Err(MachineError::WriteError), machine.run_on(&mut screen));
+    assert_eq!(4, machine.last_ip());
+}
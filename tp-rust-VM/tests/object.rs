@@ -0,0 +1,126 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Machine, ObjectError, ObjectFile, MAX_NAME_LEN, OBJECT_MAGIC};
+
+const LISTINGS: &[(&str, &[u8])] = &[
+    (include_str!("examples/99bottles.dis"), include_bytes!("examples/99bottles.bin")),
+    (include_str!("examples/count.dis"), include_bytes!("examples/count.bin")),
+    (include_str!("examples/factorial.dis"), include_bytes!("examples/factorial.bin")),
+    (include_str!("examples/fibonacci.dis"), include_bytes!("examples/fibonacci.bin")),
+    (include_str!("examples/hello_world.dis"), include_bytes!("examples/hello_world.bin")),
+    (include_str!("afact.dis"), include_bytes!("afact.bin")),
+    (include_str!("fact.dis"), include_bytes!("fact.bin")),
+    (include_str!("fibo.dis"), include_bytes!("fibo.bin")),
+    (include_str!("function.dis"), include_bytes!("function.bin")),
+    (include_str!("multiply.dis"), include_bytes!("multiply.bin")),
+    (include_str!("push_pop.dis"), include_bytes!("push_pop.bin")),
+    (include_str!("rfact.dis"), include_bytes!("rfact.bin")),
+    (include_str!("rfact_tr.dis"), include_bytes!("rfact_tr.bin")),
+];
+
+#[test]
+fn assemble_listings() {
+    // Assembling a listing gives back the original binary
+    for (source, binary) in LISTINGS {
+        let object = assemble(source).unwrap();
+        assert_eq!(&binary[..], &object.image().unwrap()[..]);
+    }
+}
+
+#[test]
+fn symbols_and_lines() {
+    let object = assemble(include_str!("afact.dis")).unwrap();
+    assert_eq!(Some(87), object.symbol("afact"));
+    assert_eq!(None, object.symbol("nothing"));
+
+    let symbols = object.symbol_table();
+    assert_eq!(Some(("mult_loop", 0)), symbols.lookup(32));
+    assert_eq!("mult_loop+4", symbols.describe(36));
+    assert_eq!(vec!["ite_end_1"], symbols.names_at(68).collect::<Vec<_>>());
+
+    // Line 1 is `0000   loadimm r2 <- #4096`, line 3 is at address 8
+    assert_eq!(Some(1), object.line_of(2));
+    assert_eq!(Some(3), object.line_of(8));
+}
+
+#[test]
+fn roundtrip() {
+    let object = assemble(include_str!("examples/hello_world.dis")).unwrap();
+    let bytes = object.to_bytes();
+    assert!(ObjectFile::is_object(&bytes));
+    assert_eq!(object, ObjectFile::from_bytes(&bytes).unwrap());
+
+    assert_eq!(Err(ObjectError::BadMagic), ObjectFile::from_bytes(include_bytes!("fact.bin")));
+    assert_eq!(Err(ObjectError::Truncated), ObjectFile::from_bytes(&bytes[..bytes.len() - 1]));
+    let mut future = OBJECT_MAGIC.to_vec();
+    future.extend([99, 0]);
+    assert_eq!(Err(ObjectError::UnsupportedVersion(99)), ObjectFile::from_bytes(&future));
+}
+
+#[test]
+fn sections_and_entry() {
+    let source = "
+        .data
+    value:
+        .word 1234
+        .text
+    start:
+        loadimm r1 <- #value
+        load r1 <- [r1]     ; 1234
+        out_number r1
+        exit
+        .entry start
+    ";
+    let object = assemble(source).unwrap();
+    assert_eq!(2, object.sections.len());
+    assert_eq!(10, object.sections[1].addr);
+    assert_eq!(0, object.entry);
+
+    let mut machine = Machine::load_object(&object.to_bytes()).unwrap();
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"1234"[..], &out[..]);
+}
+
+#[test]
+fn load_object_at_entry() {
+    let source = "
+        .entry main
+    message:
+        b'Hi\\n'
+    main:
+        loadimm r1 <- #message
+        load r1 <- [r1]
+        out r1
+        exit
+    ";
+    let object = assemble(source).unwrap();
+    let mut machine = Machine::load_object(&object.to_bytes()).unwrap();
+    assert_eq!(3, machine.regs()[0]);
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"H"[..], &out[..]);
+}
+
+#[test]
+fn assembly_errors() {
+    let err = assemble("exit\nloadimm r1 <- #nowhere\n").unwrap_err();
+    assert_eq!(2, err.line);
+    assert!(assemble("loadimm r1 <- #5000000000").is_err());
+    assert!(assemble("sub r16 <- r1 - r2").is_err());
+    assert!(assemble("here:\nhere:").is_err());
+    assert!(assemble("jump somewhere").is_err());
+}
+
+#[test]
+fn long_names() {
+    // Names are stored with their length on one byte
+    let name = "l".repeat(MAX_NAME_LEN);
+    let object = assemble(&format!("{name}:\nexit\n")).unwrap();
+    assert_eq!(object, ObjectFile::from_bytes(&object.to_bytes()).unwrap());
+
+    let error = assemble(&format!("{name}x:\nexit\n")).unwrap_err();
+    assert_eq!(1, error.line);
+    assert!(error.message.contains("longer than 255 bytes"), "{error}");
+    assert!(assemble(&format!(".extern {name}x\n")).is_err());
+}
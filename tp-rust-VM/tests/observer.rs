@@ -0,0 +1,113 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Instruction, Machine, MachineObserver};
+use std::collections::BTreeMap;
+
+/// Record every event as a line of text.
+#[derive(Default)]
+struct Trace(Vec<String>);
+
+impl MachineObserver for Trace {
+    fn on_fetch(&mut self, ip: u32, instr: &Instruction) {
+        self.0.push(format!("{ip:04} {instr}"));
+    }
+
+    fn on_reg_write(&mut self, reg: usize, old: u32, new: u32) {
+        self.0.push(format!("  r{reg}: {old} -> {new}"));
+    }
+
+    fn on_mem_read(&mut self, addr: u32, data: &[u8]) {
+        self.0.push(format!("  [{addr}] -> {data:?}"));
+    }
+
+    fn on_mem_write(&mut self, addr: u32, data: &[u8]) {
+        self.0.push(format!("  [{addr}] <- {data:?}"));
+    }
+
+    fn on_output(&mut self, bytes: &[u8]) {
+        self.0.push(format!("  out {:?}", String::from_utf8_lossy(bytes)));
+    }
+}
+
+/// Count the instructions run at every address.
+#[derive(Default)]
+struct Profile(BTreeMap<u32, u64>);
+
+impl MachineObserver for Profile {
+    fn on_fetch(&mut self, ip: u32, _instr: &Instruction) {
+        *self.0.entry(ip).or_default() += 1;
+    }
+}
+
+#[test]
+fn trace() {
+    let object = assemble(
+        "
+        loadimm r1 <- #300
+        store16 [r1] <- r1
+        load8 r2 <- [r1]
+        out r2
+        bnz r2, end
+        out_number r1
+    end:
+        exit
+    ",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    machine.attach(Trace::default());
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(",", String::from_utf8(out).unwrap());
+    assert_eq!(
+        [
+            "0000 loadimm r1 <- #300",
+            "  r1: 0 -> 300",
+            "0004 store16 [r1] <- r1",
+            "  [300] <- [44, 1]",
+            "0007 load8 r2 <- [r1]",
+            "  r2: 0 -> 44",
+            "  [300] -> [44]",
+            "0010 out r2",
+            "  out \",\"",
+            "0012 bnz r2, #2",
+            "  r0: 16 -> 18",
+            "0018 exit",
+        ],
+        &machine.observer::<Trace>().unwrap().0[..]
+    );
+}
+
+#[test]
+fn observers_are_independent() {
+    let mut machine = Machine::new(include_bytes!("examples/factorial.bin"));
+    machine.attach(Profile::default());
+    machine.attach(Trace::default());
+    let mut steps = 0;
+    while !machine.step_on(&mut vec![]).unwrap() {
+        steps += 1;
+    }
+    let profile = machine.observer::<Profile>().unwrap();
+    assert_eq!(steps + 1, profile.0.values().sum::<u64>());
+    assert_eq!(Some(&1), profile.0.get(&0));
+    let trace = machine.observer::<Trace>().unwrap();
+    assert_eq!(steps + 1, trace.0.iter().filter(|line| !line.starts_with(' ')).count() as u64);
+
+    // Observers can be reset between runs
+    machine.observer_mut::<Profile>().unwrap().0.clear();
+    machine.set_reg(0, 0).unwrap();
+    machine.step_on(&mut vec![]).unwrap();
+    assert_eq!(1, machine.observer::<Profile>().unwrap().0.len());
+}
+
+#[test]
+fn faulting_instructions() {
+    let object = assemble("loadimm r1 <- #4094\nload r2 <- [r1]\n").unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    machine.attach(Trace::default());
+    assert!(machine.run_on(&mut vec![]).is_err());
+    // The fetch is reported, but not the failed read
+    let trace = &machine.observer::<Trace>().unwrap().0;
+    assert_eq!(["0000 loadimm r1 <- #4094", "  r1: 0 -> 4094", "0004 load r2 <- [r1]"], &trace[..]);
+    assert!(machine.observer::<Profile>().is_none());
+}
@@ -0,0 +1,223 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, bf2vm, compile, optimize, Instruction, Isa, Machine, ObjectFile};
+
+/// Output, registers besides the IP and number of steps of a run, starting
+/// with the given register values.
+fn run(object: &ObjectFile, regs: &[(usize, u32)], input: &[u8]) -> (Vec<u8>, Vec<u32>, usize) {
+    let mut machine = Machine::load_object(&object.to_bytes()).unwrap();
+    for &(reg, value) in regs {
+        machine.set_reg(reg, value).unwrap();
+    }
+    let mut out = vec![];
+    let mut input = input;
+    let mut steps = 1;
+    while !machine.step_with(&mut input, &mut out).unwrap() {
+        steps += 1;
+    }
+    (out, machine.regs()[1..].to_vec(), steps)
+}
+
+/// Check that the registers end with the same values, where a register
+/// holding the address of a label must hold its new address.
+fn assert_same_regs(object: &ObjectFile, regs: &[u32], optimized: &ObjectFile, opt_regs: &[u32]) {
+    let end = object.sections[0].data.len() as u32;
+    for (reg, (&value, &opt_value)) in regs.iter().zip(opt_regs).enumerate() {
+        let label = object.symbols.iter().find(|s| s.section == 0 && s.addr == value && value < end);
+        let expected = label.map_or(value, |s| optimized.symbol(&s.name).unwrap());
+        assert_eq!(expected, opt_value, "r{}", reg + 1);
+    }
+}
+
+/// Run the original and optimized programs side by side, check that they
+/// behave the same, and return the code sizes and step counts.
+fn compare(object: &ObjectFile, regs: &[(usize, u32)], input: &[u8]) -> [(usize, usize); 2] {
+    let optimized = optimize(object);
+    let (out, final_regs, steps) = run(object, regs, input);
+    let (opt_out, opt_regs, opt_steps) = run(&optimized, regs, input);
+    assert_eq!(String::from_utf8_lossy(&out), String::from_utf8_lossy(&opt_out));
+    assert_same_regs(object, &final_regs, &optimized, &opt_regs);
+    assert!(opt_steps <= steps);
+    let size = |o: &ObjectFile| o.sections[0].data.len();
+    assert!(size(&optimized) <= size(object));
+    [(size(object), steps), (size(&optimized), opt_steps)]
+}
+
+#[test]
+fn listings_are_equivalent() {
+    // push_pop.dis is left out as it keeps the value of the IP in r1, and
+    // fibonacci.dis as it takes too long to run
+    let listings = [
+        include_str!("afact.dis"),
+        include_str!("fact.dis"),
+        include_str!("fibo.dis"),
+        include_str!("function.dis"),
+        include_str!("multiply.dis"),
+        include_str!("rfact.dis"),
+        include_str!("rfact_tr.dis"),
+        include_str!("examples/99bottles.dis"),
+        include_str!("examples/count.dis"),
+        include_str!("examples/factorial.dis"),
+        include_str!("examples/hello_world.dis"),
+    ];
+    for listing in listings {
+        let object = assemble(listing).unwrap();
+        // multiply.dis takes its arguments in r11 and r12, the others in r10
+        for n in [1, 2, 5] {
+            compare(&object, &[(10, n), (11, 7), (12, n)], b"");
+        }
+    }
+}
+
+#[test]
+fn compiled_programs_are_equivalent() {
+    let source = r#"
+    fn fact(n) {
+        if (n == 0) { return 1; }
+        return n * fact(n - 1);
+    }
+    fn main() {
+        var i = 0;
+        while (i != 8) {
+            print(i, "! = ", fact(i), "\n");
+            i = i + 1;
+        }
+    }
+    "#;
+    for isa in [Isa::Base, Isa::Extended] {
+        let [(size, steps), (opt_size, opt_steps)] = compare(&assemble(&compile(source, isa).unwrap()).unwrap(), &[], b"");
+        assert!(opt_size < size);
+        assert!(opt_steps < steps);
+    }
+}
+
+#[test]
+fn brainfuck_is_equivalent() {
+    let object = bf2vm(">,[>,]<[.<]++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.").unwrap();
+    let optimized = optimize(&object);
+    // The tape follows the code, so only the output is compared
+    let (out, _, steps) = run(&object, &[], b"stressed");
+    let (opt_out, _, opt_steps) = run(&optimized, &[], b"stressed");
+    assert_eq!(String::from_utf8_lossy(&out), String::from_utf8_lossy(&opt_out));
+    assert!(optimized.sections[0].data.len() < object.sections[0].data.len());
+    assert!(opt_steps < steps);
+}
+
+#[test]
+fn push_pop_and_stack_adjustments() {
+    let object = assemble(
+        "
+        loadimm r2 <- #4096
+        loadimm r1 <- #7
+        push r1
+        pop r4
+        push r4
+        pop r4
+        loadimm r3 <- #4
+        sub r2 <- r2 - r3
+        loadimm r3 <- #8
+        sub r2 <- r2 - r3
+        loadimm r3 <- #-12
+        sub r2 <- r2 - r3
+        loadimm r3 <- #0
+        exit
+    ",
+    )
+    .unwrap();
+    let [(size, _), (opt_size, _)] = compare(&object, &[], b"");
+    // loadimm r2, loadimm r1, move r4 <- r1, loadimm r3, exit
+    assert_eq!(4 + 4 + 4 + 4 + 1, opt_size);
+    assert!(size > 60);
+}
+
+#[test]
+fn folding_keeps_the_instruction_set() {
+    let source = "
+        loadimm r1 <- #20000
+        sub r2 <- r2 - r1
+        loadimm r1 <- #20000
+        sub r2 <- r2 - r1
+        loadimm r1 <- #0
+        exit
+    ";
+    // The sum needs `loadimm32`, which the original program does not use
+    let object = assemble(source).unwrap();
+    let code = optimize(&object).image().unwrap();
+    let mut addr = 0;
+    while addr < code.len() {
+        assert!(code[addr] <= 8, "{}", Instruction::decode(&code, addr).unwrap());
+        addr += Instruction::decode(&code, addr).unwrap().size();
+    }
+    compare(&object, &[], b"");
+
+    let object = assemble(&format!("loadimm32 r4 <- #100000\n{source}")).unwrap();
+    let [(size, _), (opt_size, _)] = compare(&object, &[], b"");
+    assert_eq!((6 + 4 + 4 + 4 + 4 + 4 + 1, 6 + 6 + 4 + 4 + 1), (size, opt_size));
+}
+
+#[test]
+fn jumps_to_next_instruction() {
+    let object = assemble(
+        "
+        loadimm r0 <- #next
+    next:
+        loadimm r5 <- #after
+        move r0 <- r5 if r1 != 0
+        jmp after
+    after:
+        loadimm r1 <- #1
+        loadimm r1 <- #1
+        exit
+    ",
+    )
+    .unwrap();
+    let optimized = optimize(&object);
+    let code = &optimized.sections[0].data;
+    // The jmp is removed, and the symbols follow the code
+    assert_eq!(optimized.symbol("after"), Some(8));
+    assert_eq!(&[4, 5, 8, 0, 1, 0, 5, 1, 4, 1, 1, 0, 7], &code[..]);
+    compare(&object, &[], b"");
+}
+
+#[test]
+fn relocations_are_kept() {
+    // The optimized object can still be linked against a library which uses
+    // the moved labels
+    let main = assemble(
+        "
+        .global value
+        .extern print_value
+        loadimm r2 <- #4096
+        loadimm r3 <- #4
+        loadimm r3 <- #4
+        sub r2 <- r2 - r3
+        loadimm r3 <- #back
+        store [r2] <- r3
+        loadimm r0 <- #print_value
+    back:
+        exit
+    .data
+    value:
+        .word 42
+    ",
+    )
+    .unwrap();
+    let lib = assemble(
+        "
+        .global print_value
+        .extern value
+    print_value:
+        loadimm r4 <- #value
+        load r4 <- [r4]
+        out_number r4
+        ret
+    ",
+    )
+    .unwrap();
+    let optimized = optimize(&main);
+    assert!(optimized.sections[0].data.len() < main.sections[0].data.len());
+    assert_eq!(optimized.sections[0].data.len() as u32, optimized.sections[1].addr);
+    let program = interpreter::link(&[optimized, lib]).unwrap();
+    let (out, _, _) = run(&program, &[], b"");
+    assert_eq!(&b"42"[..], &out[..]);
+}
@@ -0,0 +1,161 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Access, Instruction, Machine, MachineError, PAGE_COUNT};
+
+/// A page table mapping the pages of `entries`, the others being unmapped.
+fn page_table(entries: &[(usize, u32)]) -> String {
+    (0..PAGE_COUNT)
+        .map(|page| {
+            let entry = entries.iter().find(|&&(p, _)| p == page).map_or(0, |&(_, entry)| entry);
+            format!(".word {entry}\n")
+        })
+        .collect()
+}
+
+/// Machine with traps at `vectors` and paging enabled, running `source`.
+fn run(source: &str) -> (Machine, Result<(), MachineError>, String) {
+    let object = assemble(source).unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    if let Some(vectors) = object.symbol("vectors") {
+        machine.enable_traps(vectors);
+    }
+    machine.enable_paging();
+    let mut out = vec![];
+    let result = machine.run_on(&mut out);
+    (machine, result, String::from_utf8(out).unwrap())
+}
+
+/// Enter `body` in user mode, with page 0 accessible to the user, a user
+/// stack in page 1 and the supervisor stack in page 15. Page faults exit.
+fn user_program(body: &str) -> String {
+    format!(
+        "
+        loadimm r2 <- #4096
+        loadimm r1 <- #pages
+        setptb r1
+        loadimm r1 <- #0x200
+        push r1
+        loadimm r1 <- #user
+        push r1
+        loadimm r1 <- #0x100
+        push r1
+        rti
+    handler:
+        exit
+    vectors:
+        .word 0
+        .word 0
+        .word 0
+        .word 0
+        .word handler
+        .word 0
+    pages:
+        {}
+    user:
+        {body}
+    ",
+        page_table(&[(0, 0x01f), (1, 0x117), (15, 0xf07)])
+    )
+}
+
+#[test]
+fn supervisor_example() {
+    let (machine, result, out) = run(include_str!("examples/supervisor.dis"));
+    assert_eq!(Ok(()), result);
+    assert_eq!("321\nBB!16\n", out);
+    assert!(!machine.user_mode());
+    // Both programs pushed their `B` at the same virtual address, but in
+    // their own frames
+    assert_eq!(0, machine.memory()[0x9fc]);
+    assert_eq!(66, machine.memory()[0xbfc]);
+}
+
+#[test]
+fn translation() {
+    let (machine, result, _) = run(&format!(
+        "
+        loadimm r1 <- #pages
+        setptb r1
+        exit
+    pages:
+        {}
+    ",
+        page_table(&[(0, 0x00f), (1, 0x313), (2, 0x20d)])
+    ));
+    assert_eq!(Ok(()), result);
+    assert_eq!(Ok(0x320), machine.translate(0x120, Access::Read));
+    assert_eq!(Err(MachineError::PageFault { addr: 0x120 }), machine.translate(0x120, Access::Write));
+    assert_eq!(Err(MachineError::PageFault { addr: 0x1ff }), machine.translate(0x1ff, Access::Execute));
+    assert_eq!(Ok(0x2ff), machine.translate(0x2ff, Access::Execute));
+    assert_eq!(Err(MachineError::PageFault { addr: 0x2ff }), machine.translate(0x2ff, Access::Read));
+    assert_eq!(Err(MachineError::PageFault { addr: 0x300 }), machine.translate(0x300, Access::Read));
+    assert_eq!(Err(MachineError::InvalidMemAddr), machine.translate(5000, Access::Read));
+    // The supervisor reads the page 1 without its user bit
+    assert_eq!(Ok(0x10), machine.translate(0x10, Access::Read));
+}
+
+#[test]
+fn page_fault_from_user_mode() {
+    let (machine, result, _) = run(&user_program(
+        "
+        loadimm r5 <- #4000
+    fault:
+        store [r5] <- r5
+        exit
+    ",
+    ));
+    assert_eq!(Ok(()), result);
+    assert!(!machine.user_mode());
+
+    // The trap switched to the supervisor stack, below which the cause word
+    // says it came from user mode and holds the address, followed by the
+    // faulting IP and the user r2
+    let fault = assemble(&user_program("loadimm r5 <- #4000\nfault:\nstore [r5] <- r5\n")).unwrap().symbol("fault").unwrap();
+    assert_eq!(4084, machine.regs()[2]);
+    let words: Vec<u32> =
+        machine.memory()[4084..].chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect();
+    assert_eq!([4 | 0x100 | 4000 << 16, fault, 0x200], &words[..]);
+    assert_eq!(&[0; 4], &machine.memory()[4000..4004]);
+}
+
+#[test]
+fn privileged_instructions() {
+    // Not handled, `exit` from user mode stops the machine in user mode
+    let (machine, result, out) = run(&user_program("loadimm r1 <- #42\nout_number r1\nexit\n"));
+    assert_eq!(Err(MachineError::PrivilegedInstruction), result);
+    assert_eq!("42", out);
+    assert!(machine.user_mode());
+    assert_eq!(0x200, machine.regs()[2]);
+
+    let (_, result, _) = run(&user_program("loadimm r1 <- #0\nsetptb r1\n"));
+    assert_eq!(Err(MachineError::PrivilegedInstruction), result);
+
+    // Without paging, there is no page table to set
+    let code = assemble("loadimm r1 <- #0\nsetptb r1\nexit\n").unwrap().image().unwrap();
+    assert_eq!(Instruction::SetPageTable { src: 1 }, Instruction::decode(&code, 4).unwrap());
+    assert_eq!("setptb r1", Instruction::decode(&code, 4).unwrap().to_string());
+    assert_eq!(&[41, 1], &code[4..6]);
+    let mut machine = Machine::new(&code);
+    assert_eq!(Err(MachineError::InvalidOpcode), machine.run_on(&mut vec![]));
+}
+
+#[test]
+fn store_across_pages() {
+    // The second page is read-only, so nothing is written by the store
+    // crossing into it
+    let (machine, result, _) = run(&format!(
+        "
+        loadimm r1 <- #pages
+        setptb r1
+        loadimm r1 <- #254
+        loadimm32 r2 <- #0x11223344
+        store [r1] <- r2
+        exit
+    pages:
+        {}
+    ",
+        page_table(&[(0, 0x00f), (1, 0x10b)])
+    ));
+    assert_eq!(Err(MachineError::PageFault { addr: 256 }), result);
+    assert_eq!(&[0, 0], &machine.memory()[254..256]);
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Interval, Machine, MachineError, RangeAnalysis, RangeWarning, NREGS};
+
+fn analyze(source: &str) -> RangeAnalysis {
+    let object = assemble(source).unwrap();
+    RangeAnalysis::run(&object.image().unwrap(), object.entry as usize)
+}
+
+#[test]
+fn programs_with_a_bounded_stack() {
+    for (name, code) in [
+        ("afact", &include_bytes!("afact.bin")[..]),
+        ("fact", include_bytes!("fact.bin")),
+        ("fibo", include_bytes!("fibo.bin")),
+        ("function", include_bytes!("function.bin")),
+        ("push_pop", include_bytes!("push_pop.bin")),
+    ] {
+        assert_eq!(0, RangeAnalysis::run(code, 0).warnings().len(), "{name}");
+    }
+}
+
+#[test]
+fn unbounded_recursion_and_strings() {
+    // The depth of the recursion is unknown
+    let analysis = RangeAnalysis::run(include_bytes!("rfact.bin"), 0);
+    assert!(analysis.warnings().iter().all(|w| matches!(w, RangeWarning::MemoryAccess { .. })));
+    assert!(!analysis.warnings().is_empty());
+    // The end of the string is only known from its contents
+    let analysis = RangeAnalysis::run(include_bytes!("examples/hello_world.bin"), 0);
+    assert_eq!(
+        ["0104: `load r3 <- [r10]` may access outside of the memory, with an address in any value"],
+        &analysis.warnings().iter().map(ToString::to_string).collect::<Vec<_>>()[..]
+    );
+}
+
+#[test]
+fn loop_counters_and_branches() {
+    let analysis = analyze(
+        "
+        loadimm r1 <- #10
+        loadimm r4 <- #1
+        loadimm r5 <- #4092
+    loop:
+        sub r6 <- r5 - r1
+        store [r6] <- r1        ; 4082 to 4091
+        sub r1 <- r1 - r4
+        bnz r1, loop
+        load r7 <- [r5]
+    end:
+        exit
+    ",
+    );
+    assert!(analysis.warnings().is_empty());
+    let at_store = analysis.before(16).unwrap();
+    assert_eq!((Interval::new(1, 10), Interval::new(4082, 4091)), (at_store[1], at_store[6]));
+    assert_eq!(Interval::constant(19), at_store[0]);
+    let at_end = analysis.before(27).unwrap();
+    assert_eq!((Interval::constant(0), Interval::constant(4092)), (at_end[1], at_end[5]));
+    assert_eq!(None, analysis.before(31));
+}
+
+#[test]
+fn memory_faults() {
+    let analysis = analyze(
+        "
+        loadimm r1 <- #4094
+        store16 [r1] <- r1
+        store [r1] <- r1
+        load8 r2 <- [r1]
+        loadimm r3 <- #5
+        sub r3 <- r1 - r3       ; 4089
+        move r1 <- r3 if r2 != 0
+        load r2 <- [r1]         ; 4089 or 4094
+        exit
+    ",
+    );
+    assert_eq!(
+        [
+            "0007: `store [r1] <- r1` always accesses outside of the memory, with an address in 4094",
+            "0025: `load r2 <- [r1]` may access outside of the memory, with an address in [4089, 4094]",
+        ],
+        &analysis.warnings().iter().map(ToString::to_string).collect::<Vec<_>>()[..]
+    );
+}
+
+#[test]
+fn unknown_jumps() {
+    let analysis = analyze(
+        "
+        in r1
+        load r5 <- [r1]
+        move r0 <- r5 if r1 != 0
+        loadimm r6 <- #next
+        move r0 <- r6 if r1 == 0    ; always taken
+        exit
+    next:
+        .word 0xffffffff
+    ",
+    );
+    let warnings = analysis.warnings();
+    assert_eq!(3, warnings.len());
+    assert!(matches!(warnings[0], RangeWarning::MemoryAccess { addr: 2, range: Interval::FULL, .. }));
+    assert!(matches!(warnings[1], RangeWarning::UnknownJump { addr: 5, range: Interval::FULL, .. }));
+    assert_eq!(RangeWarning::InvalidInstruction { addr: 18 }, warnings[2]);
+    assert_eq!(Interval::constant(0), analysis.before(9).unwrap()[1]);
+    assert_eq!(None, analysis.before(17));
+}
+
+#[test]
+fn faults_are_predicted() {
+    // The stores fault at the end of the memory, depending on r10
+    let object = assemble(include_str!("vm2rust/ops.dis")).unwrap();
+    let image = object.image().unwrap();
+    let mut regs = [Interval::constant(0); NREGS];
+    regs[0] = Interval::constant(object.entry);
+    regs[10] = Interval::FULL;
+    let analysis = RangeAnalysis::run_with(&image, object.entry as usize, regs);
+    assert!(analysis.warnings().iter().all(|w| matches!(w, RangeWarning::MemoryAccess { .. })));
+
+    let mut machine = Machine::new(&image);
+    machine.set_reg(10, 4095).unwrap();
+    let result = machine.run_with(&mut &b""[..], &mut vec![]);
+    assert!(matches!(result, Err(MachineError::InvalidMemAddr)));
+    let ip = machine.regs()[0];
+    assert!(analysis.warnings().iter().any(|w| analysis.before(w.addr()).unwrap()[0] == Interval::constant(ip)));
+}
@@ -0,0 +1,51 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Machine, MachineError, SliceOutcome};
+
+#[test]
+fn slice_boundaries() {
+    let object = assemble("loadimm r1 <- #1\nloadimm r2 <- #2\nexit\n").unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    let (mut input, mut output) = (&b""[..], vec![]);
+    assert_eq!(Ok(SliceOutcome::Yielded), machine.run_slice(0, &mut input, &mut output));
+    assert_eq!(0, machine.regs()[0]);
+    assert_eq!(Ok(SliceOutcome::Yielded), machine.run_slice(2, &mut input, &mut output));
+    assert_eq!([8, 1, 2], machine.regs()[..3]);
+    // The slice ends early on exit
+    assert_eq!(Ok(SliceOutcome::Exited), machine.run_slice(10, &mut input, &mut output));
+    assert_eq!(9, machine.regs()[0]);
+}
+
+#[test]
+fn faults() {
+    let object = assemble("loadimm r1 <- #4096\nstore [r1] <- r1\nexit\n").unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    let result = machine.run_slice(5, &mut &b""[..], &mut vec![]);
+    assert_eq!(Err(MachineError::InvalidMemAddr), result);
+    assert_eq!(4, machine.last_ip());
+}
+
+#[test]
+fn round_robin() {
+    let programs: [&[u8]; 3] = [
+        include_bytes!("examples/hello_world.bin"),
+        include_bytes!("examples/factorial.bin"),
+        include_bytes!("examples/99bottles.bin"),
+    ];
+    let mut machines: Vec<(Machine, Vec<u8>, bool)> =
+        programs.iter().map(|code| (Machine::new(code), vec![], false)).collect();
+    let mut rounds = 0;
+    while machines.iter().any(|(_, _, exited)| !exited) {
+        for (machine, output, exited) in machines.iter_mut().filter(|(_, _, exited)| !exited) {
+            *exited = machine.run_slice(7, &mut &b""[..], output).unwrap() == SliceOutcome::Exited;
+        }
+        rounds += 1;
+    }
+    assert!(rounds > 100);
+
+    for (code, (_, output, _)) in programs.iter().zip(machines) {
+        let mut expected = vec![];
+        Machine::new(code).run_on(&mut expected).unwrap();
+        assert_eq!(expected, output);
+    }
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Machine, UninitializedRead};
+
+fn sanitized_run(code: &[u8], r10: u32) -> Machine {
+    let mut machine = Machine::new(code);
+    machine.enable_sanitizer();
+    machine.set_reg(10, r10).unwrap();
+    machine.run_on(&mut vec![]).unwrap();
+    machine
+}
+
+#[test]
+fn read_below_stack_pointer() {
+    let object = assemble(
+        "
+        loadimm r2 <- #4096
+        loadimm r3 <- #4
+        sub r2 <- r2 - r3
+        store [r2] <- r3        ; 4092 is now initialized
+        sub r3 <- r2 - r3       ; 4088 is not
+        load r1 <- [r2]
+        load r1 <- [r3]
+        exit
+    ",
+    )
+    .unwrap();
+    let machine = sanitized_run(&object.image().unwrap(), 0);
+    assert_eq!(&[UninitializedRead { ip: 22, addr: 4088 }], machine.sanitizer_reports());
+}
+
+#[test]
+fn enabled_twice() {
+    // 0: load r1 <- [r1]
+    // 3: exit
+    let mut machine = Machine::new(&[3, 1, 1, 7]);
+    machine.enable_sanitizer();
+    machine.enable_sanitizer();
+    machine.set_reg(1, 100).unwrap();
+    machine.run_on(&mut vec![]).unwrap();
+    assert_eq!(&[UninitializedRead { ip: 0, addr: 100 }], machine.sanitizer_reports());
+}
+
+#[test]
+fn partially_initialized_word() {
+    // 0: load r1 <- [r1]
+    // 3: exit
+    // The word at 2 overlaps the end of the image
+    let mut machine = Machine::new(&[3, 1, 1, 7]);
+    machine.enable_sanitizer();
+    machine.set_reg(1, 2).unwrap();
+    machine.run_on(&mut vec![]).unwrap();
+    assert_eq!(&[UninitializedRead { ip: 0, addr: 4 }], machine.sanitizer_reports());
+}
+
+#[test]
+fn disabled_by_default() {
+    // 0: load r1 <- [r1] with r1 == 100
+    // 3: exit
+    let mut machine = Machine::new(&[3, 1, 1, 7]);
+    machine.set_reg(1, 100).unwrap();
+    machine.run_on(&mut vec![]).unwrap();
+    assert!(machine.sanitizer_reports().is_empty());
+}
+
+#[test]
+fn correct_programs_are_clean() {
+    for code in [&include_bytes!("fact.bin")[..], include_bytes!("afact.bin"), include_bytes!("rfact.bin")] {
+        for i in 1..8 {
+            assert!(sanitized_run(code, i).sanitizer_reports().is_empty());
+        }
+    }
+}
+
+#[test]
+fn word_load_past_the_image() {
+    // print loads a whole word for every character, and reads past the end
+    // of the image for the last characters of the last string
+    let machine = sanitized_run(include_bytes!("examples/99bottles.bin"), 0);
+    assert!(!machine.sanitizer_reports().is_empty());
+    assert!(machine.sanitizer_reports().iter().all(|r| *r == UninitializedRead { ip: 1177, addr: 1444 }));
+}
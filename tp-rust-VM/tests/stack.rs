@@ -0,0 +1,133 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Machine, StackAnalysis, StackIssue};
+use std::collections::BTreeMap;
+
+fn analyze(source: &str, recursion: &[(&str, u32)]) -> StackAnalysis {
+    let object = assemble(source).unwrap();
+    let symbols = object.symbol_table();
+    let recursion = recursion.iter().map(|&(name, depth)| (symbols.address_of(name).unwrap() as usize, depth));
+    StackAnalysis::run(&object.image().unwrap(), object.entry as usize, &recursion.collect())
+}
+
+#[test]
+fn listings() {
+    for (name, code, lowest) in [
+        ("99bottles", &include_bytes!("examples/99bottles.bin")[..], 4080),
+        ("factorial", include_bytes!("examples/factorial.bin"), 4084),
+        ("afact", include_bytes!("afact.bin"), 4088),
+        ("fact", include_bytes!("fact.bin"), 4088),
+        ("function", include_bytes!("function.bin"), 4092),
+        ("multiply", include_bytes!("multiply.bin"), 4092),
+    ] {
+        let analysis = StackAnalysis::run(code, 0, &BTreeMap::new());
+        assert_eq!((Some(lowest), true), (analysis.lowest(), analysis.fits()), "{name}");
+    }
+    // print saves r10 and r11 and calls out_char
+    let analysis = StackAnalysis::run(include_bytes!("examples/99bottles.bin"), 0, &BTreeMap::new());
+    let print = analysis.function(537).unwrap();
+    assert_eq!((12, Some(12)), (print.frame, print.total));
+}
+
+#[test]
+fn recursion_needs_a_bound() {
+    let code = include_bytes!("rfact.bin");
+    let analysis = StackAnalysis::run(code, 0, &BTreeMap::new());
+    assert_eq!([StackIssue::Recursion { function: 87 }], analysis.issues());
+    assert_eq!((None, false), (analysis.lowest(), analysis.fits()));
+    assert_eq!(Some(0), analysis.function(24).unwrap().total);
+
+    // Every activation saves r10 and pushes a return address
+    let analysis = StackAnalysis::run(code, 0, &BTreeMap::from([(87, 10)]));
+    assert_eq!((8, Some(80)), (analysis.function(87).unwrap().frame, analysis.function(87).unwrap().total));
+    assert_eq!(Some(4092 - 80), analysis.lowest());
+
+    // which is not exceeded when computing 10!, the last activation not
+    // pushing anything
+    let mut machine = Machine::new(code);
+    machine.set_reg(10, 10).unwrap();
+    let mut lowest = u32::MAX;
+    while !machine.step_with(&mut &b""[..], &mut vec![]).unwrap() {
+        lowest = lowest.min(machine.regs()[2]);
+    }
+    assert_eq!(4092 - 72, lowest);
+}
+
+#[test]
+fn mutual_recursion() {
+    let source = "
+        loadimm r2 <- #4096
+        call even
+        exit
+    even:
+        push r10
+        call odd
+        pop r10
+        ret
+    odd:
+        call even
+        ret
+    ";
+    let analysis = analyze(source, &[("even", 3)]);
+    assert_eq!(vec![StackIssue::Recursion { function: 92 }], analysis.issues());
+    // 5 activations using at most 8 bytes before a recursive call
+    let analysis = analyze(source, &[("even", 3), ("odd", 2)]);
+    assert!(analysis.issues().is_empty());
+    assert_eq!(Some(4092 - 40), analysis.lowest());
+}
+
+#[test]
+fn unbalanced_stacks() {
+    let analysis = analyze(
+        "
+        loadimm r2 <- #4096
+        call leaks
+        call grows
+        exit
+    leaks:
+        push r10
+        ret
+    grows:
+        push r10
+        bnz r10, grows
+        ret
+    ",
+        &[],
+    );
+    let issues: Vec<String> = analysis.issues().iter().map(ToString::to_string).collect();
+    assert_eq!(
+        [
+            "0070: returns with 4 bytes left on the stack",
+            "0073: reached with different stack depths",
+            "0104: returns with 4 bytes left on the stack",
+        ],
+        &issues[..]
+    );
+    assert!(!analysis.fits());
+
+    let analysis = StackAnalysis::run(include_bytes!("push_pop.bin"), 0, &BTreeMap::new());
+    assert_eq!([StackIssue::UnknownWrite { addr: 61 }], analysis.issues());
+}
+
+#[test]
+fn stack_overlapping_the_code() {
+    let source = "
+        loadimm r2 <- #top
+        call f
+        exit
+    f:
+        push r10
+        push r11
+        pop r11
+        pop r10
+        ret
+        [0, 0, 0, 0, 0, 0, 0, 0]
+    top:
+        [0, 0, 0, 0]
+    ";
+    let analysis = analyze(source, &[]);
+    assert!(analysis.issues().is_empty());
+    let top = assemble(source).unwrap().symbol_table().address_of("top").unwrap() as i64;
+    assert_eq!(Some(top - 12), analysis.lowest());
+    assert!(!analysis.fits());
+}
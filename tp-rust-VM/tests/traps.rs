@@ -0,0 +1,180 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, Instruction, Machine, MachineError};
+
+/// Run `source` with the fault-vector table at its `table` label.
+fn run(source: &str) -> (Machine, Result<(), MachineError>, String) {
+    let object = assemble(source).unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    machine.enable_traps(object.symbol("table").unwrap());
+    let mut out = vec![];
+    let result = machine.run_on(&mut out);
+    (machine, result, String::from_utf8(out).unwrap())
+}
+
+/// Handler printing the cause, then skipping the faulting instruction whose
+/// size is in r9.
+const SKIP: &str = "
+    skip:
+        load r8 <- [r2]
+        out_number r8
+        loadimm r3 <- #-4
+        sub r6 <- r2 - r3
+        load r7 <- [r6]
+        sub r7 <- r7 - r9
+        store [r6] <- r7
+        rti
+";
+
+#[test]
+fn division() {
+    let object = assemble("loadimm r1 <- #-7\nloadimm r2 <- #2\ndiv r3 <- r1 / r2\ndiv r4 <- r1 / r5\nexit\n").unwrap();
+    let code = object.image().unwrap();
+    assert_eq!(Instruction::Div { dst: 3, lhs: 1, rhs: 2 }, Instruction::decode(&code, 8).unwrap());
+    assert_eq!("div r3 <- r1 / r2", Instruction::decode(&code, 8).unwrap().to_string());
+    let mut machine = Machine::new(&code);
+    assert_eq!(Err(MachineError::DivisionByZero), machine.run_on(&mut vec![]));
+    assert_eq!(-3, machine.regs()[3] as i32);
+    assert_eq!(12, machine.last_ip());
+
+    let object = assemble("loadimm32 r1 <- #-2147483648\nloadimm r2 <- #-1\ndiv r1 <- r1 / r2\nexit\n").unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    machine.run_on(&mut vec![]).unwrap();
+    assert_eq!(i32::MIN, machine.regs()[1] as i32);
+}
+
+#[test]
+fn handled_division_by_zero() {
+    let (machine, result, out) = run(&format!(
+        "
+        loadimm r2 <- #4000
+        loadimm r9 <- #-4
+        loadimm r5 <- #42
+        div r5 <- r5 / r4
+        out r9
+        out_number r5
+        exit
+    {SKIP}
+    table:
+        .word 0
+        .word 0
+        .word 0
+        .word skip
+    "
+    ));
+    assert_eq!(Ok(()), result);
+    assert_eq!("3\u{fc}42", out);
+    // The stack is back to its state before the trap, which left the cause
+    // and the IP updated by the handler below it
+    assert_eq!(4000, machine.regs()[2]);
+    assert_eq!([3, 0, 0, 0, 16, 0, 0, 0], machine.memory()[3992..4000]);
+}
+
+#[test]
+fn resume_faulting_instruction() {
+    // The handler fixes the address, then the load runs again
+    let (machine, result, out) = run("
+        loadimm r2 <- #4096
+        loadimm r1 <- #5000
+        load r5 <- [r1]
+        out_number r5
+        exit
+    fix:
+        loadimm r1 <- #value
+        rti
+    table:
+        .word fix
+    value:
+        .word 1234
+    ");
+    assert_eq!(Ok(()), result);
+    assert_eq!("1234", out);
+    assert_eq!(4096, machine.regs()[2]);
+}
+
+#[test]
+fn cause_codes() {
+    let (_, result, out) = run(&format!(
+        r#"
+        loadimm r2 <- #4096
+        loadimm r9 <- #-2
+        b"\x06\x14"            ; out r20
+        loadimm r3 <- #5000
+        loadimm r9 <- #-3
+        load r4 <- [r3]
+        loadimm r9 <- #-1
+        b"\xff"                ; invalid opcode
+        loadimm r9 <- #-4
+        div r1 <- r1 / r5
+        exit
+    {SKIP}
+    table:
+        .word skip
+        .word skip
+        .word skip
+        .word skip
+    "#
+    ));
+    assert_eq!(Ok(()), result);
+    assert_eq!("1023", out);
+}
+
+#[test]
+fn unhandled_faults() {
+    // A null entry does not handle the fault
+    let (machine, result, _) = run("
+        loadimm r2 <- #4096
+        div r1 <- r1 / r1
+    table:
+        .word 0x1234
+    ");
+    assert_eq!(Err(MachineError::DivisionByZero), result);
+    assert_eq!(4096, machine.regs()[2]);
+
+    // Neither does a full stack, and input errors cannot be trapped
+    let (machine, result, _) = run("
+        loadimm r2 <- #4
+        loadimm r1 <- #5000
+        load r1 <- [r1]
+    table:
+        .word 8
+    ");
+    assert_eq!(Err(MachineError::InvalidMemAddr), result);
+    assert_eq!(8, machine.last_ip());
+    assert_eq!(4, machine.regs()[2]);
+    assert_eq!(None, MachineError::ReadError.trap_cause());
+
+    // Traps are disabled by default
+    let object = assemble("loadimm r2 <- #4096\ndiv r1 <- r1 / r1\nexit\n").unwrap();
+    let mut machine = Machine::new(&object.image().unwrap());
+    assert_eq!(Err(MachineError::DivisionByZero), machine.run_on(&mut vec![]));
+}
+
+#[test]
+fn fault_in_handler() {
+    // The handler faults too, nesting a second trap on the stack, whose
+    // handler exits
+    let (machine, result, out) = run("
+        loadimm r2 <- #4096
+        loadimm r1 <- #0
+        div r1 <- r1 / r1
+        exit
+    first:
+        loadimm r1 <- #5000
+        load r1 <- [r1]
+        exit
+    second:
+        out_number r2
+        exit
+    table:
+        .word second
+        .word 0
+        .word 0
+        .word first
+    ");
+    assert_eq!(Ok(()), result);
+    assert_eq!("4080", out);
+    let words: Vec<u32> =
+        machine.memory()[4080..].chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect();
+    assert_eq!([0, 17, 3, 8], &words[..]);
+}
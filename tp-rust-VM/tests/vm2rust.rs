@@ -0,0 +1,76 @@
+#![cfg(feature = "std")]
+
+use interpreter::{assemble, vm2rust, Machine, MachineError, MEMORY_SIZE, NREGS};
+
+// Regenerated with `cargo run -- --rust tests/examples/factorial.bin tests/vm2rust/factorial.rs`
+#[path = "vm2rust/factorial.rs"]
+mod factorial;
+// Regenerated with `cargo run -- --asm tests/vm2rust/ops.dis ops.o` then
+// `cargo run -- --rust ops.o tests/vm2rust/ops.rs`
+#[path = "vm2rust/ops.rs"]
+mod ops;
+
+type Native = fn(&mut [u32; NREGS], &mut [u8; MEMORY_SIZE], &mut &'static [u8], &mut Vec<u8>) -> Result<(), MachineError>;
+
+/// Result, output, registers and memory at the end of a run.
+type Outcome = (String, String, Vec<u32>, Vec<u8>);
+
+fn interpreted(image: &[u8], regs: &[u32; NREGS], mut input: &[u8]) -> Outcome {
+    let mut machine = Machine::new(image);
+    machine.enable_float();
+    for (reg, &value) in regs.iter().enumerate() {
+        machine.set_reg(reg, value).unwrap();
+    }
+    let mut out = vec![];
+    let result = machine.run_with(&mut input, &mut out);
+    let out = String::from_utf8(out).unwrap();
+    (format!("{result:?}"), out, machine.regs().to_vec(), machine.memory().to_vec())
+}
+
+fn native(run: Native, image: &[u8], regs: &[u32; NREGS], mut input: &'static [u8]) -> Outcome {
+    let mut reg = *regs;
+    let mut mem = [0; MEMORY_SIZE];
+    mem[..image.len()].copy_from_slice(image);
+    let mut out = vec![];
+    let result = run(&mut reg, &mut mem, &mut input, &mut out);
+    let out = String::from_utf8(out).unwrap();
+    (format!("{result:?}"), out, reg.to_vec(), mem.to_vec())
+}
+
+#[test]
+fn translations_are_up_to_date() {
+    assert_eq!(include_str!("vm2rust/factorial.rs"), vm2rust(include_bytes!("examples/factorial.bin"), 0));
+    let object = assemble(include_str!("vm2rust/ops.dis")).unwrap();
+    assert_eq!(include_str!("vm2rust/ops.rs"), vm2rust(&object.image().unwrap(), object.entry));
+}
+
+#[test]
+fn factorial() {
+    let mut regs = [0; NREGS];
+    regs[0] = factorial::ENTRY;
+    let outcome = native(factorial::run, factorial::IMAGE, &regs, b"");
+    assert_eq!(interpreted(factorial::IMAGE, &regs, b""), outcome);
+    assert!(outcome.1.ends_with("I'm done!\n"));
+}
+
+#[test]
+fn same_semantics_as_the_interpreter() {
+    for input in [&b""[..], b"Ibm", b"\x00\x01\xff"] {
+        // The stores fault at the end of the memory
+        for r10 in [200, 4092, 4095, 5000] {
+            let mut regs = [0; NREGS];
+            regs[0] = ops::ENTRY;
+            regs[10] = r10;
+            let outcome = native(ops::run, ops::IMAGE, &regs, input);
+            assert_eq!(interpreted(ops::IMAGE, &regs, input), outcome);
+        }
+    }
+    let mut regs = [0; NREGS];
+    regs[10] = 200;
+    let (result, out, _, _) = native(ops::run, ops::IMAGE, &regs, b"Ibm");
+    assert_eq!(("Ok(())", "Hal"), (&result[..], &out[..3]));
+    // The IP is past the faulting store
+    regs[10] = 4095;
+    let (result, _, regs, _) = native(ops::run, ops::IMAGE, &regs, b"");
+    assert_eq!(("Err(InvalidMemAddr)", 66), (&result[..], regs[0]));
+}
@@ -0,0 +1,727 @@
+//! Translation of a VM program, generated by `vm2rust`.
+
+use interpreter::{Machine, MachineError, MEMORY_SIZE, NREGS};
+use std::io::{Read, Write};
+
+/// Memory image of the program.
+pub const IMAGE: &[u8] = &[
+    4, 2, 0, 16, 4, 3, 4, 0, 5, 2, 2, 3, 2, 2, 10, 4,
+    3, 4, 0, 5, 2, 2, 3, 2, 2, 11, 4, 10, 184, 2, 4, 11,
+    39, 0, 4, 3, 4, 0, 5, 2, 2, 3, 4, 3, 53, 0, 2, 2,
+    3, 4, 0, 128, 2, 4, 3, 252, 255, 5, 2, 2, 3, 4, 3, 4,
+    0, 5, 3, 2, 3, 3, 11, 3, 4, 3, 252, 255, 5, 2, 2, 3,
+    4, 3, 4, 0, 5, 3, 2, 3, 3, 10, 3, 4, 3, 255, 255, 5,
+    7, 7, 3, 4, 3, 4, 0, 5, 2, 2, 3, 2, 2, 10, 4, 3,
+    4, 0, 5, 2, 2, 3, 2, 2, 11, 4, 10, 223, 2, 4, 11, 5,
+    0, 4, 3, 4, 0, 5, 2, 2, 3, 4, 3, 148, 0, 2, 2, 3,
+    4, 0, 128, 2, 4, 3, 252, 255, 5, 2, 2, 3, 4, 3, 4, 0,
+    5, 3, 2, 3, 3, 11, 3, 4, 3, 252, 255, 5, 2, 2, 3, 4,
+    3, 4, 0, 5, 3, 2, 3, 3, 10, 3, 8, 7, 4, 3, 4, 0,
+    5, 2, 2, 3, 2, 2, 10, 4, 3, 4, 0, 5, 2, 2, 3, 2,
+    2, 11, 4, 10, 228, 2, 4, 11, 4, 0, 4, 3, 4, 0, 5, 2,
+    2, 3, 4, 3, 237, 0, 2, 2, 3, 4, 0, 128, 2, 4, 3, 252,
+    255, 5, 2, 2, 3, 4, 3, 4, 0, 5, 3, 2, 3, 3, 11, 3,
+    4, 3, 252, 255, 5, 2, 2, 3, 4, 3, 4, 0, 5, 3, 2, 3,
+    3, 10, 3, 1, 10, 7, 0, 4, 3, 4, 0, 5, 2, 2, 3, 4,
+    3, 42, 1, 2, 2, 3, 4, 0, 50, 2, 8, 11, 4, 3, 4, 0,
+    5, 2, 2, 3, 2, 2, 10, 4, 3, 4, 0, 5, 2, 2, 3, 2,
+    2, 11, 4, 10, 232, 2, 4, 11, 1, 0, 4, 3, 4, 0, 5, 2,
+    2, 3, 4, 3, 93, 1, 2, 2, 3, 4, 0, 128, 2, 4, 3, 252,
+    255, 5, 2, 2, 3, 4, 3, 4, 0, 5, 3, 2, 3, 3, 11, 3,
+    4, 3, 252, 255, 5, 2, 2, 3, 4, 3, 4, 0, 5, 3, 2, 3,
+    3, 10, 3, 4, 4, 10, 0, 5, 4, 7, 4, 4, 5, 151, 1, 1,
+    0, 5, 4, 4, 0, 155, 1, 4, 0, 91, 0, 4, 3, 4, 0, 5,
+    2, 2, 3, 2, 2, 10, 4, 3, 4, 0, 5, 2, 2, 3, 2, 2,
+    11, 4, 10, 233, 2, 4, 11, 10, 0, 4, 3, 4, 0, 5, 2, 2,
+    3, 4, 3, 204, 1, 2, 2, 3, 4, 0, 128, 2, 4, 3, 252, 255,
+    5, 2, 2, 3, 4, 3, 4, 0, 5, 3, 2, 3, 3, 11, 3, 4,
+    3, 252, 255, 5, 2, 2, 3, 4, 3, 4, 0, 5, 3, 2, 3, 3,
+    10, 3, 7, 5, 13, 1, 11, 1, 14, 12, 0, 4, 8, 1, 0, 5,
+    8, 14, 8, 4, 9, 15, 2, 1, 0, 9, 8, 4, 0, 31, 2, 5,
+    11, 11, 13, 4, 3, 1, 0, 5, 14, 14, 3, 4, 0, 251, 1, 4,
+    3, 252, 255, 5, 2, 2, 3, 4, 3, 4, 0, 5, 3, 2, 3, 3,
+    0, 3, 4, 11, 1, 0, 4, 8, 1, 0, 5, 8, 10, 8, 4, 9,
+    74, 2, 1, 0, 9, 8, 4, 0, 109, 2, 1, 12, 10, 0, 4, 3,
+    4, 0, 5, 2, 2, 3, 4, 3, 97, 2, 2, 2, 3, 4, 0, 243,
+    1, 4, 3, 1, 0, 5, 10, 10, 3, 4, 0, 54, 2, 4, 3, 252,
+    255, 5, 2, 2, 3, 4, 3, 4, 0, 5, 3, 2, 3, 3, 0, 3,
+    4, 8, 140, 2, 1, 0, 8, 11, 4, 0, 165, 2, 3, 3, 10, 6,
+    3, 4, 3, 255, 255, 5, 10, 10, 3, 4, 3, 1, 0, 5, 11, 11,
+    3, 4, 0, 128, 2, 4, 3, 252, 255, 5, 2, 2, 3, 4, 3, 4,
+    0, 5, 3, 2, 3, 3, 0, 3, 73, 32, 119, 105, 108, 108, 32, 99,
+    111, 109, 112, 117, 116, 101, 32, 115, 111, 109, 101, 32, 102, 97, 99, 116,
+    111, 114, 105, 97, 108, 115, 32, 102, 111, 114, 32, 121, 111, 117, 10, 102,
+    97, 99, 116, 40, 41, 32, 61, 32, 10, 73, 39, 109, 32, 100, 111, 110,
+    101, 33, 10,
+];
+
+/// Address of the first instruction.
+pub const ENTRY: u32 = 0;
+
+/// Run the program from the IP held in `reg[0]` until it exits or faults.
+pub fn run<R: Read, W: Write>(
+    reg: &mut [u32; NREGS],
+    mem: &mut [u8; MEMORY_SIZE],
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), MachineError> {
+    loop {
+        match reg[0] {
+            0 => {
+                // 0000   loadimm r2 <- #4096
+                reg[0] = 4;
+                reg[2] = 4096;
+                // 0004   loadimm r3 <- #4
+                reg[0] = 8;
+                reg[3] = 4;
+                // 0008   sub r2 <- r2 - r3
+                reg[0] = 12;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0012   store [r2] <- r10
+                reg[0] = 15;
+                store(mem, reg[2], reg[10], 4)?;
+                // 0015   loadimm r3 <- #4
+                reg[0] = 19;
+                reg[3] = 4;
+                // 0019   sub r2 <- r2 - r3
+                reg[0] = 23;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0023   store [r2] <- r11
+                reg[0] = 26;
+                store(mem, reg[2], reg[11], 4)?;
+                // 0026   loadimm r10 <- #696
+                reg[0] = 30;
+                reg[10] = 696;
+                // 0030   loadimm r11 <- #39
+                reg[0] = 34;
+                reg[11] = 39;
+                // 0034   loadimm r3 <- #4
+                reg[0] = 38;
+                reg[3] = 4;
+                // 0038   sub r2 <- r2 - r3
+                reg[0] = 42;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0042   loadimm r3 <- #53
+                reg[0] = 46;
+                reg[3] = 53;
+                // 0046   store [r2] <- r3
+                reg[0] = 49;
+                store(mem, reg[2], reg[3], 4)?;
+                // 0049   loadimm r0 <- #640
+                reg[0] = 53;
+                reg[0] = 640;
+            }
+            53 => {
+                // 0053   loadimm r3 <- #-4
+                reg[0] = 57;
+                reg[3] = 4294967292;
+                // 0057   sub r2 <- r2 - r3
+                reg[0] = 61;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0061   loadimm r3 <- #4
+                reg[0] = 65;
+                reg[3] = 4;
+                // 0065   sub r3 <- r2 - r3
+                reg[0] = 69;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0069   load r11 <- [r3]
+                reg[0] = 72;
+                reg[11] = load(mem, reg[3], 4)?;
+                // 0072   loadimm r3 <- #-4
+                reg[0] = 76;
+                reg[3] = 4294967292;
+                // 0076   sub r2 <- r2 - r3
+                reg[0] = 80;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0080   loadimm r3 <- #4
+                reg[0] = 84;
+                reg[3] = 4;
+                // 0084   sub r3 <- r2 - r3
+                reg[0] = 88;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0088   load r10 <- [r3]
+                reg[0] = 91;
+                reg[10] = load(mem, reg[3], 4)?;
+            }
+            91 => {
+                // 0091   loadimm r3 <- #-1
+                reg[0] = 95;
+                reg[3] = 4294967295;
+                // 0095   sub r7 <- r7 - r3
+                reg[0] = 99;
+                reg[7] = reg[7].wrapping_sub(reg[3]);
+                // 0099   loadimm r3 <- #4
+                reg[0] = 103;
+                reg[3] = 4;
+                // 0103   sub r2 <- r2 - r3
+                reg[0] = 107;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0107   store [r2] <- r10
+                reg[0] = 110;
+                store(mem, reg[2], reg[10], 4)?;
+                // 0110   loadimm r3 <- #4
+                reg[0] = 114;
+                reg[3] = 4;
+                // 0114   sub r2 <- r2 - r3
+                reg[0] = 118;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0118   store [r2] <- r11
+                reg[0] = 121;
+                store(mem, reg[2], reg[11], 4)?;
+                // 0121   loadimm r10 <- #735
+                reg[0] = 125;
+                reg[10] = 735;
+                // 0125   loadimm r11 <- #5
+                reg[0] = 129;
+                reg[11] = 5;
+                // 0129   loadimm r3 <- #4
+                reg[0] = 133;
+                reg[3] = 4;
+                // 0133   sub r2 <- r2 - r3
+                reg[0] = 137;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0137   loadimm r3 <- #148
+                reg[0] = 141;
+                reg[3] = 148;
+                // 0141   store [r2] <- r3
+                reg[0] = 144;
+                store(mem, reg[2], reg[3], 4)?;
+                // 0144   loadimm r0 <- #640
+                reg[0] = 148;
+                reg[0] = 640;
+            }
+            148 => {
+                // 0148   loadimm r3 <- #-4
+                reg[0] = 152;
+                reg[3] = 4294967292;
+                // 0152   sub r2 <- r2 - r3
+                reg[0] = 156;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0156   loadimm r3 <- #4
+                reg[0] = 160;
+                reg[3] = 4;
+                // 0160   sub r3 <- r2 - r3
+                reg[0] = 164;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0164   load r11 <- [r3]
+                reg[0] = 167;
+                reg[11] = load(mem, reg[3], 4)?;
+                // 0167   loadimm r3 <- #-4
+                reg[0] = 171;
+                reg[3] = 4294967292;
+                // 0171   sub r2 <- r2 - r3
+                reg[0] = 175;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0175   loadimm r3 <- #4
+                reg[0] = 179;
+                reg[3] = 4;
+                // 0179   sub r3 <- r2 - r3
+                reg[0] = 183;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0183   load r10 <- [r3]
+                reg[0] = 186;
+                reg[10] = load(mem, reg[3], 4)?;
+                // 0186   out_number r7
+                reg[0] = 188;
+                out_number(output, reg[7])?;
+                // 0188   loadimm r3 <- #4
+                reg[0] = 192;
+                reg[3] = 4;
+                // 0192   sub r2 <- r2 - r3
+                reg[0] = 196;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0196   store [r2] <- r10
+                reg[0] = 199;
+                store(mem, reg[2], reg[10], 4)?;
+                // 0199   loadimm r3 <- #4
+                reg[0] = 203;
+                reg[3] = 4;
+                // 0203   sub r2 <- r2 - r3
+                reg[0] = 207;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0207   store [r2] <- r11
+                reg[0] = 210;
+                store(mem, reg[2], reg[11], 4)?;
+                // 0210   loadimm r10 <- #740
+                reg[0] = 214;
+                reg[10] = 740;
+                // 0214   loadimm r11 <- #4
+                reg[0] = 218;
+                reg[11] = 4;
+                // 0218   loadimm r3 <- #4
+                reg[0] = 222;
+                reg[3] = 4;
+                // 0222   sub r2 <- r2 - r3
+                reg[0] = 226;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0226   loadimm r3 <- #237
+                reg[0] = 230;
+                reg[3] = 237;
+                // 0230   store [r2] <- r3
+                reg[0] = 233;
+                store(mem, reg[2], reg[3], 4)?;
+                // 0233   loadimm r0 <- #640
+                reg[0] = 237;
+                reg[0] = 640;
+            }
+            237 => {
+                // 0237   loadimm r3 <- #-4
+                reg[0] = 241;
+                reg[3] = 4294967292;
+                // 0241   sub r2 <- r2 - r3
+                reg[0] = 245;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0245   loadimm r3 <- #4
+                reg[0] = 249;
+                reg[3] = 4;
+                // 0249   sub r3 <- r2 - r3
+                reg[0] = 253;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0253   load r11 <- [r3]
+                reg[0] = 256;
+                reg[11] = load(mem, reg[3], 4)?;
+                // 0256   loadimm r3 <- #-4
+                reg[0] = 260;
+                reg[3] = 4294967292;
+                // 0260   sub r2 <- r2 - r3
+                reg[0] = 264;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0264   loadimm r3 <- #4
+                reg[0] = 268;
+                reg[3] = 4;
+                // 0268   sub r3 <- r2 - r3
+                reg[0] = 272;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0272   load r10 <- [r3]
+                reg[0] = 275;
+                reg[10] = load(mem, reg[3], 4)?;
+                // 0275   move r10 <- r7 if r0 != 0
+                reg[0] = 279;
+                if reg[0] != 0 { reg[10] = reg[7]; }
+                // 0279   loadimm r3 <- #4
+                reg[0] = 283;
+                reg[3] = 4;
+                // 0283   sub r2 <- r2 - r3
+                reg[0] = 287;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0287   loadimm r3 <- #298
+                reg[0] = 291;
+                reg[3] = 298;
+                // 0291   store [r2] <- r3
+                reg[0] = 294;
+                store(mem, reg[2], reg[3], 4)?;
+                // 0294   loadimm r0 <- #562
+                reg[0] = 298;
+                reg[0] = 562;
+            }
+            298 => {
+                // 0298   out_number r11
+                reg[0] = 300;
+                out_number(output, reg[11])?;
+                // 0300   loadimm r3 <- #4
+                reg[0] = 304;
+                reg[3] = 4;
+                // 0304   sub r2 <- r2 - r3
+                reg[0] = 308;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0308   store [r2] <- r10
+                reg[0] = 311;
+                store(mem, reg[2], reg[10], 4)?;
+                // 0311   loadimm r3 <- #4
+                reg[0] = 315;
+                reg[3] = 4;
+                // 0315   sub r2 <- r2 - r3
+                reg[0] = 319;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0319   store [r2] <- r11
+                reg[0] = 322;
+                store(mem, reg[2], reg[11], 4)?;
+                // 0322   loadimm r10 <- #744
+                reg[0] = 326;
+                reg[10] = 744;
+                // 0326   loadimm r11 <- #1
+                reg[0] = 330;
+                reg[11] = 1;
+                // 0330   loadimm r3 <- #4
+                reg[0] = 334;
+                reg[3] = 4;
+                // 0334   sub r2 <- r2 - r3
+                reg[0] = 338;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0338   loadimm r3 <- #349
+                reg[0] = 342;
+                reg[3] = 349;
+                // 0342   store [r2] <- r3
+                reg[0] = 345;
+                store(mem, reg[2], reg[3], 4)?;
+                // 0345   loadimm r0 <- #640
+                reg[0] = 349;
+                reg[0] = 640;
+            }
+            349 => {
+                // 0349   loadimm r3 <- #-4
+                reg[0] = 353;
+                reg[3] = 4294967292;
+                // 0353   sub r2 <- r2 - r3
+                reg[0] = 357;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0357   loadimm r3 <- #4
+                reg[0] = 361;
+                reg[3] = 4;
+                // 0361   sub r3 <- r2 - r3
+                reg[0] = 365;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0365   load r11 <- [r3]
+                reg[0] = 368;
+                reg[11] = load(mem, reg[3], 4)?;
+                // 0368   loadimm r3 <- #-4
+                reg[0] = 372;
+                reg[3] = 4294967292;
+                // 0372   sub r2 <- r2 - r3
+                reg[0] = 376;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0376   loadimm r3 <- #4
+                reg[0] = 380;
+                reg[3] = 4;
+                // 0380   sub r3 <- r2 - r3
+                reg[0] = 384;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0384   load r10 <- [r3]
+                reg[0] = 387;
+                reg[10] = load(mem, reg[3], 4)?;
+                // 0387   loadimm r4 <- #10
+                reg[0] = 391;
+                reg[4] = 10;
+                // 0391   sub r4 <- r7 - r4
+                reg[0] = 395;
+                reg[4] = reg[7].wrapping_sub(reg[4]);
+                // 0395   loadimm r5 <- #407
+                reg[0] = 399;
+                reg[5] = 407;
+                // 0399   move r0 <- r5 if r4 != 0
+                reg[0] = 403;
+                if reg[4] != 0 { reg[0] = reg[5]; }
+            }
+            403 => {
+                // 0403   loadimm r0 <- #411
+                reg[0] = 407;
+                reg[0] = 411;
+            }
+            407 => {
+                // 0407   loadimm r0 <- #91
+                reg[0] = 411;
+                reg[0] = 91;
+            }
+            411 => {
+                // 0411   loadimm r3 <- #4
+                reg[0] = 415;
+                reg[3] = 4;
+                // 0415   sub r2 <- r2 - r3
+                reg[0] = 419;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0419   store [r2] <- r10
+                reg[0] = 422;
+                store(mem, reg[2], reg[10], 4)?;
+                // 0422   loadimm r3 <- #4
+                reg[0] = 426;
+                reg[3] = 4;
+                // 0426   sub r2 <- r2 - r3
+                reg[0] = 430;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0430   store [r2] <- r11
+                reg[0] = 433;
+                store(mem, reg[2], reg[11], 4)?;
+                // 0433   loadimm r10 <- #745
+                reg[0] = 437;
+                reg[10] = 745;
+                // 0437   loadimm r11 <- #10
+                reg[0] = 441;
+                reg[11] = 10;
+                // 0441   loadimm r3 <- #4
+                reg[0] = 445;
+                reg[3] = 4;
+                // 0445   sub r2 <- r2 - r3
+                reg[0] = 449;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0449   loadimm r3 <- #460
+                reg[0] = 453;
+                reg[3] = 460;
+                // 0453   store [r2] <- r3
+                reg[0] = 456;
+                store(mem, reg[2], reg[3], 4)?;
+                // 0456   loadimm r0 <- #640
+                reg[0] = 460;
+                reg[0] = 640;
+            }
+            460 => {
+                // 0460   loadimm r3 <- #-4
+                reg[0] = 464;
+                reg[3] = 4294967292;
+                // 0464   sub r2 <- r2 - r3
+                reg[0] = 468;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0468   loadimm r3 <- #4
+                reg[0] = 472;
+                reg[3] = 4;
+                // 0472   sub r3 <- r2 - r3
+                reg[0] = 476;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0476   load r11 <- [r3]
+                reg[0] = 479;
+                reg[11] = load(mem, reg[3], 4)?;
+                // 0479   loadimm r3 <- #-4
+                reg[0] = 483;
+                reg[3] = 4294967292;
+                // 0483   sub r2 <- r2 - r3
+                reg[0] = 487;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0487   loadimm r3 <- #4
+                reg[0] = 491;
+                reg[3] = 4;
+                // 0491   sub r3 <- r2 - r3
+                reg[0] = 495;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0495   load r10 <- [r3]
+                reg[0] = 498;
+                reg[10] = load(mem, reg[3], 4)?;
+                // 0498   exit
+                reg[0] = 499;
+                return Ok(());
+            }
+            499 => {
+                // 0499   sub r13 <- r1 - r11
+                reg[0] = 503;
+                reg[13] = reg[1].wrapping_sub(reg[11]);
+                // 0503   move r14 <- r12 if r0 != 0
+                reg[0] = 507;
+                if reg[0] != 0 { reg[14] = reg[12]; }
+            }
+            507 => {
+                // 0507   loadimm r8 <- #1
+                reg[0] = 511;
+                reg[8] = 1;
+                // 0511   sub r8 <- r14 - r8
+                reg[0] = 515;
+                reg[8] = reg[14].wrapping_sub(reg[8]);
+                // 0515   loadimm r9 <- #527
+                reg[0] = 519;
+                reg[9] = 527;
+                // 0519   move r0 <- r9 if r8 != 0
+                reg[0] = 523;
+                if reg[8] != 0 { reg[0] = reg[9]; }
+            }
+            523 => {
+                // 0523   loadimm r0 <- #543
+                reg[0] = 527;
+                reg[0] = 543;
+            }
+            527 => {
+                // 0527   sub r11 <- r11 - r13
+                reg[0] = 531;
+                reg[11] = reg[11].wrapping_sub(reg[13]);
+                // 0531   loadimm r3 <- #1
+                reg[0] = 535;
+                reg[3] = 1;
+                // 0535   sub r14 <- r14 - r3
+                reg[0] = 539;
+                reg[14] = reg[14].wrapping_sub(reg[3]);
+                // 0539   loadimm r0 <- #507
+                reg[0] = 543;
+                reg[0] = 507;
+            }
+            543 => {
+                // 0543   loadimm r3 <- #-4
+                reg[0] = 547;
+                reg[3] = 4294967292;
+                // 0547   sub r2 <- r2 - r3
+                reg[0] = 551;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0551   loadimm r3 <- #4
+                reg[0] = 555;
+                reg[3] = 4;
+                // 0555   sub r3 <- r2 - r3
+                reg[0] = 559;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0559   load r0 <- [r3]
+                reg[0] = 562;
+                reg[0] = load(mem, reg[3], 4)?;
+            }
+            562 => {
+                // 0562   loadimm r11 <- #1
+                reg[0] = 566;
+                reg[11] = 1;
+            }
+            566 => {
+                // 0566   loadimm r8 <- #1
+                reg[0] = 570;
+                reg[8] = 1;
+                // 0570   sub r8 <- r10 - r8
+                reg[0] = 574;
+                reg[8] = reg[10].wrapping_sub(reg[8]);
+                // 0574   loadimm r9 <- #586
+                reg[0] = 578;
+                reg[9] = 586;
+                // 0578   move r0 <- r9 if r8 != 0
+                reg[0] = 582;
+                if reg[8] != 0 { reg[0] = reg[9]; }
+            }
+            582 => {
+                // 0582   loadimm r0 <- #621
+                reg[0] = 586;
+                reg[0] = 621;
+            }
+            586 => {
+                // 0586   move r12 <- r10 if r0 != 0
+                reg[0] = 590;
+                if reg[0] != 0 { reg[12] = reg[10]; }
+                // 0590   loadimm r3 <- #4
+                reg[0] = 594;
+                reg[3] = 4;
+                // 0594   sub r2 <- r2 - r3
+                reg[0] = 598;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0598   loadimm r3 <- #609
+                reg[0] = 602;
+                reg[3] = 609;
+                // 0602   store [r2] <- r3
+                reg[0] = 605;
+                store(mem, reg[2], reg[3], 4)?;
+                // 0605   loadimm r0 <- #499
+                reg[0] = 609;
+                reg[0] = 499;
+            }
+            609 => {
+                // 0609   loadimm r3 <- #1
+                reg[0] = 613;
+                reg[3] = 1;
+                // 0613   sub r10 <- r10 - r3
+                reg[0] = 617;
+                reg[10] = reg[10].wrapping_sub(reg[3]);
+                // 0617   loadimm r0 <- #566
+                reg[0] = 621;
+                reg[0] = 566;
+            }
+            621 => {
+                // 0621   loadimm r3 <- #-4
+                reg[0] = 625;
+                reg[3] = 4294967292;
+                // 0625   sub r2 <- r2 - r3
+                reg[0] = 629;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0629   loadimm r3 <- #4
+                reg[0] = 633;
+                reg[3] = 4;
+                // 0633   sub r3 <- r2 - r3
+                reg[0] = 637;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0637   load r0 <- [r3]
+                reg[0] = 640;
+                reg[0] = load(mem, reg[3], 4)?;
+            }
+            640 => {
+                // 0640   loadimm r8 <- #652
+                reg[0] = 644;
+                reg[8] = 652;
+                // 0644   move r0 <- r8 if r11 != 0
+                reg[0] = 648;
+                if reg[11] != 0 { reg[0] = reg[8]; }
+            }
+            648 => {
+                // 0648   loadimm r0 <- #677
+                reg[0] = 652;
+                reg[0] = 677;
+            }
+            652 => {
+                // 0652   load r3 <- [r10]
+                reg[0] = 655;
+                reg[3] = load(mem, reg[10], 4)?;
+                // 0655   out r3
+                reg[0] = 657;
+                out(output, reg[3])?;
+                // 0657   loadimm r3 <- #-1
+                reg[0] = 661;
+                reg[3] = 4294967295;
+                // 0661   sub r10 <- r10 - r3
+                reg[0] = 665;
+                reg[10] = reg[10].wrapping_sub(reg[3]);
+                // 0665   loadimm r3 <- #1
+                reg[0] = 669;
+                reg[3] = 1;
+                // 0669   sub r11 <- r11 - r3
+                reg[0] = 673;
+                reg[11] = reg[11].wrapping_sub(reg[3]);
+                // 0673   loadimm r0 <- #640
+                reg[0] = 677;
+                reg[0] = 640;
+            }
+            677 => {
+                // 0677   loadimm r3 <- #-4
+                reg[0] = 681;
+                reg[3] = 4294967292;
+                // 0681   sub r2 <- r2 - r3
+                reg[0] = 685;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0685   loadimm r3 <- #4
+                reg[0] = 689;
+                reg[3] = 4;
+                // 0689   sub r3 <- r2 - r3
+                reg[0] = 693;
+                reg[3] = reg[2].wrapping_sub(reg[3]);
+                // 0693   load r0 <- [r3]
+                reg[0] = 696;
+                reg[0] = load(mem, reg[3], 4)?;
+            }
+            _ => {
+                if interpret(reg, mem, input, output)? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Run a single instruction with the interpreter.
+fn interpret<R: Read, W: Write>(
+    reg: &mut [u32; NREGS],
+    mem: &mut [u8; MEMORY_SIZE],
+    input: &mut R,
+    output: &mut W,
+) -> Result<bool, MachineError> {
+    let mut machine = Machine::new(&mem[..]);
+    machine.enable_float();
+    for (r, &value) in reg.iter().enumerate() {
+        machine.set_reg(r, value)?;
+    }
+    let result = machine.step_with(input, output);
+    reg.copy_from_slice(machine.regs());
+    mem.copy_from_slice(machine.memory());
+    result
+}
+
+/// Read `width` bytes in little-endian order, as `load`, `load8` and `load16`.
+fn load(mem: &[u8; MEMORY_SIZE], addr: u32, width: usize) -> Result<u32, MachineError> {
+    let addr = addr as usize;
+    if addr + width > MEMORY_SIZE {
+        return Err(MachineError::InvalidMemAddr);
+    }
+    Ok(mem[addr..addr + width].iter().rev().fold(0, |value, &byte| (value << 8) | byte as u32))
+}
+
+/// Write the `width` lowest bytes of `value` in little-endian order.
+fn store(mem: &mut [u8; MEMORY_SIZE], addr: u32, value: u32, width: usize) -> Result<(), MachineError> {
+    let addr = addr as usize;
+    if addr + width > MEMORY_SIZE {
+        return Err(MachineError::InvalidMemAddr);
+    }
+    mem[addr..addr + width].copy_from_slice(&value.to_le_bytes()[..width]);
+    Ok(())
+}
+
+fn out<W: Write>(output: &mut W, value: u32) -> Result<(), MachineError> {
+    let text = (value as u8 as char).to_string();
+    output.write_all(text.as_bytes()).map_err(|_| MachineError::WriteError)
+}
+
+fn out_number<W: Write>(output: &mut W, value: u32) -> Result<(), MachineError> {
+    write!(output, "{}", value as i32).map_err(|_| MachineError::WriteError)
+}
@@ -0,0 +1,255 @@
+//! Translation of a VM program, generated by `vm2rust`.
+
+use interpreter::{Machine, MachineError, MEMORY_SIZE, NREGS};
+use std::io::{Read, Write};
+
+/// Memory image of the program.
+pub const IMAGE: &[u8] = &[
+    4, 2, 0, 16, 28, 1, 20, 1, 33, 0, 4, 4, 1, 0, 5, 1,
+    1, 4, 6, 1, 21, 5, 1, 4, 22, 5, 1, 4, 23, 5, 1, 4,
+    24, 5, 1, 4, 25, 5, 1, 1, 17, 217, 255, 9, 6, 160, 134, 1,
+    0, 10, 6, 3, 0, 8, 6, 27, 7, 6, 1, 26, 8, 6, 1, 2,
+    10, 6, 12, 9, 10, 13, 11, 10, 15, 10, 1, 16, 10, 1, 3, 12,
+    10, 33, 13, 6, 9, 14, 205, 204, 204, 61, 31, 13, 13, 14, 32, 13,
+    13, 9, 30, 13, 13, 14, 29, 13, 13, 13, 38, 13, 34, 15, 13, 35,
+    5, 13, 14, 36, 5, 14, 13, 37, 5, 13, 13, 39, 14, 6, 1, 39,
+    14, 6, 9, 4, 3, 8, 0, 5, 2, 2, 3, 4, 3, 252, 255, 5,
+    4, 2, 3, 4, 3, 155, 0, 2, 4, 3, 40, 4, 3, 4, 0, 5,
+    2, 2, 3, 4, 3, 173, 0, 2, 2, 3, 3, 0, 2, 7,
+];
+
+/// Address of the first instruction.
+pub const ENTRY: u32 = 0;
+
+/// Run the program from the IP held in `reg[0]` until it exits or faults.
+pub fn run<R: Read, W: Write>(
+    reg: &mut [u32; NREGS],
+    mem: &mut [u8; MEMORY_SIZE],
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), MachineError> {
+    loop {
+        match reg[0] {
+            0 => {
+                // 0000   loadimm r2 <- #4096
+                reg[0] = 4;
+                reg[2] = 4096;
+            }
+            4 => {
+                // 0004   in r1
+                reg[0] = 6;
+                reg[1] = read(input)?;
+                // 0006   bneg r1, #33
+                reg[0] = 10;
+                if (reg[1] as i32) < 0 { reg[0] = 43; }
+            }
+            10 => {
+                // 0010   loadimm r4 <- #1
+                reg[0] = 14;
+                reg[4] = 1;
+                // 0014   sub r1 <- r1 - r4
+                reg[0] = 18;
+                reg[1] = reg[1].wrapping_sub(reg[4]);
+                // 0018   out r1
+                reg[0] = 20;
+                out(output, reg[1])?;
+                // 0020   slt r5 <- r1, r4
+                reg[0] = 24;
+                reg[5] = ((reg[1] as i32) < (reg[4] as i32)) as u32;
+                // 0024   sle r5 <- r1, r4
+                reg[0] = 28;
+                reg[5] = ((reg[1] as i32) <= (reg[4] as i32)) as u32;
+                // 0028   ult r5 <- r1, r4
+                reg[0] = 32;
+                reg[5] = (reg[1] < reg[4]) as u32;
+                // 0032   ule r5 <- r1, r4
+                reg[0] = 36;
+                reg[5] = (reg[1] <= reg[4]) as u32;
+                // 0036   eq r5 <- r1, r1
+                reg[0] = 40;
+                reg[5] = 1;
+                // 0040   jmp #-39
+                reg[0] = 4;
+            }
+            43 => {
+                // 0043   loadimm32 r6 <- #100000
+                reg[0] = 49;
+                reg[6] = 100000;
+                // 0049   loadhi r6 <- #3
+                reg[0] = 53;
+                reg[6] = 0x30000 | (reg[6] & 0xffff);
+                // 0053   out_number r6
+                reg[0] = 55;
+                out_number(output, reg[6])?;
+                // 0055   move r7 <- r6 if r1 < 0
+                reg[0] = 59;
+                if (reg[1] as i32) < 0 { reg[7] = reg[6]; }
+                // 0059   move r8 <- r6 if r1 == 0
+                reg[0] = 63;
+                if reg[1] == 0 { reg[8] = reg[6]; }
+                // 0063   store [r10] <- r6
+                reg[0] = 66;
+                store(mem, reg[10], reg[6], 4)?;
+                // 0066   load8s r9 <- [r10]
+                reg[0] = 69;
+                reg[9] = load(mem, reg[10], 1)? as u8 as i8 as u32;
+                // 0069   load16 r11 <- [r10]
+                reg[0] = 72;
+                reg[11] = load(mem, reg[10], 2)?;
+                // 0072   store8 [r10] <- r1
+                reg[0] = 75;
+                store(mem, reg[10], reg[1], 1)?;
+                // 0075   store16 [r10] <- r1
+                reg[0] = 78;
+                store(mem, reg[10], reg[1], 2)?;
+                // 0078   load r12 <- [r10]
+                reg[0] = 81;
+                reg[12] = load(mem, reg[10], 4)?;
+                // 0081   itof r13 <- r6
+                reg[0] = 84;
+                reg[13] = (reg[6] as i32 as f32).to_bits();
+                // 0084   loadimm32 r14 <- #1036831949
+                reg[0] = 90;
+                reg[14] = 1036831949;
+                // 0090   fmul r13 <- r13 * r14
+                reg[0] = 94;
+                reg[13] = (f32::from_bits(reg[13]) * f32::from_bits(reg[14])).to_bits();
+                // 0094   fdiv r13 <- r13 / r9
+                reg[0] = 98;
+                reg[13] = (f32::from_bits(reg[13]) / f32::from_bits(reg[9])).to_bits();
+                // 0098   fsub r13 <- r13 - r14
+                reg[0] = 102;
+                reg[13] = (f32::from_bits(reg[13]) - f32::from_bits(reg[14])).to_bits();
+                // 0102   fadd r13 <- r13 + r13
+                reg[0] = 106;
+                reg[13] = (f32::from_bits(reg[13]) + f32::from_bits(reg[13])).to_bits();
+                // 0106   out_float r13
+                reg[0] = 108;
+                out_float(output, reg[13])?;
+                // 0108   ftoi r15 <- r13
+                reg[0] = 111;
+                reg[15] = f32::from_bits(reg[13]) as i32 as u32;
+                // 0111   flt r5 <- r13, r14
+                reg[0] = 115;
+                reg[5] = (f32::from_bits(reg[13]) < f32::from_bits(reg[14])) as u32;
+                // 0115   fle r5 <- r14, r13
+                reg[0] = 119;
+                reg[5] = (f32::from_bits(reg[14]) <= f32::from_bits(reg[13])) as u32;
+                // 0119   feq r5 <- r13, r13
+                reg[0] = 123;
+                reg[5] = (f32::from_bits(reg[13]) == f32::from_bits(reg[13])) as u32;
+                // 0123   div r14 <- r6 / r1
+                reg[0] = 127;
+                reg[14] = div(reg[6], reg[1])?;
+                // 0127   div r14 <- r6 / r9
+                reg[0] = 131;
+                reg[14] = div(reg[6], reg[9])?;
+                // 0131   loadimm r3 <- #8
+                reg[0] = 135;
+                reg[3] = 8;
+                // 0135   sub r2 <- r2 - r3
+                reg[0] = 139;
+                reg[2] = reg[2].wrapping_sub(reg[3]);
+                // 0139   loadimm r3 <- #-4
+                reg[0] = 143;
+                reg[3] = 4294967292;
+                // 0143   sub r4 <- r2 - r3
+                reg[0] = 147;
+                reg[4] = reg[2].wrapping_sub(reg[3]);
+                // 0147   loadimm r3 <- #155
+                reg[0] = 151;
+                reg[3] = 155;
+                // 0151   store [r4] <- r3
+                reg[0] = 154;
+                store(mem, reg[4], reg[3], 4)?;
+                // 0154   rti
+                reg[0] = 155;
+                rti(reg, mem)?;
+            }
+            _ => {
+                if interpret(reg, mem, input, output)? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Run a single instruction with the interpreter.
+fn interpret<R: Read, W: Write>(
+    reg: &mut [u32; NREGS],
+    mem: &mut [u8; MEMORY_SIZE],
+    input: &mut R,
+    output: &mut W,
+) -> Result<bool, MachineError> {
+    let mut machine = Machine::new(&mem[..]);
+    machine.enable_float();
+    for (r, &value) in reg.iter().enumerate() {
+        machine.set_reg(r, value)?;
+    }
+    let result = machine.step_with(input, output);
+    reg.copy_from_slice(machine.regs());
+    mem.copy_from_slice(machine.memory());
+    result
+}
+
+/// Read `width` bytes in little-endian order, as `load`, `load8` and `load16`.
+fn load(mem: &[u8; MEMORY_SIZE], addr: u32, width: usize) -> Result<u32, MachineError> {
+    let addr = addr as usize;
+    if addr + width > MEMORY_SIZE {
+        return Err(MachineError::InvalidMemAddr);
+    }
+    Ok(mem[addr..addr + width].iter().rev().fold(0, |value, &byte| (value << 8) | byte as u32))
+}
+
+/// Write the `width` lowest bytes of `value` in little-endian order.
+fn store(mem: &mut [u8; MEMORY_SIZE], addr: u32, value: u32, width: usize) -> Result<(), MachineError> {
+    let addr = addr as usize;
+    if addr + width > MEMORY_SIZE {
+        return Err(MachineError::InvalidMemAddr);
+    }
+    mem[addr..addr + width].copy_from_slice(&value.to_le_bytes()[..width]);
+    Ok(())
+}
+
+fn out<W: Write>(output: &mut W, value: u32) -> Result<(), MachineError> {
+    let text = (value as u8 as char).to_string();
+    output.write_all(text.as_bytes()).map_err(|_| MachineError::WriteError)
+}
+
+fn out_number<W: Write>(output: &mut W, value: u32) -> Result<(), MachineError> {
+    write!(output, "{}", value as i32).map_err(|_| MachineError::WriteError)
+}
+
+fn out_float<W: Write>(output: &mut W, value: u32) -> Result<(), MachineError> {
+    write!(output, "{}", f32::from_bits(value)).map_err(|_| MachineError::WriteError)
+}
+
+/// Signed division truncating toward zero, as `div`.
+fn div(lhs: u32, rhs: u32) -> Result<u32, MachineError> {
+    match rhs {
+        0 => Err(MachineError::DivisionByZero),
+        _ => Ok((lhs as i32).wrapping_div(rhs as i32) as u32),
+    }
+}
+
+/// Pop the cause and the IP pushed by a trap, as `rti`.
+fn rti(reg: &mut [u32; NREGS], mem: &[u8; MEMORY_SIZE]) -> Result<(), MachineError> {
+    let sp = reg[2] as usize;
+    if sp + 8 > MEMORY_SIZE {
+        return Err(MachineError::InvalidMemAddr);
+    }
+    reg[0] = u32::from_le_bytes(mem[sp + 4..sp + 8].try_into().unwrap());
+    reg[2] += 8;
+    Ok(())
+}
+
+/// Read a byte, or -1 at the end of the input.
+fn read<R: Read>(input: &mut R) -> Result<u32, MachineError> {
+    let mut byte = [0u8];
+    match input.read(&mut byte) {
+        Ok(0) => Ok(u32::MAX),
+        Ok(_) => Ok(byte[0] as u32),
+        Err(_) => Err(MachineError::ReadError),
+    }
+}